@@ -0,0 +1,31 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use wgpu_sandbox::camera::{Camera, CameraUniform, Projection};
+use wgpu_sandbox::camera_path::{CameraPath, CameraPlayer};
+use wgpu_sandbox::culling::Frustum;
+
+const FRAME_TIME: std::time::Duration = std::time::Duration::from_micros(16_667);
+
+fn flythrough_benchmark(c: &mut Criterion) {
+    let path = CameraPath::load(concat!(env!("CARGO_MANIFEST_DIR"), "/res/flythrough.json")).unwrap();
+    let frames = (path.duration() / FRAME_TIME.as_secs_f32()).ceil() as usize;
+
+    c.bench_function("flythrough", |b| {
+        b.iter(|| {
+            // Same fixed timestep every run so results are reproducible
+            let mut camera = Camera::new((0.0, 5.0, 10.0), cgmath::Deg(-90.0), cgmath::Deg(-20.0));
+            let projection = Projection::new(1280, 720, cgmath::Deg(45.0), 0.1, 100.0);
+            let mut uniform = CameraUniform::new();
+            let mut player = CameraPlayer::new(path.clone());
+            let mut planes = 0;
+            for _ in 0..frames {
+                player.update(&mut camera, FRAME_TIME);
+                uniform.update_view_proj(&camera, &projection);
+                planes += Frustum::from_view_proj(&uniform.view_proj).planes.len();
+            }
+            planes
+        })
+    });
+}
+
+criterion_group!(benches, flythrough_benchmark);
+criterion_main!(benches);
@@ -1,18 +1,41 @@
 use std::f32::consts::FRAC_PI_2;
 
-use cgmath::{perspective, prelude::*, Rad};
+use cgmath::{ortho, perspective, prelude::*, Rad};
 use cgmath::{Matrix4, Point3, Vector3, Deg};
 
+// Maps OpenGL depth -1..1 to wgpu's 0..1. Column-major, like every Matrix4::new.
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: Matrix4<f32> = Matrix4::new(
     1.0, 0.0, 0.0, 0.0,
     0.0, 1.0, 0.0, 0.0,
-    0.0, 0.0, 0.5, 0.5,
-    0.0, 0.0, 0.0, 1.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
+);
+
+// Maps wgpu depth d to 1 - d.
+#[rustfmt::skip]
+pub const REVERSE_Z_MATRIX: Matrix4<f32> = Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, -1.0, 0.0,
+    0.0, 0.0, 1.0, 1.0,
 );
 
 pub const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
 
+// Right-handed perspective with depth 1 at znear and 0 at infinity.
+pub fn infinite_reverse_z_perspective(fovy: Rad<f32>, aspect: f32, znear: f32) -> Matrix4<f32> {
+    let f = 1.0 / (fovy.0 / 2.0).tan();
+    #[rustfmt::skip]
+    let matrix = Matrix4::new(
+        f / aspect, 0.0, 0.0, 0.0,
+        0.0, f, 0.0, 0.0,
+        0.0, 0.0, 0.0, -1.0,
+        0.0, 0.0, znear, 0.0,
+    );
+    matrix
+}
+
 #[derive(Debug)]
 pub struct Camera {
     pub position: Point3<f32>,
@@ -38,26 +61,50 @@ impl Camera {
     }
 
     pub fn calc_matrix(&self) -> Matrix4<f32> {
-        let (sin_pitch, cos_pitch) = self.pitch.0.sin_cos();
-        let (sin_yaw, cos_yaw) = self.yaw.0.sin_cos();
-
         Matrix4::look_to_rh(
             self.position,
-            Vector3::new(
-                cos_pitch * cos_yaw,
-                sin_pitch,
-                cos_pitch * sin_yaw
-            ).normalize(),
+            self.forward(),
             Vector3::unit_y(),
         )
     }
+
+    // Unit vector the camera is looking along.
+    pub fn forward(&self) -> Vector3<f32> {
+        let (sin_pitch, cos_pitch) = self.pitch.0.sin_cos();
+        let (sin_yaw, cos_yaw) = self.yaw.0.sin_cos();
+        Vector3::new(
+            cos_pitch * cos_yaw,
+            sin_pitch,
+            cos_pitch * sin_yaw
+        ).normalize()
+    }
+
+    pub fn right(&self) -> Vector3<f32> {
+        self.forward().cross(Vector3::unit_y()).normalize()
+    }
+
+    pub fn up(&self) -> Vector3<f32> {
+        self.right().cross(self.forward()).normalize()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProjectionKind {
+    Perspective { fovy: Rad<f32> },
+    // `height` is how many world units fit vertically on screen.
+    Orthographic { height: f32 },
 }
 
 pub struct Projection {
     aspect: f32,
-    fovy: Rad<f32>,
+    kind: ProjectionKind,
     znear: f32,
     zfar: f32,
+    min_fovy: Rad<f32>,
+    max_fovy: Rad<f32>,
+    // Reverse-Z with an infinite far plane for perspective; orthographic keeps
+    // zfar but still maps near to 1 and far to 0.
+    reverse_z: bool,
 }
 impl Projection {
     pub fn new<F: Into<Rad<f32>>>(
@@ -69,16 +116,91 @@ impl Projection {
     ) -> Self {
         Self {
             aspect: width as f32 / height as f32,
-            fovy: fovy.into(),
+            kind: ProjectionKind::Perspective { fovy: fovy.into() },
             znear,
             zfar,
+            min_fovy: Deg(10.0).into(),
+            max_fovy: Deg(100.0).into(),
+            reverse_z: false,
         }
     }
+    pub fn orthographic(
+        width: u32,
+        height: u32,
+        view_height: f32,
+        znear: f32,
+        zfar: f32,
+    ) -> Self {
+        let mut projection = Self::new(width, height, Deg(45.0), znear, zfar);
+        projection.kind = ProjectionKind::Orthographic { height: view_height };
+        projection
+    }
     pub fn resize(&mut self, width: u32, height: u32) {
         self.aspect = width as f32 / height as f32;
     }
+    pub fn reverse_z(&self) -> bool {
+        self.reverse_z
+    }
+    pub fn set_reverse_z(&mut self, reverse_z: bool) {
+        self.reverse_z = reverse_z;
+    }
+    pub fn kind(&self) -> ProjectionKind {
+        self.kind
+    }
+    pub fn set_kind(&mut self, kind: ProjectionKind) {
+        self.kind = match kind {
+            ProjectionKind::Perspective { fovy } => ProjectionKind::Perspective { fovy: self.clamp_fovy(fovy) },
+            ProjectionKind::Orthographic { height } => ProjectionKind::Orthographic { height: height.max(f32::EPSILON) },
+        };
+    }
+    pub fn set_fovy_range<F: Into<Rad<f32>>>(&mut self, min: F, max: F) {
+        self.min_fovy = min.into();
+        self.max_fovy = max.into();
+        self.set_kind(self.kind);
+    }
+    fn clamp_fovy(&self, fovy: Rad<f32>) -> Rad<f32> {
+        Rad(fovy.0.clamp(self.min_fovy.0, self.max_fovy.0))
+    }
+    // Switches between perspective and orthographic while keeping roughly the same
+    // framing at `focus_distance` from the camera.
+    pub fn toggle_orthographic(&mut self, focus_distance: f32) {
+        match self.kind {
+            ProjectionKind::Perspective { fovy } => {
+                let height = 2.0 * focus_distance * (fovy.0 / 2.0).tan();
+                self.set_kind(ProjectionKind::Orthographic { height });
+            }
+            ProjectionKind::Orthographic { height } => {
+                let fovy = Rad(2.0 * (height / (2.0 * focus_distance)).atan());
+                self.set_kind(ProjectionKind::Perspective { fovy });
+            }
+        }
+    }
+    // Multiplies the field of view (or ortho height) by `factor`; < 1 zooms in.
+    pub fn zoom(&mut self, factor: f32) {
+        match self.kind {
+            ProjectionKind::Perspective { fovy } => self.set_kind(ProjectionKind::Perspective { fovy: fovy * factor }),
+            ProjectionKind::Orthographic { height } => self.set_kind(ProjectionKind::Orthographic { height: height * factor }),
+        }
+    }
     pub fn calc_matrix(&self) -> Matrix4<f32> {
-        OPENGL_TO_WGPU_MATRIX * perspective(self.fovy, self.aspect, self.znear, self.zfar)
+        match (self.kind, self.reverse_z) {
+            (ProjectionKind::Perspective { fovy }, false) => {
+                OPENGL_TO_WGPU_MATRIX * perspective(fovy, self.aspect, self.znear, self.zfar)
+            }
+            (ProjectionKind::Perspective { fovy }, true) => {
+                infinite_reverse_z_perspective(fovy, self.aspect, self.znear)
+            }
+            (ProjectionKind::Orthographic { height }, reverse_z) => {
+                let half_height = height / 2.0;
+                let half_width = half_height * self.aspect;
+                let matrix = OPENGL_TO_WGPU_MATRIX * ortho(-half_width, half_width, -half_height, half_height, self.znear, self.zfar);
+                if reverse_z {
+                    REVERSE_Z_MATRIX * matrix
+                } else {
+                    matrix
+                }
+            }
+        }
     }
 }
 
@@ -103,4 +225,77 @@ impl CameraUniform {
         self.view_proj = (projection.calc_matrix() * camera.calc_matrix()).into();
     }
 
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // View-space point to normalized device coordinates.
+    fn ndc(projection: &Projection, point: [f32; 3]) -> [f32; 3] {
+        let clip = projection.calc_matrix() * Point3::from(point).to_homogeneous();
+        [clip.x / clip.w, clip.y / clip.w, clip.z / clip.w]
+    }
+
+    fn assert_near(actual: [f32; 3], expected: [f32; 3]) {
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-4, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    fn fovy(projection: &Projection) -> Deg<f32> {
+        match projection.kind() {
+            ProjectionKind::Perspective { fovy } => fovy.into(),
+            kind => panic!("expected perspective, got {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn perspective_corners() {
+        // 90 degrees, so the frustum edges are at x = ±z and y = ±z.
+        let mut projection = Projection::new(100, 100, Deg(90.0), 1.0, 100.0);
+        assert_near(ndc(&projection, [1.0, 1.0, -1.0]), [1.0, 1.0, 0.0]);
+        assert_near(ndc(&projection, [-100.0, -100.0, -100.0]), [-1.0, -1.0, 1.0]);
+
+        projection.set_reverse_z(true);
+        assert_near(ndc(&projection, [1.0, 1.0, -1.0]), [1.0, 1.0, 1.0]);
+        // No far plane; depth only approaches 0.
+        assert_near(ndc(&projection, [-100.0, -100.0, -100.0]), [-1.0, -1.0, 0.01]);
+        assert_near(ndc(&projection, [0.0, 0.0, -1.0e6]), [0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn orthographic_corners() {
+        // Aspect 2, so 4 units across and 2 up.
+        let mut projection = Projection::orthographic(200, 100, 2.0, 1.0, 100.0);
+        assert_near(ndc(&projection, [2.0, 1.0, -1.0]), [1.0, 1.0, 0.0]);
+        assert_near(ndc(&projection, [-2.0, -1.0, -100.0]), [-1.0, -1.0, 1.0]);
+
+        projection.set_reverse_z(true);
+        assert_near(ndc(&projection, [2.0, 1.0, -1.0]), [1.0, 1.0, 1.0]);
+        assert_near(ndc(&projection, [-2.0, -1.0, -100.0]), [-1.0, -1.0, 0.0]);
+    }
+
+    #[test]
+    fn zoom_is_clamped_to_fovy_range() {
+        let mut projection = Projection::new(100, 100, Deg(45.0), 0.1, 100.0);
+        projection.set_fovy_range(Deg(30.0), Deg(60.0));
+        projection.zoom(0.1);
+        assert!((fovy(&projection).0 - 30.0).abs() < 1e-4);
+        projection.zoom(100.0);
+        assert!((fovy(&projection).0 - 60.0).abs() < 1e-4);
+
+        // Narrowing the range clamps the current field of view too.
+        projection.set_fovy_range(Deg(20.0), Deg(40.0));
+        assert!((fovy(&projection).0 - 40.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn orthographic_zoom_stays_positive() {
+        let mut projection = Projection::orthographic(100, 100, 10.0, 0.1, 100.0);
+        projection.zoom(0.0);
+        match projection.kind() {
+            ProjectionKind::Orthographic { height } => assert!(height > 0.0),
+            kind => panic!("expected orthographic, got {:?}", kind),
+        }
+    }
+}
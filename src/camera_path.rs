@@ -0,0 +1,346 @@
+use std::path::Path;
+
+use anyhow::Context;
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Quaternion, Rad, Rotation, Rotation3, Vector3};
+use serde::{Deserialize, Serialize};
+
+use crate::camera::Camera;
+
+// Set to a path file to play it as soon as the window opens.
+pub const CAMERA_PATH_ENV: &str = "WIG_CAMERA_PATH";
+// Where F5 recordings are written.
+pub const RECORDING_FILE: &str = "camera_path.json";
+// Seconds between keyframes when recording.
+const RECORD_INTERVAL: f32 = 0.25;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Keyframe {
+    // Seconds from the start of the path.
+    pub time: f32,
+    pub position: [f32; 3],
+    // Radians, same convention as camera::Camera.
+    pub yaw: f32,
+    pub pitch: f32,
+}
+
+impl Keyframe {
+    pub fn from_camera(time: f32, camera: &Camera) -> Self {
+        Self {
+            time,
+            position: camera.position.into(),
+            yaw: camera.yaw.0,
+            pitch: camera.pitch.0,
+        }
+    }
+
+    fn orientation(&self) -> Quaternion<f32> {
+        orientation_from_yaw_pitch(Rad(self.yaw), Rad(self.pitch))
+    }
+}
+
+// Rotation taking +X to the camera's forward vector.
+pub fn orientation_from_yaw_pitch(yaw: Rad<f32>, pitch: Rad<f32>) -> Quaternion<f32> {
+    Quaternion::from_angle_y(-yaw) * Quaternion::from_angle_z(pitch)
+}
+
+pub fn yaw_pitch_from_orientation(orientation: Quaternion<f32>) -> (Rad<f32>, Rad<f32>) {
+    let forward = orientation.rotate_vector(Vector3::unit_x()).normalize();
+    (Rad(forward.z.atan2(forward.x)), Rad(forward.y.clamp(-1.0, 1.0).asin()))
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CameraPath {
+    pub keyframes: Vec<Keyframe>,
+    #[serde(default)]
+    pub looped: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraPose {
+    pub position: Point3<f32>,
+    pub yaw: Rad<f32>,
+    pub pitch: Rad<f32>,
+}
+
+impl CameraPose {
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.position = self.position;
+        camera.yaw = self.yaw;
+        camera.pitch = self.pitch;
+    }
+}
+
+impl CameraPath {
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read camera path {:?}", path))?;
+        let mut camera_path: CameraPath = serde_json::from_str(&text)
+            .with_context(|| format!("Failed to parse camera path {:?}", path))?;
+        camera_path.keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Ok(camera_path)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let text = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, text).with_context(|| format!("Failed to write camera path {:?}", path))
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |k| k.time)
+    }
+
+    // Catmull-Rom through the keyframe positions, slerp between orientations.
+    // Times outside the path clamp to the first/last keyframe.
+    pub fn sample(&self, time: f32) -> Option<CameraPose> {
+        let keys = &self.keyframes;
+        let first = keys.first()?;
+        let last = keys.last()?;
+        if keys.len() == 1 || time <= first.time {
+            return Some(pose(first.position.into(), first.orientation()));
+        }
+        if time >= last.time {
+            return Some(pose(last.position.into(), last.orientation()));
+        }
+
+        let i = keys.partition_point(|k| k.time <= time).saturating_sub(1);
+        let k1 = &keys[i];
+        let k2 = &keys[i + 1];
+        let k0 = &keys[i.saturating_sub(1)];
+        let k3 = &keys[(i + 2).min(keys.len() - 1)];
+        let span = k2.time - k1.time;
+        let t = if span > 0.0 { (time - k1.time) / span } else { 0.0 };
+
+        let position = catmull_rom(
+            k0.position.into(),
+            k1.position.into(),
+            k2.position.into(),
+            k3.position.into(),
+            t,
+        );
+        let orientation = k1.orientation().slerp(k2.orientation(), t);
+        Some(pose(Point3::from_vec(position), orientation))
+    }
+}
+
+fn pose(position: Point3<f32>, orientation: Quaternion<f32>) -> CameraPose {
+    let (yaw, pitch) = yaw_pitch_from_orientation(orientation);
+    CameraPose { position, yaw, pitch }
+}
+
+// Uniform Catmull-Rom between p1 and p2.
+pub fn catmull_rom(p0: Vector3<f32>, p1: Vector3<f32>, p2: Vector3<f32>, p3: Vector3<f32>, t: f32) -> Vector3<f32> {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    (p1 * 2.0
+        + (p2 - p0) * t
+        + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+        + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3)
+        * 0.5
+}
+
+// Drives the camera along a path. While playing it replaces
+// CameraController::update_camera.
+pub struct CameraPlayer {
+    pub path: CameraPath,
+    time: f32,
+}
+
+impl CameraPlayer {
+    pub fn new(path: CameraPath) -> Self {
+        Self { path, time: 0.0 }
+    }
+
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn is_finished(&self) -> bool {
+        !self.path.looped && self.time >= self.path.duration()
+    }
+
+    // Advances playback and poses the camera. Returns false once a non-looping path has ended.
+    pub fn update(&mut self, camera: &mut Camera, dt: instant::Duration) -> bool {
+        self.time += dt.as_secs_f32();
+        let duration = self.path.duration();
+        if self.path.looped && duration > 0.0 {
+            self.time %= duration;
+        }
+        if let Some(pose) = self.path.sample(self.time) {
+            pose.apply(camera);
+        }
+        !self.is_finished()
+    }
+}
+
+// Samples the live camera at a fixed interval.
+pub struct CameraRecorder {
+    path: CameraPath,
+    time: f32,
+    since_last: f32,
+}
+
+impl CameraRecorder {
+    pub fn new(camera: &Camera) -> Self {
+        Self {
+            path: CameraPath {
+                keyframes: vec![Keyframe::from_camera(0.0, camera)],
+                looped: false,
+            },
+            time: 0.0,
+            since_last: 0.0,
+        }
+    }
+
+    pub fn record(&mut self, camera: &Camera, dt: instant::Duration) {
+        let dt = dt.as_secs_f32();
+        self.time += dt;
+        self.since_last += dt;
+        if self.since_last >= RECORD_INTERVAL {
+            self.since_last = 0.0;
+            self.path.keyframes.push(Keyframe::from_camera(self.time, camera));
+        }
+    }
+
+    pub fn finish(mut self, camera: &Camera) -> CameraPath {
+        if self.since_last > 0.0 {
+            self.path.keyframes.push(Keyframe::from_camera(self.time, camera));
+        }
+        self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-4, "{} != {}", a, b);
+    }
+
+    fn assert_pose(pose: CameraPose, keyframe: &Keyframe) {
+        for i in 0..3 {
+            assert_close(pose.position[i], keyframe.position[i]);
+        }
+        assert_close(pose.yaw.0, keyframe.yaw);
+        assert_close(pose.pitch.0, keyframe.pitch);
+    }
+
+    fn keyframe(time: f32, x: f32, yaw: f32) -> Keyframe {
+        Keyframe { time, position: [x, 1.0, -x], yaw, pitch: 0.2 }
+    }
+
+    fn path(looped: bool) -> CameraPath {
+        CameraPath {
+            keyframes: vec![keyframe(1.0, 0.0, 0.0), keyframe(2.0, 4.0, 0.5), keyframe(3.0, 6.0, 1.0)],
+            looped,
+        }
+    }
+
+    #[test]
+    fn catmull_rom_passes_through_the_inner_points() {
+        let p = [
+            Vector3::new(-1.0, 0.0, 2.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            Vector3::new(3.0, 2.0, -1.0),
+            Vector3::new(5.0, 0.0, 0.0),
+        ];
+        assert_eq!(catmull_rom(p[0], p[1], p[2], p[3], 0.0), p[1]);
+        assert_eq!(catmull_rom(p[0], p[1], p[2], p[3], 1.0), p[2]);
+    }
+
+    #[test]
+    fn sample_hits_keyframes_and_clamps_outside() {
+        let path = path(false);
+        for keyframe in &path.keyframes {
+            assert_pose(path.sample(keyframe.time).unwrap(), keyframe);
+        }
+        assert_pose(path.sample(0.0).unwrap(), &path.keyframes[0]);
+        assert_pose(path.sample(10.0).unwrap(), &path.keyframes[2]);
+
+        let halfway = path.sample(1.5).unwrap();
+        assert!(halfway.position.x > 0.0 && halfway.position.x < 4.0);
+        assert_close(halfway.yaw.0, 0.25);
+
+        assert_eq!(CameraPath::default().sample(1.0), None);
+    }
+
+    #[test]
+    fn looped_paths_wrap_and_others_finish() {
+        let mut camera = Camera::new((0.0, 0.0, 0.0), Rad(0.0), Rad(0.0));
+        let mut player = CameraPlayer::new(path(true));
+        assert!(player.update(&mut camera, Duration::from_millis(3500)));
+        assert_close(player.time(), 0.5);
+        // Before the first keyframe, so held at it.
+        assert_close(camera.position.x, 0.0);
+        assert!(player.update(&mut camera, Duration::from_millis(1500)));
+        assert_close(player.time(), 2.0);
+        assert_close(camera.position.x, 4.0);
+
+        let mut player = CameraPlayer::new(path(false));
+        assert!(player.update(&mut camera, Duration::from_millis(2000)));
+        assert!(!player.update(&mut camera, Duration::from_millis(2000)));
+        assert!(player.is_finished());
+        assert_close(camera.position.x, 6.0);
+    }
+
+    #[test]
+    fn yaw_and_pitch_survive_a_quaternion_round_trip() {
+        for yaw in [-3.0, -1.2, 0.0, 0.7, 3.0] {
+            for pitch in [-1.5, -0.3, 0.0, 0.9, 1.5] {
+                let (back_yaw, back_pitch) = yaw_pitch_from_orientation(orientation_from_yaw_pitch(Rad(yaw), Rad(pitch)));
+                assert_close(back_yaw.0, yaw);
+                assert_close(back_pitch.0, pitch);
+            }
+        }
+        // The forward vector matches Camera's.
+        let camera = Camera::new((0.0, 0.0, 0.0), Rad(0.7), Rad(0.3));
+        let forward = orientation_from_yaw_pitch(camera.yaw, camera.pitch).rotate_vector(Vector3::unit_x());
+        assert!((forward - camera.forward().normalize()).magnitude() < 1e-5);
+    }
+
+    #[test]
+    fn recordings_play_back_the_recorded_poses() {
+        let pose_at = |time: f32| Camera::new((time, 2.0, -time * 0.5), Rad(time * 0.3), Rad(0.1));
+        let step = Duration::from_millis(100);
+        let mut recorder = CameraRecorder::new(&pose_at(0.0));
+        let mut time = 0.0;
+        for _ in 0..17 {
+            time += step.as_secs_f32();
+            recorder.record(&pose_at(time), step);
+        }
+        let path = recorder.finish(&pose_at(time));
+        assert!(path.keyframes.len() >= 6);
+        assert_close(path.duration(), time);
+
+        for keyframe in &path.keyframes {
+            let expected = Keyframe::from_camera(keyframe.time, &pose_at(keyframe.time));
+            assert_pose(path.sample(keyframe.time).unwrap(), &expected);
+        }
+    }
+
+    #[test]
+    fn paths_round_trip_through_json() {
+        let file = std::env::temp_dir().join(format!("wgpu-sandbox-camera-path-{}.json", std::process::id()));
+        let path = path(true);
+        path.save(&file).unwrap();
+        assert_eq!(CameraPath::load(&file).unwrap(), path);
+
+        // Keyframes are sorted on load, and looped defaults to false.
+        std::fs::write(
+            &file,
+            r#"{"keyframes": [
+                {"time": 2.0, "position": [1, 0, 0], "yaw": 0, "pitch": 0},
+                {"time": 1.0, "position": [0, 0, 0], "yaw": 0, "pitch": 0}
+            ]}"#,
+        )
+        .unwrap();
+        let loaded = CameraPath::load(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(loaded.keyframes.iter().map(|k| k.time).collect::<Vec<_>>(), [1.0, 2.0]);
+        assert!(!loaded.looped);
+    }
+}
@@ -1,13 +1,77 @@
 use std::time::Duration;
 
-use cgmath::{InnerSpace, Rad, Vector3};
-use winit::{dpi::PhysicalPosition, event::{ElementState, MouseScrollDelta}, keyboard::KeyCode};
+use cgmath::{InnerSpace, Point3, Rad, Vector2, Vector3, Zero};
+use winit::{dpi::PhysicalPosition, event::MouseScrollDelta};
 
-use crate::camera::{self, Camera, SAFE_FRAC_PI_2};
-use std::f32::consts::FRAC_PI_2;
+use crate::input::Action;
+use crate::player::PlayerInput;
+use crate::camera::{Camera, Projection, SAFE_FRAC_PI_2};
+
+// Distance an orbit starts at when switching over from fly mode.
+const DEFAULT_ORBIT_DISTANCE: f32 = 5.0;
+const MIN_ORBIT_DISTANCE: f32 = 0.5;
+// Pan speed per pixel of mouse motion, scaled by the orbit distance.
+const PAN_SPEED: f32 = 0.002;
+// Fraction the FOV changes per pixel of scroll; one wheel line is ~100 pixels.
+const ZOOM_SPEED: f32 = 0.001;
+// Fraction the orbit distance changes per pixel of scroll.
+const DOLLY_SPEED: f32 = 0.002;
+// Distance used to match perspective and orthographic framing in fly mode.
+const FLY_FOCUS_DISTANCE: f32 = 10.0;
+// Radians per pixel at sensitivity 1.0. Mouse deltas used to be scaled by dt,
+// this keeps the old feel at 60 fps without depending on the frame rate.
+const LOOK_SCALE: f32 = 1.0 / 60.0;
+
+// Tunables for how movement and look respond to input.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MovementSettings {
+    // Units per second squared while speeding up towards the target velocity.
+    pub acceleration: f32,
+    // Units per second squared while slowing down, including after keys are released.
+    pub deceleration: f32,
+    // How quickly pending mouse look is applied, per second. 0 applies it immediately.
+    pub look_damping: f32,
+    // Speed multipliers while Action::Sprint or Action::Slow is held.
+    pub sprint_multiplier: f32,
+    pub slow_multiplier: f32,
+}
+
+impl Default for MovementSettings {
+    fn default() -> Self {
+        Self {
+            acceleration: 40.0,
+            deceleration: 30.0,
+            look_damping: 0.0,
+            sprint_multiplier: 3.0,
+            slow_multiplier: 0.25,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CameraMode {
+    // Free-fly yaw/pitch movement.
+    Fly,
+    // Rotate around `target` at `distance`, for inspecting models.
+    Orbit { target: Point3<f32>, distance: f32 },
+}
 
 #[derive(Debug)]
 pub struct CameraController {
+    pub mode: CameraMode,
+    pub movement: MovementSettings,
+    // Current movement velocity in world space; eases towards the input direction.
+    velocity: Vector3<f32>,
+    sprint: bool,
+    slow: bool,
+    // Gamepad sticks: strafe/forward as a fraction of speed, and yaw/pitch rates in radians per second.
+    analog_move: Vector2<f32>,
+    analog_look: Vector2<f32>,
+    toggle_mode_requested: bool,
+    toggle_projection_requested: bool,
+    panning: bool,
+    pan_horizontal: f32,
+    pan_vertical: f32,
     amount_left: f32,
     amount_right: f32,
     amount_forward: f32,
@@ -24,6 +88,18 @@ pub struct CameraController {
 impl CameraController {
     pub fn new(speed: f32, sensitivity: f32) -> Self {
         Self {
+            mode: CameraMode::Fly,
+            movement: MovementSettings::default(),
+            velocity: Vector3::new(0.0, 0.0, 0.0),
+            sprint: false,
+            slow: false,
+            analog_move: Vector2::zero(),
+            analog_look: Vector2::zero(),
+            toggle_mode_requested: false,
+            toggle_projection_requested: false,
+            panning: false,
+            pan_horizontal: 0.0,
+            pan_vertical: 0.0,
             amount_left: 0.0,
             amount_right: 0.0,
             amount_forward: 0.0,
@@ -38,44 +114,124 @@ impl CameraController {
         }
     }
 
-    pub fn process_keyboard(&mut self, key: KeyCode, state: ElementState) -> bool{
-        let amount = if state == ElementState::Pressed { 1.0 } else { 0.0 };
-        match key {
-            KeyCode::KeyW | KeyCode::ArrowUp => {
-                self.amount_forward = amount;
-                true
-            }
-            KeyCode::KeyS | KeyCode::ArrowDown => {
-                self.amount_backward = amount;
-                true
-            }
-            KeyCode::KeyA | KeyCode::ArrowLeft => {
-                self.amount_left = amount;
-                true
+    // Returns false for actions the camera doesn't care about.
+    pub fn process_action(&mut self, action: Action, pressed: bool) -> bool {
+        let amount = if pressed { 1.0 } else { 0.0 };
+        match action {
+            Action::MoveForward => self.amount_forward = amount,
+            Action::MoveBackward => self.amount_backward = amount,
+            Action::MoveLeft => self.amount_left = amount,
+            Action::MoveRight => self.amount_right = amount,
+            Action::Jump => self.amount_up = amount,
+            Action::Crouch => self.amount_down = amount,
+            Action::Sprint => self.sprint = pressed,
+            Action::Slow => self.slow = pressed,
+            Action::Pan => self.set_panning(pressed),
+            Action::ToggleCameraMode => {
+                if pressed {
+                    self.toggle_mode_requested = true;
+                }
             }
-            KeyCode::KeyD | KeyCode::ArrowRight => {
-                self.amount_right = amount;
-                true
+            Action::ToggleProjection => {
+                if pressed {
+                    self.toggle_projection_requested = true;
+                }
             }
-            KeyCode::Space => {
-                self.amount_up = amount;
-                true
-            }
-            KeyCode::ShiftLeft => {
-                self.amount_down = amount;
-                true
-            }
-            _ => false,
+            _ => return false,
         }
+        true
     }
 
+    // Lets go of everything, e.g. when the console takes the keyboard and the
+    // key releases would never reach us.
+    pub fn reset_input(&mut self) {
+        self.amount_forward = 0.0;
+        self.amount_backward = 0.0;
+        self.amount_left = 0.0;
+        self.amount_right = 0.0;
+        self.amount_up = 0.0;
+        self.amount_down = 0.0;
+        self.sprint = false;
+        self.slow = false;
+        self.panning = false;
+        self.rotate_horizontal = 0.0;
+        self.rotate_vertical = 0.0;
+    }
+
+    // Several motion events can arrive per frame, so deltas accumulate until
+    // update_camera consumes them.
     pub fn process_mouse(&mut self, mouse_dx: f64, mouse_dy: f64) {
-        self.rotate_horizontal = mouse_dx as f32;
-        self.rotate_vertical = mouse_dy as f32;
+        if self.panning {
+            self.pan_horizontal += mouse_dx as f32;
+            self.pan_vertical += mouse_dy as f32;
+        } else {
+            self.rotate_horizontal += mouse_dx as f32;
+            self.rotate_vertical += mouse_dy as f32;
+        }
+    }
+
+    // Stick input replaces the previous frame's; it's a state, not a delta.
+    pub fn set_analog(&mut self, movement: Vector2<f32>, look: Vector2<f32>) {
+        self.analog_move = movement;
+        self.analog_look = look;
+    }
+
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+    }
+
+    pub fn set_sensitivity(&mut self, sensitivity: f32) {
+        self.sensitivity = sensitivity;
+    }
+
+    #[cfg(test)]
+    pub fn velocity(&self) -> Vector3<f32> {
+        self.velocity
+    }
+
+    fn speed_multiplier(&self) -> f32 {
+        let mut multiplier = 1.0;
+        if self.sprint {
+            multiplier *= self.movement.sprint_multiplier;
+        }
+        if self.slow {
+            multiplier *= self.movement.slow_multiplier;
+        }
+        multiplier
+    }
+
+    // Middle mouse button; only orbit mode pans.
+    pub fn set_panning(&mut self, panning: bool) {
+        self.panning = panning && matches!(self.mode, CameraMode::Orbit { .. });
+    }
+
+    pub fn is_panning(&self) -> bool {
+        self.panning
+    }
+
+    pub fn set_mode(&mut self, mode: CameraMode, camera: &mut Camera) {
+        self.mode = mode;
+        self.panning = false;
+        self.velocity = Vector3::new(0.0, 0.0, 0.0);
+        if let CameraMode::Orbit { target, distance } = mode {
+            camera.position = target - camera.forward() * distance;
+        }
+    }
+
+    // Orbit around the point the camera is currently looking at, or go back to flying.
+    pub fn toggle_mode(&mut self, camera: &mut Camera) {
+        let mode = match self.mode {
+            CameraMode::Fly => CameraMode::Orbit {
+                target: camera.position + camera.forward() * DEFAULT_ORBIT_DISTANCE,
+                distance: DEFAULT_ORBIT_DISTANCE,
+            },
+            CameraMode::Orbit { .. } => CameraMode::Fly,
+        };
+        self.set_mode(mode, camera);
     }
 
     pub fn process_scroll(&mut self, delta: &MouseScrollDelta) {
-        self.scroll = -match delta {
+        self.scroll -= match delta {
             // I'm assuming a line is about 100 pixels
             MouseScrollDelta::LineDelta(_, scroll) => scroll * 100.0,
             MouseScrollDelta::PixelDelta(PhysicalPosition {
@@ -86,39 +242,125 @@ impl CameraController {
     }
 
     pub fn update_camera(&mut self, camera: &mut Camera, dt: Duration) {
+        if self.toggle_mode_requested {
+            self.toggle_mode_requested = false;
+            self.toggle_mode(camera);
+        }
+        match self.mode {
+            CameraMode::Fly => self.update_fly(camera, dt),
+            CameraMode::Orbit { target, distance } => self.update_orbit(camera, target, distance, dt),
+        }
+    }
+
+    // Used instead of update_camera while a Player has the camera: look is applied
+    // here, movement is handed back for the player to collide with the world.
+    pub fn update_player(&mut self, camera: &mut Camera, dt: Duration) -> PlayerInput {
+        let dt = dt.as_secs_f32();
+        self.apply_look(camera, dt);
+        // Jump and crouch are passed on as flags rather than folded into the
+        // direction, where they'd take a share of the ground speed.
+        PlayerInput {
+            direction: self.update_velocity(camera, dt, false),
+            jump: self.amount_up > 0.0,
+            crouch: self.amount_down > 0.0,
+            climb_speed: self.speed * self.speed_multiplier(),
+        }
+    }
+
+    // Scroll zooms the projection in fly mode (orbit mode dollies instead).
+    pub fn update_projection(&mut self, projection: &mut Projection) {
+        if self.toggle_projection_requested {
+            self.toggle_projection_requested = false;
+            let focus_distance = match self.mode {
+                CameraMode::Orbit { distance, .. } => distance,
+                CameraMode::Fly => FLY_FOCUS_DISTANCE,
+            };
+            projection.toggle_orthographic(focus_distance);
+        }
+        if self.mode == CameraMode::Fly && self.scroll != 0.0 {
+            projection.zoom((1.0 + self.scroll * ZOOM_SPEED).max(0.1));
+            self.scroll = 0.0;
+        }
+    }
+
+    fn update_orbit(&mut self, camera: &mut Camera, mut target: Point3<f32>, mut distance: f32, dt: Duration) {
         let dt = dt.as_secs_f32();
 
-        // Move forward/backward and left/right
+        // Rotate around the target
+        self.apply_look(camera, dt);
+
+        // Dolly towards/away from the target. Like zoom, scroll is a per-event
+        // delta, so it isn't scaled by dt.
+        distance = (distance * (1.0 + self.scroll * DOLLY_SPEED).max(0.1)).max(MIN_ORBIT_DISTANCE);
+        self.scroll = 0.0;
+
+        // Pan the target in the view plane. Mouse deltas are already per-frame,
+        // so they aren't scaled by dt.
+        target += camera.right() * -self.pan_horizontal * PAN_SPEED * distance;
+        target += camera.up() * self.pan_vertical * PAN_SPEED * distance;
+        self.pan_horizontal = 0.0;
+        self.pan_vertical = 0.0;
+
+        // Keys move the target across the ground plane
+        target += self.update_velocity(camera, dt, true) * dt;
+
+        camera.position = target - camera.forward() * distance;
+        self.mode = CameraMode::Orbit { target, distance };
+    }
+
+    fn update_fly(&mut self, camera: &mut Camera, dt: Duration) {
+        let dt = dt.as_secs_f32();
+
+        // Move along the ground plane and up/down. Since we don't use roll,
+        // vertical movement is just the y axis.
+        camera.position += self.update_velocity(camera, dt, true) * dt;
+
+        self.apply_look(camera, dt);
+    }
+
+    // Eases the velocity towards the direction the keys ask for and returns it.
+    // Without `vertical`, jump and crouch don't move along y.
+    fn update_velocity(&mut self, camera: &Camera, dt: f32, vertical: bool) -> Vector3<f32> {
         let (yaw_sin, yaw_cos) = camera.yaw.0.sin_cos();
         let forward = Vector3::new(yaw_cos, 0.0, yaw_sin).normalize();
         let right = Vector3::new(-yaw_sin, 0.0, yaw_cos).normalize();
-        camera.position += forward * (self.amount_forward - self.amount_backward) * self.speed * dt;
-        camera.position += right * (self.amount_right - self.amount_left) * self.speed * dt;
-
-        // Move in/out (aka. "zoom")
-        // Note: this isn't an actual zoom. The camera's position
-        // changes when zooming. I've added this to make it easier
-        // to get closer to an object you want to focus on.
-        let (pitch_sin, pitch_cos) = camera.pitch.0.sin_cos();
-        let scrollward = Vector3::new(pitch_cos * yaw_cos, pitch_sin, pitch_cos * yaw_sin).normalize();
-        camera.position += scrollward * self.scroll * self.speed * self.sensitivity * dt;
-        self.scroll = 0.0;
-
-        // Move up/down. Since we don't use roll, we can just
-        // modify the y coordinate directly.
-        camera.position.y += (self.amount_up - self.amount_down) * self.speed * dt;
+        let mut direction = forward * (self.amount_forward - self.amount_backward)
+            + right * (self.amount_right - self.amount_left)
+            + forward * self.analog_move.y
+            + right * self.analog_move.x;
+        if vertical {
+            direction += Vector3::unit_y() * (self.amount_up - self.amount_down);
+        }
+        // Clamped so diagonals aren't faster than straight lines, while a
+        // half-tilted stick still moves at half speed.
+        let direction = if direction.magnitude2() > 1.0 { direction.normalize() } else { direction };
+        let target = direction * self.speed * self.speed_multiplier();
+        let rate = if target.magnitude2() > self.velocity.magnitude2() {
+            self.movement.acceleration
+        } else {
+            self.movement.deceleration
+        };
+        self.velocity = approach(self.velocity, target, rate * dt);
+        self.velocity
+    }
 
-        // Rotate
-        camera.yaw += Rad(self.rotate_horizontal) * self.sensitivity * dt;
-        camera.pitch += Rad(-self.rotate_vertical) * self.sensitivity * dt;
+    // Applies the damped share of the accumulated mouse motion; the rest carries over.
+    fn apply_look(&mut self, camera: &mut Camera, dt: f32) {
+        let fraction = damping_fraction(self.movement.look_damping, dt);
+        let horizontal = self.rotate_horizontal * fraction;
+        let vertical = self.rotate_vertical * fraction;
+        self.rotate_horizontal -= horizontal;
+        self.rotate_vertical -= vertical;
 
-        // If process_mouse isn't called every frame, these values
-        // will not get set to zero, and the camera will rotate
-        // when moving in a non-cardinal direction.
-        self.rotate_horizontal = 0.0;
-        self.rotate_vertical = 0.0;
+        camera.yaw += Rad(horizontal * self.sensitivity * LOOK_SCALE);
+        camera.pitch += Rad(-vertical * self.sensitivity * LOOK_SCALE);
+        camera.yaw += Rad(self.analog_look.x * dt);
+        camera.pitch += Rad(self.analog_look.y * dt);
+        self.clamp_pitch(camera);
+    }
 
-        // Keep the camera's angle from going too high/low.
+    // Keep the camera's angle from going too high/low.
+    fn clamp_pitch(&self, camera: &mut Camera) {
         if camera.pitch < -Rad(SAFE_FRAC_PI_2) {
             camera.pitch = -Rad(SAFE_FRAC_PI_2);
         } else if camera.pitch > Rad(SAFE_FRAC_PI_2) {
@@ -126,3 +368,162 @@ impl CameraController {
         }
     }
 }
+
+// Moves `current` towards `target` by at most `max_delta`.
+pub fn approach(current: Vector3<f32>, target: Vector3<f32>, max_delta: f32) -> Vector3<f32> {
+    let diff = target - current;
+    let distance = diff.magnitude();
+    if distance <= max_delta || distance == 0.0 {
+        target
+    } else {
+        current + diff * (max_delta / distance)
+    }
+}
+
+// Share of a pending value to apply this frame for exponential damping. Frame-rate
+// independent: two steps of dt leave the same remainder as one step of 2 * dt.
+pub fn damping_fraction(damping: f32, dt: f32) -> f32 {
+    if damping <= 0.0 {
+        1.0
+    } else {
+        1.0 - (-damping * dt).exp()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STEP: Duration = Duration::from_millis(50);
+
+    fn camera() -> Camera {
+        // Yaw 0 looks down +x.
+        Camera::new((0.0, 0.0, 0.0), Rad(0.0), Rad(0.0))
+    }
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-5, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn approach_steps_and_snaps() {
+        let target = Vector3::new(10.0, 0.0, 0.0);
+        assert_eq!(approach(Vector3::zero(), target, 4.0), Vector3::new(4.0, 0.0, 0.0));
+        assert_eq!(approach(Vector3::new(8.0, 0.0, 0.0), target, 4.0), target);
+        assert_eq!(approach(target, target, 0.0), target);
+    }
+
+    #[test]
+    fn accelerates_to_target_speed() {
+        let mut controller = CameraController::new(10.0, 1.0);
+        let mut camera = camera();
+        controller.process_action(Action::MoveForward, true);
+        // 40 units/s² for 50 ms is 2 units/s per step, so 5 steps to reach 10.
+        for step in 1..=4 {
+            controller.update_camera(&mut camera, STEP);
+            assert_close(controller.velocity().x, 2.0 * step as f32);
+        }
+        controller.update_camera(&mut camera, STEP);
+        assert_eq!(controller.velocity(), Vector3::new(10.0, 0.0, 0.0));
+        controller.update_camera(&mut camera, STEP);
+        assert_eq!(controller.velocity(), Vector3::new(10.0, 0.0, 0.0));
+
+        controller.process_action(Action::Sprint, true);
+        for _ in 0..10 {
+            controller.update_camera(&mut camera, STEP);
+        }
+        assert_close(controller.velocity().x, 30.0);
+
+        // Slows at the deceleration rate, 1.5 units/s per step.
+        controller.process_action(Action::Sprint, false);
+        controller.process_action(Action::MoveForward, false);
+        controller.update_camera(&mut camera, STEP);
+        assert_close(controller.velocity().x, 28.5);
+        for _ in 0..20 {
+            controller.update_camera(&mut camera, STEP);
+        }
+        assert_eq!(controller.velocity(), Vector3::zero());
+    }
+
+    #[test]
+    fn diagonals_are_not_faster() {
+        let mut controller = CameraController::new(10.0, 1.0);
+        let mut camera = camera();
+        controller.process_action(Action::MoveForward, true);
+        controller.process_action(Action::MoveRight, true);
+        for _ in 0..20 {
+            controller.update_camera(&mut camera, STEP);
+        }
+        assert_close(controller.velocity().magnitude(), 10.0);
+    }
+
+    #[test]
+    fn jump_and_crouch_keep_full_ground_speed() {
+        let mut controller = CameraController::new(10.0, 1.0);
+        let mut camera = camera();
+        controller.process_action(Action::MoveForward, true);
+        controller.process_action(Action::Jump, true);
+        controller.process_action(Action::Crouch, true);
+        let mut input = controller.update_player(&mut camera, STEP);
+        for _ in 0..10 {
+            input = controller.update_player(&mut camera, STEP);
+        }
+        assert_eq!(input.direction, Vector3::new(10.0, 0.0, 0.0));
+        assert!(input.jump && input.crouch);
+        assert_close(input.climb_speed, 10.0);
+    }
+
+    #[test]
+    fn damping_is_frame_rate_independent() {
+        let one_step = 1.0 - damping_fraction(8.0, 0.032);
+        let two_steps = (1.0 - damping_fraction(8.0, 0.016)).powi(2);
+        assert_close(one_step, two_steps);
+        assert_eq!(damping_fraction(0.0, 0.016), 1.0);
+
+        let look = |steps: &[u64]| {
+            let mut controller = CameraController::new(10.0, 1.0);
+            controller.movement.look_damping = 8.0;
+            let mut camera = camera();
+            controller.process_mouse(100.0, 0.0);
+            for &ms in steps {
+                controller.update_camera(&mut camera, Duration::from_millis(ms));
+            }
+            camera.yaw.0
+        };
+        assert_close(look(&[32]), look(&[16, 16]));
+    }
+
+    #[test]
+    fn dolly_is_frame_rate_independent() {
+        let dolly = |dt: Duration| {
+            let mut controller = CameraController::new(10.0, 1.0);
+            let mut camera = camera();
+            controller.process_action(Action::ToggleCameraMode, true);
+            controller.update_camera(&mut camera, dt);
+            // One wheel notch away from the target.
+            controller.process_scroll(&MouseScrollDelta::LineDelta(0.0, -1.0));
+            controller.update_camera(&mut camera, dt);
+            match controller.mode {
+                CameraMode::Orbit { distance, .. } => distance,
+                CameraMode::Fly => panic!("not orbiting"),
+            }
+        };
+        assert_close(dolly(STEP), DEFAULT_ORBIT_DISTANCE * 1.2);
+        assert_close(dolly(Duration::from_millis(100)), dolly(STEP));
+    }
+
+    #[test]
+    fn mouse_deltas_accumulate_until_applied() {
+        let mut controller = CameraController::new(10.0, 2.0);
+        let mut camera = camera();
+        controller.process_mouse(3.0, 0.0);
+        controller.process_mouse(4.0, 2.0);
+        controller.update_camera(&mut camera, STEP);
+        assert_close(camera.yaw.0, 7.0 * 2.0 * LOOK_SCALE);
+        assert_close(camera.pitch.0, -2.0 * 2.0 * LOOK_SCALE);
+
+        // Consumed; nothing more without new motion.
+        controller.update_camera(&mut camera, STEP);
+        assert_close(camera.yaw.0, 7.0 * 2.0 * LOOK_SCALE);
+    }
+}
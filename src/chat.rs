@@ -0,0 +1,472 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+// Loopback only unless an address is given, so `host` doesn't open a port to the network.
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:27960";
+pub const MAX_NAME_LEN: usize = 24;
+pub const MAX_MESSAGE_LEN: usize = 400;
+// One JSON packet per line; anything longer drops the connection.
+const MAX_PACKET_BYTES: u64 = 4096;
+// A client that hasn't said hello by then is dropped.
+const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+const ACCEPT_POLL: Duration = Duration::from_millis(20);
+// Per resolved address. Connecting happens on the render thread, so an
+// unreachable host costs a short hitch rather than the OS's minute-long timeout.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+// Each client has its own writer thread, so one that stops reading never
+// stalls the others. Its connection is closed once a write has been stuck this
+// long, or once this many packets are waiting for it.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(2);
+const PEER_QUEUE: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    // Milliseconds since the Unix epoch, stamped by the server.
+    pub timestamp_ms: u64,
+    pub name: String,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientPacket {
+    Hello { name: String },
+    Say { text: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerPacket {
+    // Sent to every client, including the one that said it.
+    Chat(ChatMessage),
+    // Joins, leaves and the like.
+    Notice { timestamp_ms: u64, text: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChatEvent {
+    Message(ChatMessage),
+    Notice { timestamp_ms: u64, text: String },
+    // The server closed the connection or it failed; no more events follow.
+    Disconnected,
+}
+
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+// HH:MM in UTC; std has no time zones.
+pub fn format_time(timestamp_ms: u64) -> String {
+    let minutes = timestamp_ms / 60_000;
+    format!("{:02}:{:02}", (minutes / 60) % 24, minutes % 60)
+}
+
+// Trims, drops control characters and caps the length. Empty names become "player".
+pub fn sanitize_name(name: &str) -> String {
+    let name = clean(name, MAX_NAME_LEN);
+    if name.is_empty() {
+        "player".to_string()
+    } else {
+        name
+    }
+}
+
+pub fn sanitize_message(text: &str) -> String {
+    clean(text, MAX_MESSAGE_LEN)
+}
+
+fn clean(text: &str, max_len: usize) -> String {
+    text.chars()
+        .filter(|c| !c.is_control())
+        .take(max_len)
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+// A stable color per name, so the same player always looks the same.
+pub fn name_color(name: &str) -> [u8; 3] {
+    const PALETTE: [[u8; 3]; 8] = [
+        [255, 120, 120],
+        [120, 200, 255],
+        [140, 255, 140],
+        [255, 210, 100],
+        [220, 140, 255],
+        [100, 240, 220],
+        [255, 160, 210],
+        [200, 200, 120],
+    ];
+    // FNV-1a, which unlike DefaultHasher is the same on every run.
+    let hash = name
+        .bytes()
+        .fold(0xcbf29ce484222325u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3));
+    PALETTE[(hash % PALETTE.len() as u64) as usize]
+}
+
+fn write_packet<T: Serialize>(stream: &mut TcpStream, packet: &T) -> io::Result<()> {
+    let mut line = serde_json::to_string(packet)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())
+}
+
+// None at end of stream.
+fn read_packet<T: for<'de> Deserialize<'de>>(reader: &mut BufReader<TcpStream>) -> io::Result<Option<T>> {
+    let mut line = String::new();
+    let read = reader.by_ref().take(MAX_PACKET_BYTES).read_line(&mut line)?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if !line.ends_with('\n') {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "packet too long"));
+    }
+    serde_json::from_str(&line)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+struct Peer {
+    id: u64,
+    // Lines for the peer's writer thread. Dropping it ends the thread.
+    queue: SyncSender<Arc<str>>,
+}
+
+#[derive(Default)]
+struct Shared {
+    running: AtomicBool,
+    next_id: AtomicU64,
+    // Clients that have said hello; chat is broadcast to these.
+    peers: Mutex<Vec<Peer>>,
+    // Every open connection, so shutdown can unblock their reader threads.
+    connections: Mutex<Vec<(u64, TcpStream)>>,
+}
+
+impl Shared {
+    // Only queues the packet, so the lock is never held across a write.
+    fn broadcast(&self, packet: &ServerPacket) {
+        let line: Arc<str> = match serde_json::to_string(packet) {
+            Ok(json) => format!("{}\n", json).into(),
+            Err(e) => {
+                warn!("Couldn't encode chat packet: {}", e);
+                return;
+            }
+        };
+        // A full queue means the peer stopped reading; dropping its sender
+        // closes the connection.
+        self.peers
+            .lock()
+            .unwrap()
+            .retain(|peer| peer.queue.try_send(line.clone()).is_ok());
+    }
+
+    fn notice(&self, text: String) {
+        self.broadcast(&ServerPacket::Notice { timestamp_ms: now_ms(), text });
+    }
+
+    fn remove(&self, id: u64) {
+        self.peers.lock().unwrap().retain(|peer| peer.id != id);
+        self.connections.lock().unwrap().retain(|(other, _)| *other != id);
+    }
+}
+
+// Relays chat between connected clients, one thread per connection. Stops
+// when dropped.
+pub struct ChatServer {
+    shared: Arc<Shared>,
+    local_addr: SocketAddr,
+    accept_thread: Option<JoinHandle<()>>,
+}
+
+impl ChatServer {
+    // Port 0 picks a free port; see local_addr.
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        // Non-blocking so the accept loop can see the shutdown flag.
+        listener.set_nonblocking(true)?;
+        let local_addr = listener.local_addr()?;
+        let shared = Arc::new(Shared::default());
+        shared.running.store(true, Ordering::SeqCst);
+        let accept_shared = shared.clone();
+        let accept_thread = thread::Builder::new()
+            .name("chat-accept".to_string())
+            .spawn(move || accept_loop(listener, accept_shared))?;
+        info!("Chat server listening on {}", local_addr);
+        Ok(Self {
+            shared,
+            local_addr,
+            accept_thread: Some(accept_thread),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn client_count(&self) -> usize {
+        self.shared.peers.lock().unwrap().len()
+    }
+
+    pub fn shutdown(&mut self) {
+        if !self.shared.running.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        for (_, stream) in self.shared.connections.lock().unwrap().drain(..) {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        self.shared.peers.lock().unwrap().clear();
+        if let Some(thread) = self.accept_thread.take() {
+            let _ = thread.join();
+        }
+        info!("Chat server on {} stopped", self.local_addr);
+    }
+}
+
+impl Drop for ChatServer {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+fn accept_loop(listener: TcpListener, shared: Arc<Shared>) {
+    while shared.running.load(Ordering::SeqCst) {
+        match listener.accept() {
+            Ok((stream, addr)) => {
+                let shared = shared.clone();
+                let spawned = thread::Builder::new()
+                    .name(format!("chat-client-{}", addr))
+                    .spawn(move || {
+                        if let Err(e) = serve_client(stream, &shared) {
+                            warn!("Chat client {}: {}", addr, e);
+                        }
+                    });
+                if let Err(e) = spawned {
+                    warn!("Couldn't start chat client thread: {}", e);
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(ACCEPT_POLL),
+            Err(e) => warn!("Chat accept failed: {}", e),
+        }
+    }
+}
+
+fn serve_client(stream: TcpStream, shared: &Shared) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_nodelay(true)?;
+    let id = shared.next_id.fetch_add(1, Ordering::SeqCst);
+    shared.connections.lock().unwrap().push((id, stream.try_clone()?));
+    // Shutdown may have drained the list just before we were added.
+    if !shared.running.load(Ordering::SeqCst) {
+        shared.remove(id);
+        return Ok(());
+    }
+    let result = relay(stream, id, shared);
+    shared.remove(id);
+    result
+}
+
+fn relay(stream: TcpStream, id: u64, shared: &Shared) -> io::Result<()> {
+    stream.set_read_timeout(Some(HELLO_TIMEOUT))?;
+    let writer = stream.try_clone()?;
+    writer.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+    let name = match read_packet(&mut reader)? {
+        Some(ClientPacket::Hello { name }) => sanitize_name(&name),
+        Some(_) => return Err(io::Error::new(io::ErrorKind::InvalidData, "expected hello")),
+        None => return Ok(()),
+    };
+    reader.get_ref().set_read_timeout(None)?;
+    let (queue, outgoing) = mpsc::sync_channel(PEER_QUEUE);
+    let writer_thread = thread::Builder::new()
+        .name(format!("chat-writer-{}", id))
+        .spawn(move || write_loop(writer, outgoing))?;
+    shared.peers.lock().unwrap().push(Peer { id, queue });
+    shared.notice(format!("{} joined", name));
+
+    let result = loop {
+        match read_packet(&mut reader) {
+            Ok(Some(ClientPacket::Say { text })) => {
+                let text = sanitize_message(&text);
+                if !text.is_empty() {
+                    shared.broadcast(&ServerPacket::Chat(ChatMessage {
+                        timestamp_ms: now_ms(),
+                        name: name.clone(),
+                        text,
+                    }));
+                }
+            }
+            // A second hello is ignored rather than renaming the player.
+            Ok(Some(ClientPacket::Hello { .. })) => {}
+            Ok(None) => break Ok(()),
+            Err(e) => break Err(e),
+        }
+    };
+    shared.peers.lock().unwrap().retain(|peer| peer.id != id);
+    let _ = writer_thread.join();
+    if shared.running.load(Ordering::SeqCst) {
+        shared.notice(format!("{} left", name));
+    }
+    result
+}
+
+// Runs until the peer is removed or a write fails.
+fn write_loop(mut stream: TcpStream, outgoing: Receiver<Arc<str>>) {
+    for line in outgoing {
+        if stream.write_all(line.as_bytes()).is_err() {
+            break;
+        }
+    }
+    // Ends the peer's reader too, if this was a failed write.
+    let _ = stream.shutdown(Shutdown::Both);
+}
+
+// One connection to a ChatServer. Incoming packets are read on a background
+// thread and queued for poll.
+pub struct ChatClient {
+    name: String,
+    stream: TcpStream,
+    events: Receiver<ChatEvent>,
+    reader_thread: Option<JoinHandle<()>>,
+}
+
+impl ChatClient {
+    pub fn connect<A: ToSocketAddrs>(addr: A, name: &str) -> io::Result<Self> {
+        let mut stream = connect_timeout(addr, CONNECT_TIMEOUT)?;
+        stream.set_nodelay(true)?;
+        let name = sanitize_name(name);
+        write_packet(&mut stream, &ClientPacket::Hello { name: name.clone() })?;
+        let (sender, events) = mpsc::channel();
+        let reader = BufReader::new(stream.try_clone()?);
+        let reader_thread = thread::Builder::new()
+            .name("chat-reader".to_string())
+            .spawn(move || read_loop(reader, sender))?;
+        Ok(Self {
+            name,
+            stream,
+            events,
+            reader_thread: Some(reader_thread),
+        })
+    }
+
+    // The name after sanitizing, as other players see it.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+
+    pub fn say(&mut self, text: &str) -> io::Result<()> {
+        let text = sanitize_message(text);
+        if text.is_empty() {
+            return Ok(());
+        }
+        write_packet(&mut self.stream, &ClientPacket::Say { text })
+    }
+
+    // Everything received since the last call, without blocking.
+    pub fn poll(&self) -> Vec<ChatEvent> {
+        self.events.try_iter().collect()
+    }
+
+    // Blocks for the next event; for tests and tools rather than the frame loop.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<ChatEvent> {
+        match self.events.recv_timeout(timeout) {
+            Ok(event) => Some(event),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => Some(ChatEvent::Disconnected),
+        }
+    }
+
+    // Skips events until one matches, or None once `timeout` has passed.
+    pub fn wait_for(&self, timeout: Duration, mut matches: impl FnMut(&ChatEvent) -> bool) -> Option<ChatEvent> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let event = self.recv_timeout(remaining)?;
+            if matches(&event) {
+                return Some(event);
+            }
+            if event == ChatEvent::Disconnected {
+                return None;
+            }
+        }
+    }
+}
+
+impl Drop for ChatClient {
+    fn drop(&mut self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+        if let Some(thread) = self.reader_thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// Like TcpStream::connect, trying each resolved address in turn, but giving up
+// on each one after `timeout`.
+fn connect_timeout<A: ToSocketAddrs>(addr: A, timeout: Duration) -> io::Result<TcpStream> {
+    let mut last_error = None;
+    for addr in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "address didn't resolve")))
+}
+
+fn read_loop(mut reader: BufReader<TcpStream>, sender: Sender<ChatEvent>) {
+    loop {
+        let event = match read_packet(&mut reader) {
+            Ok(Some(ServerPacket::Chat(message))) => ChatEvent::Message(message),
+            Ok(Some(ServerPacket::Notice { timestamp_ms, text })) => ChatEvent::Notice { timestamp_ms, text },
+            Ok(None) => break,
+            Err(e) => {
+                warn!("Chat connection: {}", e);
+                break;
+            }
+        };
+        if sender.send(event).is_err() {
+            return;
+        }
+    }
+    let _ = sender.send(ChatEvent::Disconnected);
+}
+
+// A server on a free loopback port with helpers to connect clients to it.
+// Nothing leaves the machine, so integration tests and local play can use
+// it without any network setup.
+pub struct LocalChat {
+    pub server: ChatServer,
+}
+
+impl LocalChat {
+    pub fn start() -> io::Result<Self> {
+        Ok(Self { server: ChatServer::bind("127.0.0.1:0")? })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.server.local_addr()
+    }
+
+    // Connects and waits until the server has registered the client, so
+    // messages sent right after are delivered to it.
+    pub fn client(&self, name: &str, timeout: Duration) -> io::Result<ChatClient> {
+        let client = ChatClient::connect(self.addr(), name)?;
+        let joined = format!("{} joined", client.name());
+        client
+            .wait_for(timeout, |event| matches!(event, ChatEvent::Notice { text, .. } if *text == joined))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::TimedOut, "client didn't join in time"))?;
+        Ok(client)
+    }
+}
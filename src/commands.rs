@@ -0,0 +1,601 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail};
+
+use crate::chat::{self, ChatClient, ChatServer};
+use crate::console::Completer;
+use crate::cvars::{CvarRegistry, CvarValue};
+use crate::input::{self, Action, Binding, InputMap};
+use crate::profiler;
+use crate::window_state::WindowState;
+
+// Commands whose first argument is a cvar name.
+const CVAR_COMMANDS: [&str; 2] = ["set", "reset"];
+
+// Returns text to print, if any. Errors are printed along with the usage line.
+pub type CommandHandler = fn(&mut WindowState<'_>, &Args) -> anyhow::Result<Option<String>>;
+
+#[derive(Clone, Copy)]
+pub struct Command {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub help: &'static str,
+    // Tab completion candidates for each argument position.
+    pub arg_completions: &'static [&'static [&'static str]],
+    pub handler: CommandHandler,
+}
+
+// Sorted by name so `help` lists commands alphabetically.
+#[derive(Default)]
+pub struct CommandRegistry {
+    commands: BTreeMap<&'static str, Command>,
+}
+
+impl CommandRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::new();
+        registry.register(Command {
+            name: "help",
+            usage: "help [command]",
+            help: "List commands, or show how to use one",
+            arg_completions: &[],
+            handler: help,
+        });
+        registry.register(Command {
+            name: "tp",
+            usage: "tp <x> <y> <z>",
+            help: "Teleport the camera (and player) to a position",
+            arg_completions: &[],
+            handler: teleport,
+        });
+        registry.register(Command {
+            name: "light",
+            usage: "light color <r> <g> <b>",
+            help: "Set the light color, components 0..1",
+            arg_completions: &[&["color"]],
+            handler: light,
+        });
+        registry.register(Command {
+            name: "wireframe",
+            usage: "wireframe <on|off>",
+            help: "Draw the scene as lines",
+            arg_completions: &[&["on", "off"]],
+            handler: wireframe,
+        });
+        registry.register(Command {
+            name: "set",
+            usage: "set <cvar> <value...>",
+            help: "Change a console variable",
+            arg_completions: &[],
+            handler: set,
+        });
+        registry.register(Command {
+            name: "reset",
+            usage: "reset <cvar>",
+            help: "Put a console variable back to its default",
+            arg_completions: &[],
+            handler: reset,
+        });
+        registry.register(Command {
+            name: "bind",
+            usage: "bind <action> [binding]",
+            help: "Show an action's bindings, or replace them with one (ctrl+KeyJ, Mouse:Right, Gamepad:South)",
+            arg_completions: &[],
+            handler: bind,
+        });
+        registry.register(Command {
+            name: "unbind",
+            usage: "unbind <action> <binding>",
+            help: "Remove one of an action's bindings",
+            arg_completions: &[],
+            handler: unbind,
+        });
+        registry.register(Command {
+            name: "cvars",
+            usage: "cvars",
+            help: "List console variables and their values",
+            arg_completions: &[],
+            handler: list_cvars,
+        });
+        registry.register(Command {
+            name: "trace",
+            usage: "trace <start|stop> [file]",
+            help: "Capture per-pass timings to a Chrome trace (chrome://tracing, Perfetto)",
+            arg_completions: &[&["start", "stop"]],
+            handler: trace,
+        });
+        registry.register(Command {
+            name: "host",
+            usage: "host [address] [name]",
+            help: "Start a chat server and join it",
+            arg_completions: &[],
+            handler: host,
+        });
+        registry.register(Command {
+            name: "connect",
+            usage: "connect <address> [name]",
+            help: "Join a chat server",
+            arg_completions: &[],
+            handler: connect,
+        });
+        registry.register(Command {
+            name: "say",
+            usage: "say <message...>",
+            help: "Send a chat message",
+            arg_completions: &[],
+            handler: say,
+        });
+        registry.register(Command {
+            name: "disconnect",
+            usage: "disconnect",
+            help: "Leave chat, and stop the server if this is the host",
+            arg_completions: &[],
+            handler: disconnect,
+        });
+        registry.register(Command {
+            name: "clear",
+            usage: "clear",
+            help: "Clear the console output",
+            arg_completions: &[],
+            handler: clear,
+        });
+        registry
+    }
+
+    // Replaces any command with the same name.
+    pub fn register(&mut self, command: Command) {
+        self.commands.insert(command.name, command);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Command> {
+        self.commands.get(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Command> {
+        self.commands.values()
+    }
+}
+
+impl Completer for CommandRegistry {
+    // Command names for the first word, then the command's own argument lists.
+    // `help` completes command names.
+    fn complete(&self, line: &str) -> Vec<String> {
+        let (words, index) = words_and_index(line);
+        if index == 0 {
+            return self.commands.keys().map(|name| name.to_string()).collect();
+        }
+        match self.get(&words[0]) {
+            Some(command) if command.name == "help" && index == 1 => {
+                self.commands.keys().map(|name| name.to_string()).collect()
+            }
+            Some(command) => command
+                .arg_completions
+                .get(index - 1)
+                .map_or(Vec::new(), |options| options.iter().map(|o| o.to_string()).collect()),
+            None => Vec::new(),
+        }
+    }
+}
+
+// The registry's completions plus cvar names, both as the first word (a bare
+// cvar name prints or sets it) and as the argument of set and reset.
+pub struct ConsoleCompleter<'a> {
+    pub commands: &'a CommandRegistry,
+    pub cvars: &'a CvarRegistry,
+}
+
+impl Completer for ConsoleCompleter<'_> {
+    fn complete(&self, line: &str) -> Vec<String> {
+        let (words, index) = words_and_index(line);
+        let cvar_names = || self.cvars.names().map(str::to_string);
+        match index {
+            0 => {
+                let mut candidates = self.commands.complete(line);
+                candidates.extend(cvar_names());
+                candidates.sort();
+                candidates.dedup();
+                candidates
+            }
+            1 if CVAR_COMMANDS.contains(&words[0].as_str()) => cvar_names().collect(),
+            _ => self.commands.complete(line),
+        }
+    }
+}
+
+// The words so far and the index of the one being typed; a trailing space starts a new one.
+fn words_and_index(line: &str) -> (Vec<String>, usize) {
+    let words = tokenize(line);
+    let index = if line.is_empty() || line.ends_with(char::is_whitespace) {
+        words.len()
+    } else {
+        words.len() - 1
+    };
+    (words, index)
+}
+
+// A parsed command line: the command name and its arguments.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Args {
+    pub name: String,
+    pub args: Vec<String>,
+}
+
+impl Args {
+    // None for a blank line.
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut tokens = tokenize(line).into_iter();
+        let name = tokens.next()?;
+        Some(Self { name, args: tokens.collect() })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.args.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> anyhow::Result<&str> {
+        self.args
+            .get(index)
+            .map(String::as_str)
+            .ok_or_else(|| anyhow!("missing argument {}", index + 1))
+    }
+
+    pub fn parse_arg<T: FromStr>(&self, index: usize) -> anyhow::Result<T> {
+        let arg = self.get(index)?;
+        arg.parse()
+            .map_err(|_| anyhow!("invalid argument '{}'", arg))
+    }
+
+    pub fn expect_len(&self, len: usize) -> anyhow::Result<()> {
+        if self.args.len() != len {
+            bail!("expected {} arguments, got {}", len, self.args.len());
+        }
+        Ok(())
+    }
+}
+
+// Splits on whitespace; double quotes group words into one argument.
+pub fn tokenize(line: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut has_token = false;
+    for c in line.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                has_token = true;
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if has_token {
+                    tokens.push(std::mem::take(&mut current));
+                    has_token = false;
+                }
+            }
+            c => {
+                current.push(c);
+                has_token = true;
+            }
+        }
+    }
+    if has_token {
+        tokens.push(current);
+    }
+    tokens
+}
+
+pub fn parse_on_off(arg: &str) -> anyhow::Result<bool> {
+    match arg {
+        "on" | "1" | "true" => Ok(true),
+        "off" | "0" | "false" => Ok(false),
+        _ => bail!("expected on or off, got '{}'", arg),
+    }
+}
+
+fn help(state: &mut WindowState<'_>, args: &Args) -> anyhow::Result<Option<String>> {
+    if let Ok(name) = args.get(0) {
+        let command = state
+            .commands
+            .get(name)
+            .ok_or_else(|| anyhow!("unknown command '{}'", name))?;
+        return Ok(Some(format!("{}\n  {}", command.usage, command.help)));
+    }
+    let lines = state
+        .commands
+        .iter()
+        .map(|command| format!("{:<28} {}", command.usage, command.help))
+        .collect::<Vec<_>>();
+    Ok(Some(lines.join("\n")))
+}
+
+fn teleport(state: &mut WindowState<'_>, args: &Args) -> anyhow::Result<Option<String>> {
+    args.expect_len(3)?;
+    let position = cgmath::Point3::new(args.parse_arg(0)?, args.parse_arg(1)?, args.parse_arg(2)?);
+    state.teleport(position);
+    Ok(None)
+}
+
+fn light(state: &mut WindowState<'_>, args: &Args) -> anyhow::Result<Option<String>> {
+    match args.get(0)? {
+        "color" => {
+            args.expect_len(4)?;
+            let color = [args.parse_arg(1)?, args.parse_arg(2)?, args.parse_arg(3)?];
+            state.set_cvar("light_color", CvarValue::Color(color))?;
+            Ok(None)
+        }
+        other => bail!("unknown light property '{}'", other),
+    }
+}
+
+fn wireframe(state: &mut WindowState<'_>, args: &Args) -> anyhow::Result<Option<String>> {
+    args.expect_len(1)?;
+    state.set_wireframe(parse_on_off(args.get(0)?)?)?;
+    Ok(None)
+}
+
+fn bind(state: &mut WindowState<'_>, args: &Args) -> anyhow::Result<Option<String>> {
+    bind_input(&mut state.input_map, args, input::INPUT_CONFIG_FILE).map(Some)
+}
+
+fn unbind(state: &mut WindowState<'_>, args: &Args) -> anyhow::Result<Option<String>> {
+    unbind_input(&mut state.input_map, args, input::INPUT_CONFIG_FILE).map(Some)
+}
+
+// Changed bindings are saved to `path` straight away.
+fn bind_input(input_map: &mut InputMap, args: &Args, path: impl AsRef<Path>) -> anyhow::Result<String> {
+    let action: Action = args.get(0)?.parse()?;
+    if args.args.len() == 1 {
+        let bindings = input_map.bindings(action).iter().map(Binding::to_string).collect::<Vec<_>>();
+        if bindings.is_empty() {
+            return Ok(format!("{:?} is unbound", action));
+        }
+        return Ok(format!("{:?}: {}", action, bindings.join(", ")));
+    }
+    args.expect_len(2)?;
+    let binding: Binding = args.get(1)?.parse()?;
+    input_map.rebind(action, binding);
+    input_map.save(path)?;
+    Ok(format!("{:?}: {}", action, binding))
+}
+
+fn unbind_input(input_map: &mut InputMap, args: &Args, path: impl AsRef<Path>) -> anyhow::Result<String> {
+    args.expect_len(2)?;
+    let action: Action = args.get(0)?.parse()?;
+    let binding: Binding = args.get(1)?.parse()?;
+    if !input_map.bindings(action).contains(&binding) {
+        bail!("{:?} isn't bound to {}", action, binding);
+    }
+    input_map.unbind(action, &binding);
+    input_map.save(path)?;
+    Ok(format!("Unbound {} from {:?}", binding, action))
+}
+
+fn trace(state: &mut WindowState<'_>, args: &Args) -> anyhow::Result<Option<String>> {
+    match args.get(0)? {
+        "start" => {
+            state.profiler.start_trace();
+            let source = if state.profiler.gpu_timing() { "GPU and CPU" } else { "CPU only" };
+            Ok(Some(format!("Tracing render passes ({})", source)))
+        }
+        "stop" => {
+            let frames = state
+                .profiler
+                .stop_trace()
+                .ok_or_else(|| anyhow!("no trace running"))?;
+            let path = args.get(1).unwrap_or(profiler::TRACE_FILE);
+            profiler::write_chrome_trace(path, &frames)?;
+            Ok(Some(format!("Wrote {} frames to {}", frames.len(), path)))
+        }
+        other => bail!("expected start or stop, got '{}'", other),
+    }
+}
+
+fn host(state: &mut WindowState<'_>, args: &Args) -> anyhow::Result<Option<String>> {
+    if state.chat_server.is_some() {
+        bail!("already hosting, disconnect first");
+    }
+    let address = args.get(0).unwrap_or(chat::DEFAULT_ADDRESS);
+    let server = ChatServer::bind(address).map_err(|e| anyhow!("couldn't listen on {}: {}", address, e))?;
+    let local_addr = server.local_addr();
+    state.chat = Some(ChatClient::connect(local_addr, args.get(1).unwrap_or("host"))?);
+    state.chat_server = Some(server);
+    Ok(Some(format!("Hosting chat on {}", local_addr)))
+}
+
+fn connect(state: &mut WindowState<'_>, args: &Args) -> anyhow::Result<Option<String>> {
+    let address = args.get(0)?;
+    let client = ChatClient::connect(address, args.get(1).unwrap_or("player"))
+        .map_err(|e| anyhow!("couldn't connect to {}: {}", address, e))?;
+    let reply = format!("Connected to {} as {}", address, client.name());
+    state.chat = Some(client);
+    Ok(Some(reply))
+}
+
+fn say(state: &mut WindowState<'_>, args: &Args) -> anyhow::Result<Option<String>> {
+    if args.is_empty() {
+        bail!("nothing to say");
+    }
+    let client = state
+        .chat
+        .as_mut()
+        .ok_or_else(|| anyhow!("not connected, use host or connect"))?;
+    client.say(&args.args.join(" "))?;
+    Ok(None)
+}
+
+fn disconnect(state: &mut WindowState<'_>, _args: &Args) -> anyhow::Result<Option<String>> {
+    if state.chat.is_none() && state.chat_server.is_none() {
+        bail!("not connected");
+    }
+    state.chat = None;
+    state.chat_server = None;
+    Ok(Some("Left chat".to_string()))
+}
+
+fn clear(state: &mut WindowState<'_>, _args: &Args) -> anyhow::Result<Option<String>> {
+    state.console.clear();
+    Ok(None)
+}
+
+fn set(state: &mut WindowState<'_>, args: &Args) -> anyhow::Result<Option<String>> {
+    let name = args.get(0)?;
+    state.set_cvar_from_args(name, &args.args[1..])?;
+    Ok(Some(state.describe_cvar(name)))
+}
+
+fn reset(state: &mut WindowState<'_>, args: &Args) -> anyhow::Result<Option<String>> {
+    args.expect_len(1)?;
+    let name = args.get(0)?;
+    let default = state
+        .cvars
+        .get(name)
+        .ok_or_else(|| anyhow!("unknown cvar '{}'", name))?
+        .default;
+    state.set_cvar(name, default)?;
+    Ok(Some(state.describe_cvar(name)))
+}
+
+fn list_cvars(state: &mut WindowState<'_>, _args: &Args) -> anyhow::Result<Option<String>> {
+    let lines = state
+        .cvars
+        .iter()
+        .map(|cvar| format!("{:<14} {:<16} {}", cvar.name, cvar.value.to_string(), cvar.help))
+        .collect::<Vec<_>>();
+    Ok(Some(lines.join("\n")))
+}
+
+#[cfg(test)]
+mod tests {
+    use winit::keyboard::KeyCode;
+
+    use super::*;
+    use crate::console::complete_line;
+
+    fn complete(line: &str) -> (String, Vec<String>) {
+        let commands = CommandRegistry::with_builtins();
+        let cvars = CvarRegistry::with_defaults();
+        complete_line(line, &ConsoleCompleter { commands: &commands, cvars: &cvars })
+    }
+
+    #[test]
+    fn tokenize_splits_on_any_whitespace() {
+        assert_eq!(tokenize("tp 1 2 3"), ["tp", "1", "2", "3"]);
+        assert_eq!(tokenize("  tp\t1   2 3  "), ["tp", "1", "2", "3"]);
+        assert!(tokenize("").is_empty());
+        assert!(tokenize("   ").is_empty());
+    }
+
+    #[test]
+    fn quotes_group_words() {
+        assert_eq!(tokenize(r#"say "hello  there" bob"#), ["say", "hello  there", "bob"]);
+        assert_eq!(tokenize(r#"say hel"lo th"ere"#), ["say", "hello there"]);
+        // An empty pair is still an argument.
+        assert_eq!(tokenize(r#"connect "" name"#), ["connect", "", "name"]);
+        // Unterminated quotes run to the end of the line.
+        assert_eq!(tokenize(r#"say "hello there  "#), ["say", "hello there  "]);
+    }
+
+    #[test]
+    fn args_split_the_name_from_the_arguments() {
+        let parsed = args(r#"  set light_color 1 "0.5" 0 "#);
+        assert_eq!(parsed.name, "set");
+        assert_eq!(parsed.args, ["light_color", "1", "0.5", "0"]);
+        assert_eq!(parsed.get(2).unwrap(), "0.5");
+        assert_eq!(parsed.get(4).unwrap_err().to_string(), "missing argument 5");
+        assert_eq!(parsed.parse_arg::<f32>(1).unwrap(), 1.0);
+        assert_eq!(parsed.parse_arg::<f32>(0).unwrap_err().to_string(), "invalid argument 'light_color'");
+        assert!(parsed.expect_len(4).is_ok());
+        assert_eq!(parsed.expect_len(3).unwrap_err().to_string(), "expected 3 arguments, got 4");
+
+        assert!(args("clear").is_empty());
+        assert_eq!(Args::parse("   "), None);
+    }
+
+    #[test]
+    fn on_off_accepts_the_usual_spellings() {
+        for on in ["on", "true", "1"] {
+            assert!(parse_on_off(on).unwrap());
+        }
+        for off in ["off", "false", "0"] {
+            assert!(!parse_on_off(off).unwrap());
+        }
+        for bad in ["yes", "2", "", "On"] {
+            assert!(parse_on_off(bad).is_err(), "{:?} parsed", bad);
+        }
+    }
+
+    fn args(line: &str) -> Args {
+        Args::parse(line).unwrap()
+    }
+
+    // Unique per test, so tests running in parallel don't share a file.
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("wgpu-sandbox-{}-{}.json", name, std::process::id()))
+    }
+
+    #[test]
+    fn bind_replaces_bindings_and_saves() {
+        let path = temp_path("bind");
+        let mut input_map = InputMap::default();
+        assert_eq!(bind_input(&mut input_map, &args("bind MoveForward"), &path).unwrap(), "MoveForward: KeyW, ArrowUp");
+        assert!(!path.exists(), "listing shouldn't save");
+
+        let output = bind_input(&mut input_map, &args("bind MoveForward ctrl+KeyI"), &path).unwrap();
+        assert_eq!(output, "MoveForward: ctrl+KeyI");
+        assert_eq!(input_map.bindings(Action::MoveForward), ["ctrl+KeyI".parse::<Binding>().unwrap()]);
+        assert_eq!(InputMap::load(&path).unwrap(), input_map);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(bind_input(&mut input_map, &args("bind Fly KeyF"), &path).is_err());
+        assert!(bind_input(&mut input_map, &args("bind Jump hyper+KeyF"), &path).is_err());
+        assert!(bind_input(&mut input_map, &args("bind Jump KeyF extra"), &path).is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn unbind_removes_one_binding_and_saves() {
+        let path = temp_path("unbind");
+        let mut input_map = InputMap::default();
+        unbind_input(&mut input_map, &args("unbind Look Mouse:Left"), &path).unwrap();
+        assert!(input_map.bindings(Action::Look).is_empty());
+        assert_eq!(bind_input(&mut input_map, &args("bind Look"), &path).unwrap(), "Look is unbound");
+        unbind_input(&mut input_map, &args("unbind Jump Gamepad:South"), &path).unwrap();
+        assert_eq!(input_map.bindings(Action::Jump), [Binding::key(KeyCode::Space)]);
+        assert_eq!(InputMap::load(&path).unwrap(), input_map);
+        std::fs::remove_file(&path).unwrap();
+
+        let error = unbind_input(&mut input_map, &args("unbind Jump KeyQ"), &path).unwrap_err();
+        assert_eq!(error.to_string(), "Jump isn't bound to KeyQ");
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn set_and_reset_complete_cvar_names() {
+        assert_eq!(complete("set sen"), ("set sensitivity ".to_string(), Vec::new()));
+        assert_eq!(complete("reset clear_c"), ("reset clear_color ".to_string(), Vec::new()));
+        assert_eq!(complete("set s"), ("set s".to_string(), vec!["sensitivity".to_string(), "speed".to_string()]));
+        // Only the first argument is a cvar.
+        assert_eq!(complete("set speed s"), ("set speed s".to_string(), Vec::new()));
+    }
+
+    #[test]
+    fn first_word_completes_commands_and_cvars() {
+        assert_eq!(complete("vs"), ("vsync ".to_string(), Vec::new()));
+        assert_eq!(complete("wire"), ("wireframe ".to_string(), Vec::new()));
+        // Commands and cvars mixed, in name order.
+        let (line, candidates) = complete("s");
+        assert_eq!(line, "s");
+        assert_eq!(candidates, ["say", "sensitivity", "set", "speed"]);
+    }
+
+    #[test]
+    fn other_arguments_fall_through_to_the_registry() {
+        assert_eq!(complete("wireframe o"), ("wireframe o".to_string(), vec!["on".to_string(), "off".to_string()]));
+        assert_eq!(complete("help res"), ("help reset ".to_string(), Vec::new()));
+    }
+}
@@ -1,43 +1,562 @@
-use glyphon::{Attrs, Buffer, Family, FontSystem, Shaping};
-use log::info;
-use winit::event::WindowEvent;
-
-pub fn process_events(event: WindowEvent, text_buffer: &mut Buffer, font_system: &mut FontSystem, chat_text: &mut String, new_text: &str) {
-    
-}
-
-pub fn write_to_console(text_buffer: &mut Buffer, font_system: &mut FontSystem, chat_text: &mut String, new_text: &str) {
-    info!("Writing to console: {}", new_text);
-    *chat_text = format!("{}\n{}", new_text, chat_text);
-    text_buffer.set_text(
-        font_system,
-        chat_text,
-        Attrs::new().family(Family::SansSerif),
-        Shaping::Advanced,
-    );
-    text_buffer.shape_until_scroll(font_system, false);
-}
-
-pub fn handle_user_input(text_buffer: &mut Buffer, font_system: &mut FontSystem, chat_text: &mut String, new_line: &mut String, new_char: char) {
-    info!("Handling user input: {}", new_char);
-    new_line.push(new_char);
-    text_buffer.set_text(
-        font_system,
-        &format!("{}\n{}", new_line, chat_text),
-        Attrs::new().family(Family::SansSerif),
-        Shaping::Advanced,
-    );
-}
-
-pub fn enter_new_line(text_buffer: &mut Buffer, font_system: &mut FontSystem, chat_text: &mut String, new_line: &mut String) {
-    info!("Entering new line: {}", new_line);
-    *chat_text = format!("{}\n{}", new_line, chat_text);
-    text_buffer.set_text(
-        font_system,
-        chat_text,
-        Attrs::new().family(Family::SansSerif),
-        Shaping::Advanced,
-    );
-    text_buffer.shape_until_scroll(font_system, false);
-    new_line.clear();
-}
\ No newline at end of file
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::Mutex;
+
+use cosmic_text::{BufferLine, LineEnding, Scroll};
+use glyphon::{Attrs, Buffer, Color, Family, FontSystem, Metrics, Shaping, TextArea, TextBounds};
+use log::{warn, Level, LevelFilter, Log, Metadata, Record};
+use winit::event::{ElementState, KeyEvent};
+use winit::keyboard::{Key, NamedKey};
+
+use crate::markup;
+
+// Output lines kept on screen when the console is open.
+const VISIBLE_LINES: usize = 12;
+const FONT_SIZE: f32 = 20.0;
+const LINE_HEIGHT: f32 = 26.0;
+const PROMPT: &str = "> ";
+// Submitted lines are saved here so history survives restarts.
+pub const HISTORY_FILE: &str = "console_history.txt";
+const MAX_HISTORY: usize = 200;
+// Oldest output lines are dropped past this.
+pub const MAX_OUTPUT_LINES: usize = 1000;
+
+// Supplies tab completions; CommandRegistry is the real one.
+pub trait Completer {
+    // Full replacements for the last word of `line`.
+    fn complete(&self, line: &str) -> Vec<String>;
+}
+
+// Previously submitted lines, browsed with Up/Down like a shell.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct History {
+    entries: Vec<String>,
+    // Index being shown while browsing; None when editing a fresh line.
+    position: Option<usize>,
+    // The line that was being typed before browsing started.
+    draft: String,
+}
+
+impl History {
+    pub fn new(entries: Vec<String>) -> Self {
+        Self { entries, position: None, draft: String::new() }
+    }
+
+    // One entry per line. A missing file is just an empty history.
+    pub fn load<P: AsRef<Path>>(path: P) -> Self {
+        match std::fs::read_to_string(path.as_ref()) {
+            Ok(text) => Self::new(text.lines().filter(|l| !l.is_empty()).map(str::to_string).collect()),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        let mut text = self.entries.join("\n");
+        text.push('\n');
+        std::fs::write(path, text)
+    }
+
+    #[cfg(test)]
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    // Repeats of the previous line are only kept once.
+    pub fn push(&mut self, line: &str) {
+        self.position = None;
+        if self.entries.last().map(String::as_str) != Some(line) {
+            self.entries.push(line.to_string());
+        }
+        if self.entries.len() > MAX_HISTORY {
+            self.entries.drain(..self.entries.len() - MAX_HISTORY);
+        }
+    }
+
+    // Older entry, or None at the oldest. `current` is remembered when browsing starts.
+    pub fn previous(&mut self, current: &str) -> Option<&str> {
+        let index = match self.position {
+            None if self.entries.is_empty() => return None,
+            None => {
+                self.draft = current.to_string();
+                self.entries.len() - 1
+            }
+            Some(0) => return None,
+            Some(i) => i - 1,
+        };
+        self.position = Some(index);
+        Some(&self.entries[index])
+    }
+
+    // Newer entry; past the newest returns the draft and stops browsing.
+    pub fn next(&mut self) -> Option<&str> {
+        let index = self.position?;
+        if index + 1 < self.entries.len() {
+            self.position = Some(index + 1);
+            Some(&self.entries[index + 1])
+        } else {
+            self.position = None;
+            Some(&self.draft)
+        }
+    }
+}
+
+// What Tab does to `line`: the new line, and candidates to list if it was ambiguous.
+pub fn complete_line(line: &str, completer: &dyn Completer) -> (String, Vec<String>) {
+    let word_start = line.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+    let word = &line[word_start..];
+    let candidates = completer
+        .complete(line)
+        .into_iter()
+        .filter(|c| c.starts_with(word))
+        .collect::<Vec<_>>();
+    match candidates.as_slice() {
+        [] => (line.to_string(), Vec::new()),
+        [only] => (format!("{}{} ", &line[..word_start], only), Vec::new()),
+        _ => {
+            let prefix = common_prefix(&candidates);
+            let listed = if prefix.len() > word.len() { Vec::new() } else { candidates.clone() };
+            (format!("{}{}", &line[..word_start], prefix), listed)
+        }
+    }
+}
+
+fn common_prefix(words: &[String]) -> String {
+    let mut prefix = words[0].as_str();
+    for word in &words[1..] {
+        let len = prefix
+            .char_indices()
+            .zip(word.chars())
+            .take_while(|((_, a), b)| a == b)
+            .last()
+            .map_or(0, |((i, a), _)| i + a.len_utf8());
+        prefix = &prefix[..len];
+    }
+    prefix.to_string()
+}
+
+// Drop-down developer console: an output log and one editable input line.
+// Commands themselves live in commands.rs; this only handles text.
+pub struct Console {
+    pub open: bool,
+    input: String,
+    // Byte offset into `input`, always on a char boundary.
+    cursor: usize,
+    // Ring buffer of output lines, oldest first. Mirrors output_buffer.lines.
+    output: VecDeque<String>,
+    pub history: History,
+    // Lines scrolled back from the newest output.
+    scroll_back: usize,
+    // The log is appended to line by line and scrolled, never reshaped as a whole;
+    // only the small input buffer is rebuilt while typing.
+    output_buffer: Buffer,
+    input_buffer: Buffer,
+    input_dirty: bool,
+}
+
+impl Console {
+    pub fn new(font_system: &mut FontSystem, width: f32) -> Self {
+        let metrics = Metrics::new(FONT_SIZE, LINE_HEIGHT);
+        let mut output_buffer = Buffer::new(font_system, metrics);
+        output_buffer.set_size(font_system, Some(width), Some(VISIBLE_LINES as f32 * LINE_HEIGHT));
+        output_buffer.lines.clear();
+        let mut input_buffer = Buffer::new(font_system, metrics);
+        input_buffer.set_size(font_system, Some(width), Some(LINE_HEIGHT));
+        Self {
+            open: false,
+            input: String::new(),
+            cursor: 0,
+            output: VecDeque::new(),
+            history: History::load(HISTORY_FILE),
+            scroll_back: 0,
+            output_buffer,
+            input_buffer,
+            input_dirty: true,
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        self.open = !self.open;
+        self.input_dirty = true;
+    }
+
+    pub fn resize(&mut self, font_system: &mut FontSystem, width: f32) {
+        self.output_buffer.set_size(font_system, Some(width), Some(VISIBLE_LINES as f32 * LINE_HEIGHT));
+        self.input_buffer.set_size(font_system, Some(width), Some(LINE_HEIGHT));
+    }
+
+    pub fn write_to_console(&mut self, text: &str) {
+        self.write_colored(text, Color::rgb(255, 255, 255));
+    }
+
+    pub fn write_colored(&mut self, text: &str, color: Color) {
+        let attrs = Attrs::new().family(Family::Monospace).color(color);
+        // `color` is the default; markup in the text can override it per span.
+        // Text from elsewhere should go through markup::escape first.
+        for line in markup::parse_lines(text) {
+            self.output_buffer.lines.push(BufferLine::new(
+                &line.text,
+                LineEnding::default(),
+                markup::attrs_list(&line, attrs),
+                Shaping::Advanced,
+            ));
+            self.output.push_back(line.text);
+        }
+        let overflow = self.output.len().saturating_sub(MAX_OUTPUT_LINES);
+        if overflow > 0 {
+            self.output.drain(..overflow);
+            self.output_buffer.lines.drain(..overflow);
+        }
+        self.scroll_back = self.scroll_back.min(self.output.len().saturating_sub(VISIBLE_LINES));
+        self.update_scroll();
+    }
+
+    // Moves log records captured by ConsoleLogger into the output.
+    pub fn drain_log(&mut self) {
+        let records = match PENDING_LOG.lock() {
+            Ok(mut pending) => std::mem::take(&mut *pending),
+            Err(_) => return,
+        };
+        for (level, text) in records {
+            self.write_colored(&format!("[{}] {}", level, markup::escape(&text)), level_color(level));
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.output.clear();
+        self.output_buffer.lines.clear();
+        self.scroll_back = 0;
+        self.update_scroll();
+    }
+
+    pub fn set_input(&mut self, input: &str) {
+        self.input = input.to_string();
+        self.cursor = self.input.len();
+        self.input_dirty = true;
+    }
+
+    // Editing keys. Returns the line when Enter submits it.
+    pub fn process_key(&mut self, event: &KeyEvent, completer: &dyn Completer) -> Option<String> {
+        if event.state != ElementState::Pressed {
+            return None;
+        }
+        match &event.logical_key {
+            Key::Named(NamedKey::Enter) => return self.enter_new_line(),
+            Key::Named(NamedKey::Backspace) => self.backspace(),
+            Key::Named(NamedKey::Delete) => self.delete(),
+            Key::Named(NamedKey::ArrowLeft) => self.cursor = prev_boundary(&self.input, self.cursor),
+            Key::Named(NamedKey::ArrowRight) => self.cursor = next_boundary(&self.input, self.cursor),
+            Key::Named(NamedKey::Home) => self.cursor = 0,
+            Key::Named(NamedKey::End) => self.cursor = self.input.len(),
+            Key::Named(NamedKey::ArrowUp) => {
+                if let Some(line) = self.history.previous(&self.input).map(str::to_string) {
+                    self.set_input(&line);
+                }
+            }
+            Key::Named(NamedKey::ArrowDown) => {
+                if let Some(line) = self.history.next().map(str::to_string) {
+                    self.set_input(&line);
+                }
+            }
+            Key::Named(NamedKey::Tab) => self.complete(completer),
+            Key::Named(NamedKey::PageUp) => self.scroll(VISIBLE_LINES as isize - 1),
+            Key::Named(NamedKey::PageDown) => self.scroll(-(VISIBLE_LINES as isize - 1)),
+            _ => {
+                if let Some(text) = &event.text {
+                    for c in text.chars().filter(|c| !c.is_control()) {
+                        self.handle_user_input(c);
+                    }
+                }
+            }
+        }
+        self.input_dirty = true;
+        None
+    }
+
+    pub fn handle_user_input(&mut self, c: char) {
+        self.input.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+        self.input_dirty = true;
+    }
+
+    fn backspace(&mut self) {
+        if self.cursor > 0 {
+            let start = prev_boundary(&self.input, self.cursor);
+            self.input.replace_range(start..self.cursor, "");
+            self.cursor = start;
+        }
+    }
+
+    fn delete(&mut self) {
+        if self.cursor < self.input.len() {
+            let end = next_boundary(&self.input, self.cursor);
+            self.input.replace_range(self.cursor..end, "");
+        }
+    }
+
+    // Completes the word before the cursor; text after the cursor is kept.
+    fn complete(&mut self, completer: &dyn Completer) {
+        let (completed, candidates) = complete_line(&self.input[..self.cursor], completer);
+        if !candidates.is_empty() {
+            self.write_to_console(&candidates.join("  "));
+        }
+        self.input.replace_range(..self.cursor, &completed);
+        self.cursor = completed.len();
+    }
+
+    // Takes the input line, leaving an empty prompt. Blank lines are dropped.
+    pub fn enter_new_line(&mut self) -> Option<String> {
+        let line = std::mem::take(&mut self.input);
+        self.cursor = 0;
+        self.input_dirty = true;
+        self.scroll_back = 0;
+        self.update_scroll();
+        let line = line.trim();
+        if line.is_empty() {
+            return None;
+        }
+        self.history.push(line);
+        if let Err(e) = self.history.save(HISTORY_FILE) {
+            warn!("Failed to save console history: {}", e);
+        }
+        Some(line.to_string())
+    }
+
+    // Positive scrolls back towards older output.
+    pub fn scroll(&mut self, lines: isize) {
+        let max = self.output.len().saturating_sub(VISIBLE_LINES);
+        self.scroll_back = self.scroll_back.saturating_add_signed(lines).min(max);
+        self.update_scroll();
+    }
+
+    fn update_scroll(&mut self) {
+        let line = self.output.len().saturating_sub(VISIBLE_LINES + self.scroll_back);
+        self.output_buffer.set_scroll(Scroll::new(line, 0.0, 0.0));
+        self.output_buffer.set_redraw(true);
+    }
+
+    // Shapes whatever changed since the last frame.
+    pub fn prepare(&mut self, font_system: &mut FontSystem) {
+        self.output_buffer.shape_until_scroll(font_system, false);
+        if !self.input_dirty {
+            return;
+        }
+        self.input_dirty = false;
+        let text = format!("{}{}|{}", PROMPT, &self.input[..self.cursor], &self.input[self.cursor..]);
+        self.input_buffer.set_text(font_system, &text, Attrs::new().family(Family::Monospace), Shaping::Advanced);
+        self.input_buffer.shape_until_scroll(font_system, false);
+    }
+
+    pub fn text_areas(&self, left: f32, top: f32, width: u32) -> [TextArea<'_>; 2] {
+        let output_height = VISIBLE_LINES as f32 * LINE_HEIGHT;
+        let input_top = top + output_height;
+        [
+            TextArea {
+                buffer: &self.output_buffer,
+                left,
+                top,
+                scale: 1.0,
+                bounds: TextBounds {
+                    left: 0,
+                    top: top as i32,
+                    right: width as i32,
+                    bottom: input_top as i32,
+                },
+                default_color: Color::rgb(255, 255, 255),
+                custom_glyphs: &[],
+            },
+            TextArea {
+                buffer: &self.input_buffer,
+                left,
+                top: input_top,
+                scale: 1.0,
+                bounds: TextBounds {
+                    left: 0,
+                    top: input_top as i32,
+                    right: width as i32,
+                    bottom: (input_top + LINE_HEIGHT) as i32,
+                },
+                default_color: Color::rgb(255, 255, 0),
+                custom_glyphs: &[],
+            },
+        ]
+    }
+}
+
+fn prev_boundary(text: &str, index: usize) -> usize {
+    text[..index].char_indices().next_back().map_or(0, |(i, _)| i)
+}
+
+fn next_boundary(text: &str, index: usize) -> usize {
+    text[index..].chars().next().map_or(index, |c| index + c.len_utf8())
+}
+
+// Records waiting for the console; the logger can be called from any thread,
+// the console only lives on the main one.
+static PENDING_LOG: Mutex<VecDeque<(Level, String)>> = Mutex::new(VecDeque::new());
+// Caps PENDING_LOG too, in case the console never drains it.
+const MAX_PENDING_LOG: usize = MAX_OUTPUT_LINES;
+
+pub fn level_color(level: Level) -> Color {
+    match level {
+        Level::Error => Color::rgb(255, 80, 80),
+        Level::Warn => Color::rgb(255, 200, 60),
+        Level::Info => Color::rgb(255, 255, 255),
+        Level::Debug | Level::Trace => Color::rgb(150, 150, 150),
+    }
+}
+
+// Wraps env_logger so terminal output is unchanged, and also copies records
+// into the console: warnings and errors from anywhere, info from this crate.
+pub struct ConsoleLogger {
+    env: env_logger::Logger,
+}
+
+impl ConsoleLogger {
+    // Replaces env_logger::init().
+    pub fn init() -> Result<(), log::SetLoggerError> {
+        let env = env_logger::Builder::from_env(env_logger::Env::default()).build();
+        let max_level = env.filter().max(LevelFilter::Info);
+        log::set_boxed_logger(Box::new(ConsoleLogger { env }))?;
+        log::set_max_level(max_level);
+        Ok(())
+    }
+
+    fn forwards(metadata: &Metadata) -> bool {
+        match metadata.level() {
+            Level::Error | Level::Warn => true,
+            Level::Info => metadata.target().starts_with(env!("CARGO_CRATE_NAME")),
+            Level::Debug | Level::Trace => false,
+        }
+    }
+}
+
+impl Log for ConsoleLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.env.enabled(metadata) || Self::forwards(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.env.matches(record) {
+            self.env.log(record);
+        }
+        if Self::forwards(record.metadata()) {
+            if let Ok(mut pending) = PENDING_LOG.lock() {
+                if pending.len() >= MAX_PENDING_LOG {
+                    pending.pop_front();
+                }
+                pending.push_back((record.level(), record.args().to_string()));
+            }
+        }
+    }
+
+    fn flush(&self) {
+        self.env.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{Args, Command, CommandRegistry};
+    use crate::window_state::WindowState;
+
+    fn history(entries: &[&str]) -> History {
+        History::new(entries.iter().map(|e| e.to_string()).collect())
+    }
+
+    fn noop(_state: &mut WindowState<'_>, _args: &Args) -> anyhow::Result<Option<String>> {
+        Ok(None)
+    }
+
+    const LIGHT_ARGS: &[&[&str]] = &[&["color", "colour"]];
+
+    fn registry() -> CommandRegistry {
+        let mut registry = CommandRegistry::new();
+        for (name, arg_completions) in [
+            ("help", &[][..]),
+            ("clear", &[]),
+            ("connect", &[]),
+            ("connections", &[]),
+            ("light", LIGHT_ARGS),
+        ] {
+            registry.register(Command { name, usage: name, help: "", arg_completions, handler: noop });
+        }
+        registry
+    }
+
+    fn complete(line: &str) -> (String, Vec<String>) {
+        complete_line(line, &registry())
+    }
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn history_browses_and_restores_the_draft() {
+        let mut history = history(&["first", "second"]);
+        assert_eq!(history.next(), None);
+        assert_eq!(history.previous("half typed"), Some("second"));
+        assert_eq!(history.previous("ignored"), Some("first"));
+        assert_eq!(history.previous("ignored"), None);
+        assert_eq!(history.next(), Some("second"));
+        assert_eq!(history.next(), Some("half typed"));
+        // Back to editing; Down does nothing more.
+        assert_eq!(history.next(), None);
+    }
+
+    #[test]
+    fn empty_history_has_nothing_to_browse() {
+        let mut history = History::default();
+        assert_eq!(history.previous("draft"), None);
+        assert_eq!(history.next(), None);
+    }
+
+    #[test]
+    fn consecutive_duplicates_are_stored_once() {
+        let mut history = History::default();
+        for line in ["a", "a", "b", "a", "a"] {
+            history.push(line);
+        }
+        assert_eq!(history.entries(), strings(&["a", "b", "a"]).as_slice());
+    }
+
+    #[test]
+    fn push_stops_browsing_and_caps_the_length() {
+        let mut history = history(&["a", "b"]);
+        history.previous("");
+        history.push("c");
+        assert_eq!(history.previous(""), Some("c"));
+
+        for i in 0..MAX_HISTORY + 10 {
+            history.push(&i.to_string());
+        }
+        assert_eq!(history.entries().len(), MAX_HISTORY);
+        assert_eq!(history.entries().last().map(String::as_str), Some((MAX_HISTORY + 9).to_string().as_str()));
+    }
+
+    #[test]
+    fn unique_prefix_completes_the_word() {
+        assert_eq!(complete("li"), ("light ".to_string(), Vec::new()));
+        assert_eq!(complete("help cl"), ("help clear ".to_string(), Vec::new()));
+        assert_eq!(complete("nothing"), ("nothing".to_string(), Vec::new()));
+    }
+
+    #[test]
+    fn ambiguous_prefix_extends_then_lists() {
+        // Extended as far as the candidates agree, without listing them.
+        assert_eq!(complete("co"), ("connect".to_string(), Vec::new()));
+        // Nothing more in common: list them instead.
+        assert_eq!(complete("connect"), ("connect".to_string(), strings(&["connect", "connections"])));
+        assert_eq!(complete("c"), ("c".to_string(), strings(&["clear", "connect", "connections"])));
+    }
+
+    #[test]
+    fn arguments_complete_from_arg_completions() {
+        assert_eq!(complete("light c"), ("light colo".to_string(), Vec::new()));
+        assert_eq!(complete("light colo"), ("light colo".to_string(), strings(&["color", "colour"])));
+        assert_eq!(complete("light colou"), ("light colour ".to_string(), Vec::new()));
+        // Only the first argument has completions.
+        assert_eq!(complete("light color c"), ("light color c".to_string(), Vec::new()));
+    }
+}
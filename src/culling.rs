@@ -0,0 +1,258 @@
+use cgmath::{EuclideanSpace, InnerSpace, MetricSpace, Point3, Quaternion, Vector3, Vector4};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    pub fn new(min: Point3<f32>, max: Point3<f32>) -> Self {
+        Self { min, max }
+    }
+
+    // Returns a zero-sized box at the origin if there are no points.
+    pub fn from_points(points: impl IntoIterator<Item = Point3<f32>>) -> Self {
+        let mut points = points.into_iter();
+        let Some(first) = points.next() else {
+            return Self::new(Point3::origin(), Point3::origin());
+        };
+        points.fold(Self::new(first, first), |aabb, p| Self {
+            min: Point3::new(aabb.min.x.min(p.x), aabb.min.y.min(p.y), aabb.min.z.min(p.z)),
+            max: Point3::new(aabb.max.x.max(p.x), aabb.max.y.max(p.y), aabb.max.z.max(p.z)),
+        })
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        self.min.midpoint(self.max)
+    }
+
+    pub fn half_extents(&self) -> Vector3<f32> {
+        (self.max - self.min) * 0.5
+    }
+
+    // Sphere around the box. Looser than a fitted sphere but cheap and always contains it.
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        BoundingSphere {
+            center: self.center(),
+            radius: self.half_extents().magnitude(),
+        }
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb::from_points([self.min, self.max, other.min, other.max])
+    }
+
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min.x <= other.max.x && self.max.x >= other.min.x
+            && self.min.y <= other.max.y && self.max.y >= other.min.y
+            && self.min.z <= other.max.z && self.max.z >= other.min.z
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Point3<f32>,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    // Moves the sphere into world space for an instance (rotation + translation, no scale).
+    pub fn transformed(&self, position: Vector3<f32>, rotation: Quaternion<f32>) -> BoundingSphere {
+        BoundingSphere {
+            center: Point3::from_vec(rotation * self.center.to_vec() + position),
+            radius: self.radius,
+        }
+    }
+
+    pub fn union(&self, other: &BoundingSphere) -> BoundingSphere {
+        let distance = self.center.distance(other.center);
+        if distance + other.radius <= self.radius {
+            return *self;
+        }
+        if distance + self.radius <= other.radius {
+            return *other;
+        }
+        let radius = (distance + self.radius + other.radius) * 0.5;
+        let direction = (other.center - self.center) / distance;
+        BoundingSphere {
+            center: self.center + direction * (radius - self.radius),
+            radius,
+        }
+    }
+}
+
+// Plane as normal . p + d = 0, with the normal pointing into the frustum.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Plane {
+    pub normal: Vector3<f32>,
+    pub d: f32,
+}
+
+impl Plane {
+    fn from_row(row: Vector4<f32>) -> Option<Plane> {
+        let normal = row.truncate();
+        let length = normal.magnitude();
+        // The far plane of an infinite projection has no normal; it can't cull anything.
+        if length < 1e-6 {
+            return None;
+        }
+        Some(Plane {
+            normal: normal / length,
+            d: row.w / length,
+        })
+    }
+
+    pub fn signed_distance(&self, point: Point3<f32>) -> f32 {
+        self.normal.dot(point.to_vec()) + self.d
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frustum {
+    pub planes: Vec<Plane>,
+}
+
+impl Frustum {
+    // Gribb/Hartmann plane extraction for wgpu clip space (depth 0..1). Works for
+    // standard and reverse-Z projections because both keep clip z in [0, w].
+    pub fn from_view_proj(view_proj: &[[f32; 4]; 4]) -> Self {
+        let row = |i: usize| Vector4::new(view_proj[0][i], view_proj[1][i], view_proj[2][i], view_proj[3][i]);
+        let (r0, r1, r2, r3) = (row(0), row(1), row(2), row(3));
+        let planes = [r3 + r0, r3 - r0, r3 + r1, r3 - r1, r2, r3 - r2]
+            .into_iter()
+            .filter_map(Plane::from_row)
+            .collect();
+        Self { planes }
+    }
+
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.signed_distance(sphere.center) >= -sphere.radius)
+    }
+
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // Corner furthest along the plane normal
+            let positive = Point3::new(
+                if plane.normal.x >= 0.0 { aabb.max.x } else { aabb.min.x },
+                if plane.normal.y >= 0.0 { aabb.max.y } else { aabb.min.y },
+                if plane.normal.z >= 0.0 { aabb.max.z } else { aabb.min.z },
+            );
+            plane.signed_distance(positive) >= 0.0
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CullStats {
+    pub instances_total: u32,
+    pub instances_visible: u32,
+    pub meshes_total: u32,
+    pub meshes_visible: u32,
+    pub chunks_total: u32,
+    pub chunks_visible: u32,
+}
+
+// Indices of the items whose bounds touch the frustum.
+pub fn cull_aabbs<'a>(frustum: &Frustum, bounds: impl IntoIterator<Item = &'a Aabb>) -> Vec<usize> {
+    bounds
+        .into_iter()
+        .enumerate()
+        .filter(|(_, aabb)| frustum.intersects_aabb(aabb))
+        .map(|(i, _)| i)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Deg, Matrix4};
+
+    use super::*;
+    use crate::camera::Projection;
+
+    fn frustum(projection: &Projection) -> Frustum {
+        // Camera at the origin looking down -z.
+        let view = Matrix4::look_to_rh(Point3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, -1.0), Vector3::unit_y());
+        Frustum::from_view_proj(&(projection.calc_matrix() * view).into())
+    }
+
+    fn cube(center: [f32; 3], half: f32) -> Aabb {
+        let center = Point3::from(center);
+        let half = Vector3::new(half, half, half);
+        Aabb::new(center - half, center + half)
+    }
+
+    fn sphere(center: [f32; 3], radius: f32) -> BoundingSphere {
+        BoundingSphere { center: Point3::from(center), radius }
+    }
+
+    #[test]
+    fn planes_from_perspective() {
+        let frustum = frustum(&Projection::new(100, 100, Deg(90.0), 1.0, 100.0));
+        assert_eq!(frustum.planes.len(), 6);
+        // Left, right, bottom, top: 45 degrees off the view axis, through the eye.
+        let s = std::f32::consts::FRAC_1_SQRT_2;
+        let expected = [
+            Vector3::new(s, 0.0, -s),
+            Vector3::new(-s, 0.0, -s),
+            Vector3::new(0.0, s, -s),
+            Vector3::new(0.0, -s, -s),
+            Vector3::new(0.0, 0.0, -1.0),
+            Vector3::new(0.0, 0.0, 1.0),
+        ];
+        for (plane, normal) in frustum.planes.iter().zip(expected) {
+            assert!((plane.normal - normal).magnitude() < 1e-4, "{:?} != {:?}", plane.normal, normal);
+        }
+        for plane in &frustum.planes[..4] {
+            assert!(plane.d.abs() < 1e-4);
+        }
+        assert!((frustum.planes[4].signed_distance(Point3::new(0.0, 0.0, -1.0))).abs() < 1e-4);
+        assert!((frustum.planes[5].signed_distance(Point3::new(0.0, 0.0, -100.0))).abs() < 1e-3);
+    }
+
+    #[test]
+    fn boxes_inside_outside_and_straddling() {
+        let frustum = frustum(&Projection::new(100, 100, Deg(90.0), 1.0, 100.0));
+        assert!(frustum.intersects_aabb(&cube([0.0, 0.0, -10.0], 1.0)));
+        // Behind the camera, past the far plane, and well off to the side.
+        assert!(!frustum.intersects_aabb(&cube([0.0, 0.0, 10.0], 1.0)));
+        assert!(!frustum.intersects_aabb(&cube([0.0, 0.0, -200.0], 1.0)));
+        assert!(!frustum.intersects_aabb(&cube([50.0, 0.0, -10.0], 1.0)));
+        // Across the right plane, the near plane and the far plane.
+        assert!(frustum.intersects_aabb(&cube([10.0, 0.0, -10.0], 1.0)));
+        assert!(frustum.intersects_aabb(&cube([0.0, 0.0, -1.0], 0.5)));
+        assert!(frustum.intersects_aabb(&cube([0.0, 0.0, -100.0], 1.0)));
+
+        assert!(frustum.intersects_sphere(&sphere([0.0, 0.0, -10.0], 1.0)));
+        assert!(frustum.intersects_sphere(&sphere([10.5, 0.0, -10.0], 1.0)));
+        assert!(!frustum.intersects_sphere(&sphere([0.0, 0.0, 10.0], 1.0)));
+
+        let boxes = [cube([0.0, 0.0, -10.0], 1.0), cube([0.0, 0.0, 10.0], 1.0), cube([0.0, 5.0, -10.0], 1.0)];
+        assert_eq!(cull_aabbs(&frustum, &boxes), vec![0, 2]);
+    }
+
+    #[test]
+    fn infinite_reverse_z_has_no_far_plane() {
+        let mut projection = Projection::new(100, 100, Deg(90.0), 1.0, 100.0);
+        projection.set_reverse_z(true);
+        let frustum = frustum(&projection);
+        assert_eq!(frustum.planes.len(), 5);
+        // Far beyond the old far plane, but still in view.
+        assert!(frustum.intersects_aabb(&cube([0.0, 0.0, -1.0e5], 1.0)));
+        assert!(!frustum.intersects_aabb(&cube([0.0, 0.0, 10.0], 1.0)));
+        assert!(!frustum.intersects_aabb(&cube([0.0, 0.0, -0.5], 0.25)));
+    }
+
+    #[test]
+    fn reverse_z_orthographic_keeps_all_planes() {
+        let mut projection = Projection::orthographic(100, 100, 10.0, 1.0, 100.0);
+        projection.set_reverse_z(true);
+        let frustum = frustum(&projection);
+        assert_eq!(frustum.planes.len(), 6);
+        assert!(frustum.intersects_aabb(&cube([4.0, 4.0, -50.0], 0.5)));
+        assert!(!frustum.intersects_aabb(&cube([0.0, 0.0, -200.0], 1.0)));
+        assert!(!frustum.intersects_aabb(&cube([6.0, 0.0, -50.0], 0.5)));
+    }
+}
@@ -0,0 +1,357 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::rendering::DepthMode;
+use crate::window_state::WindowState;
+
+// Changed values are written here and read back at startup.
+pub const CVAR_FILE: &str = "cvars.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CvarValue {
+    Bool(bool),
+    Float(f32),
+    // RGB, components 0..1.
+    Color([f32; 3]),
+}
+
+impl CvarValue {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            CvarValue::Bool(_) => "bool",
+            CvarValue::Float(_) => "float",
+            CvarValue::Color(_) => "color",
+        }
+    }
+
+    pub fn as_float(&self) -> f32 {
+        match self {
+            CvarValue::Float(value) => *value,
+            _ => 0.0,
+        }
+    }
+
+    pub fn as_bool(&self) -> bool {
+        matches!(self, CvarValue::Bool(true))
+    }
+
+    pub fn as_color(&self) -> [f32; 3] {
+        match self {
+            CvarValue::Color(color) => *color,
+            _ => [0.0; 3],
+        }
+    }
+
+    fn same_type(&self, other: &CvarValue) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+
+    // Parses console arguments as the same type as `self`.
+    pub fn parse_like(&self, args: &[String]) -> anyhow::Result<CvarValue> {
+        let parse_float = |s: &str| s.parse::<f32>().map_err(|_| anyhow!("'{}' is not a number", s));
+        match (self, args) {
+            (CvarValue::Bool(_), [arg]) => Ok(CvarValue::Bool(crate::commands::parse_on_off(arg)?)),
+            (CvarValue::Float(_), [arg]) => Ok(CvarValue::Float(parse_float(arg)?)),
+            (CvarValue::Color(_), [r, g, b]) => Ok(CvarValue::Color([parse_float(r)?, parse_float(g)?, parse_float(b)?])),
+            (CvarValue::Color(_), _) => bail!("expected 3 values, got {}", args.len()),
+            _ => bail!("expected 1 value, got {}", args.len()),
+        }
+    }
+}
+
+impl fmt::Display for CvarValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CvarValue::Bool(value) => write!(f, "{}", if *value { "on" } else { "off" }),
+            CvarValue::Float(value) => write!(f, "{}", value),
+            CvarValue::Color([r, g, b]) => write!(f, "{} {} {}", r, g, b),
+        }
+    }
+}
+
+// Called after the value changes, to push it into whatever it controls.
+pub type CvarCallback = fn(&mut WindowState<'_>, CvarValue);
+
+#[derive(Clone, Copy)]
+pub struct Cvar {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub default: CvarValue,
+    pub value: CvarValue,
+    // Inclusive bounds for floats and each color component.
+    pub range: Option<(f32, f32)>,
+    pub on_change: Option<CvarCallback>,
+}
+
+impl Cvar {
+    pub fn new(name: &'static str, help: &'static str, default: CvarValue) -> Self {
+        Self { name, help, default, value: default, range: None, on_change: None }
+    }
+
+    pub fn range(mut self, min: f32, max: f32) -> Self {
+        self.range = Some((min, max));
+        self
+    }
+
+    pub fn on_change(mut self, callback: CvarCallback) -> Self {
+        self.on_change = Some(callback);
+        self
+    }
+
+    pub fn validate(&self, value: &CvarValue) -> anyhow::Result<()> {
+        if !self.default.same_type(value) {
+            bail!("{} is a {}, not a {}", self.name, self.default.type_name(), value.type_name());
+        }
+        let Some((min, max)) = self.range else {
+            return Ok(());
+        };
+        let in_range = |v: f32| (min..=max).contains(&v);
+        let ok = match value {
+            CvarValue::Bool(_) => true,
+            CvarValue::Float(v) => in_range(*v),
+            CvarValue::Color(c) => c.iter().all(|v| in_range(*v)),
+        };
+        if !ok {
+            bail!("{} must be between {} and {}", self.name, min, max);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct CvarRegistry {
+    cvars: BTreeMap<&'static str, Cvar>,
+}
+
+impl CvarRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_defaults() -> Self {
+        let mut registry = Self::new();
+        registry.register(
+            Cvar::new("speed", "Camera movement speed, units per second", CvarValue::Float(4.0))
+                .range(0.1, 100.0)
+                .on_change(|state, value| state.camera_controller.set_speed(value.as_float())),
+        );
+        registry.register(
+            Cvar::new("sensitivity", "Mouse look sensitivity", CvarValue::Float(0.4))
+                .range(0.01, 10.0)
+                .on_change(|state, value| state.camera_controller.set_sensitivity(value.as_float())),
+        );
+        registry.register(
+            Cvar::new("fov", "Vertical field of view in degrees", CvarValue::Float(45.0))
+                .range(10.0, 100.0)
+                .on_change(|state, value| state.set_fov(cgmath::Deg(value.as_float()))),
+        );
+        registry.register(
+            Cvar::new("light_color", "Light color, r g b", CvarValue::Color([1.0, 1.0, 1.0]))
+                .range(0.0, 1.0)
+                .on_change(|state, value| state.set_light_color(value.as_color())),
+        );
+        registry.register(
+            Cvar::new("clear_color", "Background color, r g b", CvarValue::Color([0.1, 0.2, 0.3]))
+                .range(0.0, 1.0),
+        );
+        registry.register(
+            Cvar::new("vsync", "Wait for vertical blank when presenting", CvarValue::Bool(true))
+                .on_change(|state, value| state.set_vsync(value.as_bool())),
+        );
+        registry.register(
+            Cvar::new("reverse_z", "Reverse-Z depth with an infinite far plane", CvarValue::Bool(false))
+                .on_change(|state, value| state.set_depth_mode(DepthMode::from_reverse_z(value.as_bool()))),
+        );
+        registry.register(Cvar::new("light_label", "Show a label above the light", CvarValue::Bool(true)));
+        registry.register(Cvar::new(
+            "chunk_labels",
+            "Show chunk coordinates above each visible chunk",
+            CvarValue::Bool(false),
+        ));
+        registry.register(Cvar::new(
+            "perf_overlay",
+            "Show frame timing, draw counts and memory estimates",
+            CvarValue::Bool(false),
+        ));
+        registry
+    }
+
+    pub fn register(&mut self, cvar: Cvar) {
+        self.cvars.insert(cvar.name, cvar);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Cvar> {
+        self.cvars.get(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Cvar> {
+        self.cvars.values()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.cvars.keys().copied()
+    }
+
+    // Defaults if the cvar doesn't exist, so typos in code show up as wrong values
+    // rather than panics.
+    pub fn float(&self, name: &str) -> f32 {
+        self.get(name).map_or(0.0, |cvar| cvar.value.as_float())
+    }
+
+    pub fn bool(&self, name: &str) -> bool {
+        self.get(name).is_some_and(|cvar| cvar.value.as_bool())
+    }
+
+    pub fn color(&self, name: &str) -> [f32; 3] {
+        self.get(name).map_or([0.0; 3], |cvar| cvar.value.as_color())
+    }
+
+    // Stores the value without running the callback; WindowState::set_cvar does both.
+    pub fn set(&mut self, name: &str, value: CvarValue) -> anyhow::Result<Option<CvarCallback>> {
+        let cvar = self
+            .cvars
+            .get_mut(name)
+            .ok_or_else(|| anyhow!("unknown cvar '{}'", name))?;
+        cvar.validate(&value)?;
+        cvar.value = value;
+        Ok(cvar.on_change)
+    }
+
+    // Applies saved values; unknown names and invalid values are skipped with a warning.
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(());
+        }
+        let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read cvars {:?}", path))?;
+        let values: HashMap<String, CvarValue> =
+            serde_json::from_str(&text).with_context(|| format!("Failed to parse cvars {:?}", path))?;
+        for (name, value) in values {
+            if let Err(e) = self.set(&name, value) {
+                warn!("Ignoring saved cvar: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    // Only values that differ from their default are written.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let values = self
+            .cvars
+            .values()
+            .filter(|cvar| cvar.value != cvar.default)
+            .map(|cvar| (cvar.name, cvar.value))
+            .collect::<BTreeMap<_, _>>();
+        let text = serde_json::to_string_pretty(&values)?;
+        std::fs::write(path, text).with_context(|| format!("Failed to write cvars {:?}", path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fresh() -> CvarRegistry {
+        let mut registry = CvarRegistry::new();
+        registry.register(Cvar::new("speed", "", CvarValue::Float(4.0)).range(0.1, 100.0));
+        registry.register(Cvar::new("tint", "", CvarValue::Color([1.0, 1.0, 1.0])).range(0.0, 1.0));
+        registry.register(Cvar::new("vsync", "", CvarValue::Bool(true)));
+        registry
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn error(result: anyhow::Result<impl fmt::Debug>) -> String {
+        result.unwrap_err().to_string()
+    }
+
+    #[test]
+    fn validate_checks_type_and_range() {
+        let registry = fresh();
+        let speed = registry.get("speed").unwrap();
+        assert!(speed.validate(&CvarValue::Float(0.1)).is_ok());
+        assert!(speed.validate(&CvarValue::Float(100.0)).is_ok());
+        assert_eq!(error(speed.validate(&CvarValue::Float(0.0))), "speed must be between 0.1 and 100");
+        assert_eq!(error(speed.validate(&CvarValue::Float(101.0))), "speed must be between 0.1 and 100");
+        assert_eq!(error(speed.validate(&CvarValue::Bool(true))), "speed is a float, not a bool");
+
+        // Every color component is checked.
+        let tint = registry.get("tint").unwrap();
+        assert!(tint.validate(&CvarValue::Color([0.0, 0.5, 1.0])).is_ok());
+        assert!(tint.validate(&CvarValue::Color([0.0, 1.5, 1.0])).is_err());
+        assert!(tint.validate(&CvarValue::Color([0.0, 0.5, -0.1])).is_err());
+
+        // No range, any bool.
+        assert!(registry.get("vsync").unwrap().validate(&CvarValue::Bool(false)).is_ok());
+    }
+
+    #[test]
+    fn parse_like_follows_the_type() {
+        let float = CvarValue::Float(0.0);
+        assert_eq!(float.parse_like(&args(&["2.5"])).unwrap(), CvarValue::Float(2.5));
+        assert_eq!(error(float.parse_like(&args(&["fast"]))), "'fast' is not a number");
+        assert_eq!(error(float.parse_like(&args(&["1", "2"]))), "expected 1 value, got 2");
+        assert_eq!(error(float.parse_like(&[])), "expected 1 value, got 0");
+
+        let bool = CvarValue::Bool(false);
+        assert_eq!(bool.parse_like(&args(&["on"])).unwrap(), CvarValue::Bool(true));
+        assert_eq!(bool.parse_like(&args(&["off"])).unwrap(), CvarValue::Bool(false));
+        assert!(bool.parse_like(&args(&["maybe"])).is_err());
+
+        let color = CvarValue::Color([0.0; 3]);
+        assert_eq!(color.parse_like(&args(&["0.1", "0.2", "0.3"])).unwrap(), CvarValue::Color([0.1, 0.2, 0.3]));
+        assert_eq!(error(color.parse_like(&args(&["0.1", "0.2"]))), "expected 3 values, got 2");
+        assert!(color.parse_like(&args(&["0.1", "red", "0.3"])).is_err());
+    }
+
+    #[test]
+    fn set_rejects_unknown_and_invalid_values() {
+        let mut registry = fresh();
+        assert!(registry.set("speed", CvarValue::Float(8.0)).unwrap().is_none());
+        assert_eq!(registry.float("speed"), 8.0);
+        assert_eq!(error(registry.set("sped", CvarValue::Float(8.0))), "unknown cvar 'sped'");
+        assert!(registry.set("speed", CvarValue::Float(-1.0)).is_err());
+        assert_eq!(registry.float("speed"), 8.0);
+    }
+
+    #[test]
+    fn save_and_load_round_trip_changed_values() {
+        let path = std::env::temp_dir().join(format!("wgpu-sandbox-cvars-{}.json", std::process::id()));
+        let mut registry = fresh();
+        registry.set("speed", CvarValue::Float(8.0)).unwrap();
+        registry.set("tint", CvarValue::Color([0.5, 0.25, 0.0])).unwrap();
+        registry.save(&path).unwrap();
+
+        // Defaults aren't written.
+        let saved: HashMap<String, CvarValue> = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved.len(), 2);
+        assert!(!saved.contains_key("vsync"));
+
+        let mut loaded = fresh();
+        loaded.load(&path).unwrap();
+        assert_eq!(loaded.float("speed"), 8.0);
+        assert_eq!(loaded.color("tint"), [0.5, 0.25, 0.0]);
+        assert!(loaded.bool("vsync"));
+
+        // Unknown names, wrong types and out-of-range values are skipped, the rest applied.
+        std::fs::write(&path, r#"{"speed": 500, "vsync": 1.0, "gone": true, "tint": [0, 0, 1]}"#).unwrap();
+        let mut skipped = fresh();
+        skipped.load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(skipped.float("speed"), 4.0);
+        assert!(skipped.bool("vsync"));
+        assert_eq!(skipped.color("tint"), [0.0, 0.0, 1.0]);
+
+        // A missing file is fine.
+        skipped.load(&path).unwrap();
+    }
+}
@@ -0,0 +1,216 @@
+use cgmath::{InnerSpace, Vector2, Zero};
+use gilrs::{Axis, Button, EventType};
+use log::info;
+use winit::event::ElementState;
+use winit::keyboard::ModifiersState;
+
+use crate::input::{ActionEvent, InputMap, Trigger};
+
+// Shapes raw stick deflection: nothing inside the dead zone, then the remaining
+// range rescaled to 0..1 and raised to `exponent` for finer control near the center.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AxisCurve {
+    pub dead_zone: f32,
+    pub exponent: f32,
+    pub sensitivity: f32,
+}
+
+impl AxisCurve {
+    pub fn apply(&self, value: f32) -> f32 {
+        let magnitude = value.abs();
+        if magnitude <= self.dead_zone {
+            return 0.0;
+        }
+        let scaled = ((magnitude - self.dead_zone) / (1.0 - self.dead_zone)).min(1.0);
+        scaled.powf(self.exponent) * self.sensitivity * value.signum()
+    }
+
+    // Radial dead zone, so diagonals don't snap to the axes the way two
+    // independent per-axis dead zones would.
+    pub fn apply_stick(&self, stick: Vector2<f32>) -> Vector2<f32> {
+        let magnitude = stick.magnitude();
+        if magnitude <= self.dead_zone {
+            return Vector2::zero();
+        }
+        stick / magnitude * self.apply(magnitude.min(1.0))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GamepadSettings {
+    // Left stick, output is a fraction of walking speed.
+    pub movement: AxisCurve,
+    // Right stick, output is radians per second.
+    pub look: AxisCurve,
+    pub invert_y: bool,
+}
+
+impl Default for GamepadSettings {
+    fn default() -> Self {
+        Self {
+            movement: AxisCurve { dead_zone: 0.15, exponent: 1.0, sensitivity: 1.0 },
+            look: AxisCurve { dead_zone: 0.1, exponent: 2.0, sensitivity: 3.0 },
+            invert_y: false,
+        }
+    }
+}
+
+// Just the parts of a gilrs event we use, so the mapping can be driven by
+// synthetic events without a controller plugged in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PadEvent {
+    Axis(Axis, f32),
+    Button(Button, bool),
+    Disconnected,
+}
+
+impl PadEvent {
+    pub fn from_gilrs(event: &EventType) -> Option<Self> {
+        match *event {
+            EventType::AxisChanged(axis, value, _) => Some(PadEvent::Axis(axis, value)),
+            EventType::ButtonPressed(button, _) => Some(PadEvent::Button(button, true)),
+            EventType::ButtonReleased(button, _) => Some(PadEvent::Button(button, false)),
+            EventType::Disconnected => Some(PadEvent::Disconnected),
+            _ => None,
+        }
+    }
+}
+
+// Raw stick state plus the curves that turn it into camera input. Buttons
+// (including the digital side of the triggers) go through the InputMap.
+#[derive(Debug)]
+pub struct GamepadMapper {
+    pub settings: GamepadSettings,
+    left_stick: Vector2<f32>,
+    right_stick: Vector2<f32>,
+}
+
+impl Default for GamepadMapper {
+    fn default() -> Self {
+        Self::new(GamepadSettings::default())
+    }
+}
+
+impl GamepadMapper {
+    pub fn new(settings: GamepadSettings) -> Self {
+        Self {
+            settings,
+            left_stick: Vector2::zero(),
+            right_stick: Vector2::zero(),
+        }
+    }
+
+    pub fn process(&mut self, event: PadEvent, input_map: &InputMap) -> Vec<ActionEvent> {
+        match event {
+            PadEvent::Axis(axis, value) => {
+                match axis {
+                    Axis::LeftStickX => self.left_stick.x = value,
+                    Axis::LeftStickY => self.left_stick.y = value,
+                    Axis::RightStickX => self.right_stick.x = value,
+                    Axis::RightStickY => self.right_stick.y = value,
+                    _ => {}
+                }
+                Vec::new()
+            }
+            PadEvent::Button(button, pressed) => {
+                let state = if pressed { ElementState::Pressed } else { ElementState::Released };
+                input_map.actions_for(Trigger::Gamepad(button), state, &ModifiersState::empty())
+            }
+            PadEvent::Disconnected => {
+                info!("Gamepad disconnected");
+                self.left_stick = Vector2::zero();
+                self.right_stick = Vector2::zero();
+                Vec::new()
+            }
+        }
+    }
+
+    // x is strafe right, y is forward; at most 1 in length.
+    pub fn movement(&self) -> Vector2<f32> {
+        self.settings.movement.apply_stick(self.left_stick)
+    }
+
+    // Yaw and pitch rates in radians per second, positive y looks up.
+    pub fn look(&self) -> Vector2<f32> {
+        let look = self.settings.look.apply_stick(self.right_stick);
+        if self.settings.invert_y {
+            Vector2::new(look.x, -look.y)
+        } else {
+            look
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::Action;
+
+    const LINEAR: AxisCurve = AxisCurve { dead_zone: 0.2, exponent: 1.0, sensitivity: 1.0 };
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-5, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn dead_zone_and_rescale() {
+        assert_eq!(LINEAR.apply(0.1), 0.0);
+        assert_eq!(LINEAR.apply(-0.2), 0.0);
+        assert_close(LINEAR.apply(0.6), 0.5);
+        assert_close(LINEAR.apply(-0.6), -0.5);
+        assert_close(LINEAR.apply(1.0), 1.0);
+        // Some pads report slightly past full deflection.
+        assert_close(LINEAR.apply(1.2), 1.0);
+    }
+
+    #[test]
+    fn curve_exponent_and_sensitivity() {
+        let curve = AxisCurve { exponent: 2.0, sensitivity: 3.0, ..LINEAR };
+        assert_close(curve.apply(0.6), 0.25 * 3.0);
+        assert_close(curve.apply(-1.0), -3.0);
+    }
+
+    #[test]
+    fn stick_dead_zone_is_radial() {
+        assert_eq!(LINEAR.apply_stick(Vector2::new(0.1, 0.1)), Vector2::zero());
+        // Past the dead zone on the diagonal even though each axis is inside it.
+        let diagonal = LINEAR.apply_stick(Vector2::new(0.18, 0.18));
+        assert!(diagonal.x > 0.0 && diagonal.y > 0.0);
+        assert_close(diagonal.x, diagonal.y);
+        // Full deflection past the rim is clamped to length 1.
+        assert_close(LINEAR.apply_stick(Vector2::new(1.0, 1.0)).magnitude(), 1.0);
+    }
+
+    #[test]
+    fn sticks_feed_movement_and_look() {
+        let input_map = InputMap::default();
+        let mut mapper = GamepadMapper::new(GamepadSettings { movement: LINEAR, look: LINEAR, invert_y: true });
+        assert!(mapper.process(PadEvent::Axis(Axis::LeftStickY, 0.6), &input_map).is_empty());
+        assert!(mapper.process(PadEvent::Axis(Axis::RightStickY, 1.0), &input_map).is_empty());
+        assert_close(mapper.movement().y, 0.5);
+        assert_close(mapper.look().y, -1.0);
+
+        mapper.process(PadEvent::Disconnected, &input_map);
+        assert_eq!(mapper.movement(), Vector2::zero());
+        assert_eq!(mapper.look(), Vector2::zero());
+    }
+
+    #[test]
+    fn buttons_become_action_events() {
+        let input_map = InputMap::default();
+        let mut mapper = GamepadMapper::default();
+        assert_eq!(
+            mapper.process(PadEvent::Button(Button::South, true), &input_map),
+            vec![ActionEvent { action: Action::Jump, pressed: true }]
+        );
+        assert_eq!(
+            mapper.process(PadEvent::Button(Button::South, false), &input_map),
+            vec![ActionEvent { action: Action::Jump, pressed: false }]
+        );
+        assert_eq!(
+            mapper.process(PadEvent::Button(Button::RightTrigger2, true), &input_map),
+            vec![ActionEvent { action: Action::Sprint, pressed: true }]
+        );
+        assert!(mapper.process(PadEvent::Button(Button::DPadUp, true), &input_map).is_empty());
+    }
+}
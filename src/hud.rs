@@ -0,0 +1,347 @@
+use std::collections::HashMap;
+
+use glyphon::{Attrs, Buffer, Color, Family, FontSystem, Metrics, Shaping, TextArea, TextBounds};
+
+use crate::markup;
+
+// Cached buffers not drawn for this many frames are dropped.
+const EVICT_AFTER_FRAMES: u64 = 120;
+
+// Screen point a HUD position is measured from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[allow(unused)]
+pub enum Anchor {
+    #[default]
+    TopLeft,
+    TopCenter,
+    TopRight,
+    CenterLeft,
+    Center,
+    CenterRight,
+    BottomLeft,
+    BottomCenter,
+    BottomRight,
+}
+
+impl Anchor {
+    // Fractions of the screen size, and which way positive offsets go.
+    fn factors(self) -> ([f32; 2], [f32; 2]) {
+        let (x, sx) = match self {
+            Anchor::TopLeft | Anchor::CenterLeft | Anchor::BottomLeft => (0.0, 1.0),
+            Anchor::TopCenter | Anchor::Center | Anchor::BottomCenter => (0.5, 1.0),
+            Anchor::TopRight | Anchor::CenterRight | Anchor::BottomRight => (1.0, -1.0),
+        };
+        let (y, sy) = match self {
+            Anchor::TopLeft | Anchor::TopCenter | Anchor::TopRight => (0.0, 1.0),
+            Anchor::CenterLeft | Anchor::Center | Anchor::CenterRight => (0.5, 1.0),
+            Anchor::BottomLeft | Anchor::BottomCenter | Anchor::BottomRight => (1.0, -1.0),
+        };
+        ([x, y], [sx, sy])
+    }
+}
+
+// Which part of the text sits on the anchored point, horizontally and vertically.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Align {
+    #[default]
+    Start,
+    Center,
+    End,
+}
+
+impl Align {
+    fn offset(self, size: f32) -> f32 {
+        match self {
+            Align::Start => 0.0,
+            Align::Center => -size * 0.5,
+            Align::End => -size,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum HudFont {
+    #[default]
+    SansSerif,
+    Monospace,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextStyle {
+    pub color: Color,
+    pub font_size: f32,
+    pub font: HudFont,
+    pub anchor: Anchor,
+    pub align: Align,
+    pub vertical_align: Align,
+    // Screen-space clip rectangle (left, top, right, bottom); the whole screen if None.
+    pub clip: Option<[i32; 4]>,
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        Self {
+            color: Color::rgb(255, 255, 255),
+            font_size: 18.0,
+            font: HudFont::SansSerif,
+            anchor: Anchor::TopLeft,
+            align: Align::Start,
+            vertical_align: Align::Start,
+            clip: None,
+        }
+    }
+}
+
+impl TextStyle {
+    pub fn color(mut self, color: Color) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn font_size(mut self, font_size: f32) -> Self {
+        self.font_size = font_size;
+        self
+    }
+
+    pub fn font(mut self, font: HudFont) -> Self {
+        self.font = font;
+        self
+    }
+
+    // Also aligns the text towards the anchor, so TopRight text grows leftwards.
+    pub fn anchor(mut self, anchor: Anchor) -> Self {
+        self.anchor = anchor;
+        let ([x, y], _) = anchor.factors();
+        let align = |f: f32| if f == 0.0 { Align::Start } else if f == 0.5 { Align::Center } else { Align::End };
+        self.align = align(x);
+        self.vertical_align = align(y);
+        self
+    }
+
+    #[allow(unused)]
+    pub fn align(mut self, align: Align) -> Self {
+        self.align = align;
+        self
+    }
+
+    #[allow(unused)]
+    pub fn vertical_align(mut self, align: Align) -> Self {
+        self.vertical_align = align;
+        self
+    }
+
+    #[allow(unused)]
+    pub fn clip(mut self, left: i32, top: i32, right: i32, bottom: i32) -> Self {
+        self.clip = Some([left, top, right, bottom]);
+        self
+    }
+
+    fn bounds(&self, screen_width: u32, screen_height: u32) -> TextBounds {
+        let [left, top, right, bottom] = self.clip.unwrap_or([0, 0, screen_width as i32, screen_height as i32]);
+        TextBounds { left, top, right, bottom }
+    }
+
+    // Top-left corner of text measuring `size` drawn at `position`.
+    fn place(&self, position: [f32; 2], size: [f32; 2], screen: [f32; 2]) -> [f32; 2] {
+        let (factor, sign) = self.anchor.factors();
+        [
+            screen[0] * factor[0] + position[0] * sign[0] + self.align.offset(size[0]),
+            screen[1] * factor[1] + position[1] * sign[1] + self.vertical_align.offset(size[1]),
+        ]
+    }
+}
+
+// Everything that changes shaping. Color and position don't, so they aren't part of it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct BufferKey {
+    text: String,
+    font_size_bits: u32,
+    font: HudFont,
+}
+
+struct CachedBuffer {
+    buffer: Buffer,
+    // Measured once when shaped.
+    size: [f32; 2],
+    last_used: u64,
+}
+
+struct QueuedText {
+    key: BufferKey,
+    position: [f32; 2],
+    style: TextStyle,
+}
+
+// Immediate-mode HUD text: call draw_text every frame for whatever should be on
+// screen. Shaped buffers are pooled by content, so unchanged labels cost nothing
+// to re-queue.
+#[derive(Default)]
+pub struct Hud {
+    queued: Vec<QueuedText>,
+    pool: HashMap<BufferKey, CachedBuffer>,
+    frame: u64,
+}
+
+impl Hud {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // `position` is a pixel offset from the style's anchor, pointing into the screen.
+    // `text` may contain markup, see markup::parse.
+    pub fn draw_text(&mut self, position: [f32; 2], text: &str, style: TextStyle) {
+        if text.is_empty() || style.font_size.is_nan() || style.font_size <= 0.0 {
+            return;
+        }
+        self.queued.push(QueuedText {
+            key: BufferKey {
+                text: text.to_string(),
+                font_size_bits: style.font_size.to_bits(),
+                font: style.font,
+            },
+            position,
+            style,
+        });
+    }
+
+    // Shapes any queued text that isn't in the pool yet.
+    pub fn prepare(&mut self, font_system: &mut FontSystem) {
+        for queued in &self.queued {
+            let cached = self
+                .pool
+                .entry(queued.key.clone())
+                .or_insert_with(|| shape(font_system, &queued.key));
+            cached.last_used = self.frame;
+        }
+    }
+
+    pub fn text_areas(&self, screen_width: u32, screen_height: u32) -> Vec<TextArea<'_>> {
+        let screen = [screen_width as f32, screen_height as f32];
+        self.queued
+            .iter()
+            .filter_map(|queued| {
+                let cached = self.pool.get(&queued.key)?;
+                let [left, top] = queued.style.place(queued.position, cached.size, screen);
+                Some(TextArea {
+                    buffer: &cached.buffer,
+                    left,
+                    top,
+                    scale: 1.0,
+                    bounds: queued.style.bounds(screen_width, screen_height),
+                    default_color: queued.style.color,
+                    custom_glyphs: &[],
+                })
+            })
+            .collect()
+    }
+
+    // Call after rendering: clears this frame's text and drops stale buffers.
+    pub fn end_frame(&mut self) {
+        self.queued.clear();
+        let frame = self.frame;
+        self.pool.retain(|_, cached| frame - cached.last_used < EVICT_AFTER_FRAMES);
+        self.frame += 1;
+    }
+}
+
+fn shape(font_system: &mut FontSystem, key: &BufferKey) -> CachedBuffer {
+    let font_size = f32::from_bits(key.font_size_bits);
+    let mut buffer = Buffer::new(font_system, Metrics::new(font_size, font_size * 1.3));
+    // No width, so nothing wraps; labels are measured after shaping.
+    buffer.set_size(font_system, None, None);
+    let family = match key.font {
+        HudFont::SansSerif => Family::SansSerif,
+        HudFont::Monospace => Family::Monospace,
+    };
+    markup::set_rich_text(&mut buffer, font_system, &key.text, Attrs::new().family(family), Shaping::Advanced);
+    buffer.shape_until_scroll(font_system, false);
+    let (width, height) = buffer
+        .layout_runs()
+        .fold((0.0f32, 0.0f32), |(width, _), run| (width.max(run.line_w), run.line_top + run.line_height));
+    CachedBuffer {
+        buffer,
+        size: [width, height],
+        last_used: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCREEN: [f32; 2] = [800.0, 600.0];
+    const SIZE: [f32; 2] = [100.0, 20.0];
+
+    fn place(style: TextStyle, position: [f32; 2]) -> [f32; 2] {
+        style.place(position, SIZE, SCREEN)
+    }
+
+    #[test]
+    fn anchors_measure_offsets_into_the_screen() {
+        let at = |anchor| place(TextStyle::default().anchor(anchor), [10.0, 5.0]);
+        assert_eq!(at(Anchor::TopLeft), [10.0, 5.0]);
+        assert_eq!(at(Anchor::TopCenter), [360.0, 5.0]);
+        assert_eq!(at(Anchor::TopRight), [690.0, 5.0]);
+        assert_eq!(at(Anchor::CenterLeft), [10.0, 295.0]);
+        assert_eq!(at(Anchor::Center), [360.0, 295.0]);
+        assert_eq!(at(Anchor::CenterRight), [690.0, 295.0]);
+        assert_eq!(at(Anchor::BottomLeft), [10.0, 575.0]);
+        assert_eq!(at(Anchor::BottomCenter), [360.0, 575.0]);
+        assert_eq!(at(Anchor::BottomRight), [690.0, 575.0]);
+    }
+
+    #[test]
+    fn align_overrides_the_anchor_default() {
+        let style = TextStyle::default().anchor(Anchor::TopRight).align(Align::Start);
+        assert_eq!(place(style, [10.0, 5.0]), [790.0, 5.0]);
+        let style = TextStyle::default().align(Align::Center).vertical_align(Align::End);
+        assert_eq!(place(style, [400.0, 300.0]), [350.0, 280.0]);
+    }
+
+    #[test]
+    fn clip_becomes_the_text_bounds() {
+        let bounds = |style: TextStyle| {
+            let TextBounds { left, top, right, bottom } = style.bounds(800, 600);
+            [left, top, right, bottom]
+        };
+        assert_eq!(bounds(TextStyle::default()), [0, 0, 800, 600]);
+        assert_eq!(bounds(TextStyle::default().clip(10, 20, 300, 40)), [10, 20, 300, 40]);
+    }
+
+    #[test]
+    fn empty_text_and_bad_sizes_are_ignored() {
+        let mut hud = Hud::new();
+        hud.draw_text([0.0, 0.0], "", TextStyle::default());
+        hud.draw_text([0.0, 0.0], "zero", TextStyle::default().font_size(0.0));
+        hud.draw_text([0.0, 0.0], "negative", TextStyle::default().font_size(-4.0));
+        hud.draw_text([0.0, 0.0], "nan", TextStyle::default().font_size(f32::NAN));
+        assert!(hud.queued.is_empty());
+    }
+
+    #[test]
+    fn buffers_are_reused_and_evicted_when_unused() {
+        let mut font_system = FontSystem::new();
+        let mut hud = Hud::new();
+        let style = TextStyle::default();
+
+        hud.draw_text([0.0, 0.0], "fps", style);
+        hud.draw_text([0.0, 20.0], "fps", style.color(Color::rgb(255, 0, 0)));
+        hud.draw_text([0.0, 40.0], "fps", style.font_size(30.0));
+        hud.prepare(&mut font_system);
+        // Color doesn't affect shaping, size does.
+        assert_eq!(hud.pool.len(), 2);
+        assert_eq!(hud.text_areas(800, 600).len(), 3);
+        hud.end_frame();
+        assert!(hud.queued.is_empty());
+
+        for frame in 1..=EVICT_AFTER_FRAMES {
+            assert_eq!(hud.pool.len(), 2, "evicted early at frame {}", frame);
+            hud.draw_text([0.0, 0.0], "fps", style);
+            hud.prepare(&mut font_system);
+            hud.end_frame();
+        }
+        // The 30px buffer went unused for EVICT_AFTER_FRAMES; the one drawn every frame stays.
+        assert_eq!(hud.pool.len(), 1);
+        assert!(hud.pool.keys().all(|key| key.font_size_bits == style.font_size.to_bits()));
+    }
+}
@@ -0,0 +1,416 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context};
+use log::warn;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use winit::event::{ElementState, MouseButton};
+use winit::keyboard::{KeyCode, ModifiersState};
+
+// Bindings are read from here at startup; missing or broken files fall back to the defaults.
+pub const INPUT_CONFIG_FILE: &str = "input.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Action {
+    MoveForward,
+    MoveBackward,
+    MoveLeft,
+    MoveRight,
+    Jump,
+    Crouch,
+    Sprint,
+    Slow,
+    // Held to rotate the camera with the mouse.
+    Look,
+    // Held to pan in orbit mode.
+    Pan,
+    // Toggles relative mouse mode: cursor hidden and grabbed, mouse always looks.
+    GrabCursor,
+    ToggleCameraMode,
+    ToggleProjection,
+    // Attaches the camera to a walking player, or detaches it to fly freely.
+    TogglePlayer,
+    // Switches the player between walking and noclip flight.
+    ToggleNoclip,
+    ToggleRecording,
+    PlayCameraPath,
+    ToggleConsole,
+    TogglePerfOverlay,
+    Exit,
+}
+
+impl Action {
+    // Actions that fire once per press rather than while held; key repeats are ignored for these.
+    pub fn is_trigger(self) -> bool {
+        matches!(
+            self,
+            Action::GrabCursor
+                | Action::ToggleCameraMode
+                | Action::ToggleProjection
+                | Action::TogglePlayer
+                | Action::ToggleNoclip
+                | Action::ToggleRecording
+                | Action::PlayCameraPath
+                | Action::ToggleConsole
+                | Action::TogglePerfOverlay
+                | Action::Exit
+        )
+    }
+
+    // Actions that move the camera; pressing one takes control back from a playing camera path.
+    pub fn is_movement(self) -> bool {
+        matches!(
+            self,
+            Action::MoveForward
+                | Action::MoveBackward
+                | Action::MoveLeft
+                | Action::MoveRight
+                | Action::Jump
+                | Action::Crouch
+        )
+    }
+}
+
+// By variant name, as in input.json.
+impl FromStr for Action {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        variant(s).map_err(|_| anyhow!("unknown action '{}'", s))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Trigger {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    Gamepad(gilrs::Button),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Modifiers {
+    #[serde(default)]
+    pub ctrl: bool,
+    #[serde(default)]
+    pub shift: bool,
+    #[serde(default)]
+    pub alt: bool,
+}
+
+impl Modifiers {
+    pub const NONE: Modifiers = Modifiers { ctrl: false, shift: false, alt: false };
+
+    // Every modifier this one asks for is held. Extra held modifiers are fine, so
+    // holding sprint doesn't stop the movement keys working; see InputMap::actions_for.
+    pub fn satisfied_by(&self, held: &ModifiersState) -> bool {
+        (!self.ctrl || held.control_key()) && (!self.shift || held.shift_key()) && (!self.alt || held.alt_key())
+    }
+
+    // How many modifiers the binding asks for.
+    pub fn count(&self) -> u32 {
+        self.ctrl as u32 + self.shift as u32 + self.alt as u32
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Binding {
+    pub trigger: Trigger,
+    #[serde(default)]
+    pub modifiers: Modifiers,
+}
+
+impl Binding {
+    pub fn key(key: KeyCode) -> Self {
+        Self { trigger: Trigger::Key(key), modifiers: Modifiers::NONE }
+    }
+
+    pub fn mouse(button: MouseButton) -> Self {
+        Self { trigger: Trigger::Mouse(button), modifiers: Modifiers::NONE }
+    }
+
+    pub fn gamepad(button: gilrs::Button) -> Self {
+        Self { trigger: Trigger::Gamepad(button), modifiers: Modifiers::NONE }
+    }
+}
+
+// Written like "ctrl+shift+KeyS", "Mouse:Left" or "Gamepad:South": modifiers,
+// then a winit KeyCode, mouse button or gilrs button by variant name.
+impl FromStr for Binding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut modifiers = Modifiers::NONE;
+        let mut rest = s;
+        while let Some((modifier, tail)) = rest.split_once('+') {
+            match modifier.to_ascii_lowercase().as_str() {
+                "ctrl" => modifiers.ctrl = true,
+                "shift" => modifiers.shift = true,
+                "alt" => modifiers.alt = true,
+                _ => bail!("unknown modifier '{}'", modifier),
+            }
+            rest = tail;
+        }
+        let unknown = |_| anyhow!("unknown button '{}'", rest);
+        let trigger = match rest.split_once(':') {
+            Some((device, name)) if device.eq_ignore_ascii_case("mouse") => Trigger::Mouse(variant(name).map_err(unknown)?),
+            Some((device, name)) if device.eq_ignore_ascii_case("gamepad") => {
+                Trigger::Gamepad(variant(name).map_err(unknown)?)
+            }
+            Some((device, _)) => bail!("unknown device '{}', expected Mouse or Gamepad", device),
+            None => Trigger::Key(variant(rest).map_err(unknown)?),
+        };
+        Ok(Self { trigger, modifiers })
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (held, name) in [(self.modifiers.ctrl, "ctrl"), (self.modifiers.shift, "shift"), (self.modifiers.alt, "alt")] {
+            if held {
+                write!(f, "{}+", name)?;
+            }
+        }
+        match &self.trigger {
+            Trigger::Key(key) => write!(f, "{}", variant_name(key)),
+            Trigger::Mouse(button) => write!(f, "Mouse:{}", variant_name(button)),
+            Trigger::Gamepad(button) => write!(f, "Gamepad:{}", variant_name(button)),
+        }
+    }
+}
+
+fn variant<T: DeserializeOwned>(name: &str) -> serde_json::Result<T> {
+    serde_json::from_value(serde_json::Value::String(name.to_string()))
+}
+
+// Variants with data, like MouseButton::Other, fall back to Debug.
+fn variant_name<T: Serialize + fmt::Debug>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => format!("{:?}", value),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ActionEvent {
+    pub action: Action,
+    pub pressed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputMap {
+    bindings: HashMap<Action, Vec<Binding>>,
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        let mut map = Self { bindings: HashMap::new() };
+        for (action, key) in [
+            (Action::MoveForward, KeyCode::KeyW),
+            (Action::MoveForward, KeyCode::ArrowUp),
+            (Action::MoveBackward, KeyCode::KeyS),
+            (Action::MoveBackward, KeyCode::ArrowDown),
+            (Action::MoveLeft, KeyCode::KeyA),
+            (Action::MoveLeft, KeyCode::ArrowLeft),
+            (Action::MoveRight, KeyCode::KeyD),
+            (Action::MoveRight, KeyCode::ArrowRight),
+            (Action::Jump, KeyCode::Space),
+            (Action::Crouch, KeyCode::ShiftLeft),
+            (Action::Sprint, KeyCode::ControlLeft),
+            (Action::Sprint, KeyCode::ControlRight),
+            (Action::Slow, KeyCode::AltLeft),
+            (Action::Slow, KeyCode::AltRight),
+            (Action::GrabCursor, KeyCode::KeyG),
+            (Action::ToggleCameraMode, KeyCode::KeyO),
+            (Action::ToggleProjection, KeyCode::KeyP),
+            (Action::TogglePlayer, KeyCode::KeyF),
+            (Action::ToggleNoclip, KeyCode::KeyN),
+            (Action::ToggleRecording, KeyCode::F5),
+            (Action::PlayCameraPath, KeyCode::F6),
+            (Action::ToggleConsole, KeyCode::Backquote),
+            (Action::TogglePerfOverlay, KeyCode::F3),
+            (Action::Exit, KeyCode::Escape),
+        ] {
+            map.bind(action, Binding::key(key));
+        }
+        map.bind(Action::Look, Binding::mouse(MouseButton::Left));
+        map.bind(Action::Pan, Binding::mouse(MouseButton::Middle));
+        for (action, button) in [
+            (Action::Jump, gilrs::Button::South),
+            (Action::Crouch, gilrs::Button::East),
+            (Action::Sprint, gilrs::Button::RightTrigger2),
+            (Action::Slow, gilrs::Button::LeftTrigger2),
+            (Action::ToggleCameraMode, gilrs::Button::Select),
+            (Action::ToggleProjection, gilrs::Button::North),
+            (Action::ToggleConsole, gilrs::Button::Start),
+        ] {
+            map.bind(action, Binding::gamepad(button));
+        }
+        map
+    }
+}
+
+impl InputMap {
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read input config {:?}", path))?;
+        serde_json::from_str(&text).with_context(|| format!("Failed to parse input config {:?}", path))
+    }
+
+    // The config file if there is one, otherwise the default bindings.
+    pub fn load_or_default<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Self::default();
+        }
+        Self::load(path).unwrap_or_else(|e| {
+            warn!("{:?}, using default bindings", e);
+            Self::default()
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let text = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, text).with_context(|| format!("Failed to write input config {:?}", path))
+    }
+
+    // Adds a binding; an action can have any number of them.
+    pub fn bind(&mut self, action: Action, binding: Binding) {
+        let bindings = self.bindings.entry(action).or_default();
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+    }
+
+    pub fn unbind(&mut self, action: Action, binding: &Binding) {
+        if let Some(bindings) = self.bindings.get_mut(&action) {
+            bindings.retain(|b| b != binding);
+        }
+    }
+
+    // Replaces every binding of the action with a single one.
+    pub fn rebind(&mut self, action: Action, binding: Binding) {
+        self.bindings.insert(action, vec![binding]);
+    }
+
+    pub fn bindings(&self, action: Action) -> &[Binding] {
+        self.bindings.get(&action).map_or(&[], Vec::as_slice)
+    }
+
+    // Actions a trigger changes state for. Presses must satisfy the binding's
+    // modifiers, and only the bindings asking for the most of the held ones fire,
+    // so binding Ctrl+S doesn't also press S's action. Releases ignore modifiers,
+    // so letting go of Ctrl first can't leave an action stuck.
+    pub fn actions_for(&self, trigger: Trigger, state: ElementState, held: &ModifiersState) -> Vec<ActionEvent> {
+        let pressed = state == ElementState::Pressed;
+        let matches = |b: &Binding| b.trigger == trigger && (!pressed || b.modifiers.satisfied_by(held));
+        let Some(most_specific) = self
+            .bindings
+            .values()
+            .flatten()
+            .filter(|b| matches(b))
+            .map(|b| b.modifiers.count())
+            .max()
+        else {
+            return Vec::new();
+        };
+        let mut events = self
+            .bindings
+            .iter()
+            .filter(|(_, bindings)| {
+                bindings
+                    .iter()
+                    .any(|b| matches(b) && (!pressed || b.modifiers.count() == most_specific))
+            })
+            .map(|(&action, _)| ActionEvent { action, pressed })
+            .collect::<Vec<_>>();
+        // HashMap order isn't stable; keep dispatch deterministic.
+        events.sort_by_key(|e| e.action as u8);
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CTRL_S: Binding = Binding {
+        trigger: Trigger::Key(KeyCode::KeyS),
+        modifiers: Modifiers { ctrl: true, shift: false, alt: false },
+    };
+
+    fn actions(map: &InputMap, key: KeyCode, state: ElementState, held: ModifiersState) -> Vec<(Action, bool)> {
+        map.actions_for(Trigger::Key(key), state, &held)
+            .into_iter()
+            .map(|e| (e.action, e.pressed))
+            .collect()
+    }
+
+    #[test]
+    fn bindings_parse_and_display() {
+        for text in ["KeyW", "ctrl+KeyS", "ctrl+shift+alt+F5", "Mouse:Left", "shift+Mouse:Middle", "Gamepad:South"] {
+            let binding: Binding = text.parse().unwrap();
+            assert_eq!(binding.to_string(), text);
+        }
+        assert_eq!("CTRL+KeyS".parse::<Binding>().unwrap(), CTRL_S);
+        assert_eq!("mouse:Right".parse::<Binding>().unwrap(), Binding::mouse(MouseButton::Right));
+        for bad in ["", "KeyWW", "super+KeyW", "Joystick:Left", "Mouse:South", "ctrl+"] {
+            assert!(bad.parse::<Binding>().is_err(), "{:?} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn actions_parse_by_name() {
+        assert_eq!("MoveForward".parse::<Action>().unwrap(), Action::MoveForward);
+        assert_eq!("Fly".parse::<Action>().unwrap_err().to_string(), "unknown action 'Fly'");
+    }
+
+    #[test]
+    fn plain_bindings_work_with_modifiers_held() {
+        let map = InputMap::default();
+        let pressed = ElementState::Pressed;
+        // Sprinting with Ctrl held.
+        assert_eq!(actions(&map, KeyCode::KeyW, pressed, ModifiersState::CONTROL), [(Action::MoveForward, true)]);
+        assert_eq!(actions(&map, KeyCode::KeyW, pressed, ModifiersState::empty()), [(Action::MoveForward, true)]);
+    }
+
+    #[test]
+    fn most_specific_binding_wins() {
+        let mut map = InputMap::default();
+        map.bind(Action::ToggleRecording, CTRL_S);
+        let pressed = ElementState::Pressed;
+        assert_eq!(actions(&map, KeyCode::KeyS, pressed, ModifiersState::empty()), [(Action::MoveBackward, true)]);
+        assert_eq!(actions(&map, KeyCode::KeyS, pressed, ModifiersState::CONTROL), [(Action::ToggleRecording, true)]);
+        // Extra modifiers still leave Ctrl+S the best match.
+        assert_eq!(
+            actions(&map, KeyCode::KeyS, pressed, ModifiersState::CONTROL | ModifiersState::SHIFT),
+            [(Action::ToggleRecording, true)]
+        );
+        // Shift alone doesn't satisfy Ctrl+S.
+        assert_eq!(actions(&map, KeyCode::KeyS, pressed, ModifiersState::SHIFT), [(Action::MoveBackward, true)]);
+    }
+
+    #[test]
+    fn unsatisfied_modifiers_do_not_press() {
+        let mut map = InputMap::default();
+        map.rebind(Action::ToggleRecording, CTRL_S);
+        map.unbind(Action::MoveBackward, &Binding::key(KeyCode::KeyS));
+        assert!(actions(&map, KeyCode::KeyS, ElementState::Pressed, ModifiersState::empty()).is_empty());
+        assert!(actions(&map, KeyCode::KeyZ, ElementState::Pressed, ModifiersState::empty()).is_empty());
+    }
+
+    #[test]
+    fn releases_ignore_modifiers() {
+        let mut map = InputMap::default();
+        map.bind(Action::ToggleRecording, CTRL_S);
+        let released = ElementState::Released;
+        // Ctrl let go before S: everything on S is released.
+        let expected = [(Action::MoveBackward, false), (Action::ToggleRecording, false)];
+        assert_eq!(actions(&map, KeyCode::KeyS, released, ModifiersState::empty()), expected);
+        assert_eq!(actions(&map, KeyCode::KeyS, released, ModifiersState::CONTROL), expected);
+    }
+}
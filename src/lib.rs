@@ -1,19 +1,41 @@
 mod texture;
 mod console;
+pub mod chat;
+mod markup;
+mod hud;
+mod world_text;
+mod perf;
+mod perf_overlay;
+mod profiler;
+mod commands;
+mod cvars;
 mod cameracontroller;
+mod input;
+mod gamepad;
+mod player;
 mod vertex;
-mod camera;
+pub mod camera;
+pub mod camera_path;
 mod window_state;
 use std::{f32::consts::FRAC_PI_2, time::Instant};
 mod model;
 mod rendering;
+mod pipeline;
+mod render_queue;
+mod shader_reload;
+mod reflection;
 mod light;
 mod resources;
+pub mod culling;
 mod common {
     pub mod utils;
 }
+mod structs {
+    pub mod block;
+}
 
 // use crate::wig_geo::block_vertex::BlockVertex;
+use crate::rendering::RenderSettings;
 use crate::window_state::WindowState;
 use std::sync::{Arc, Once};
 use winit::{
@@ -36,7 +58,7 @@ pub async fn run() {
             std::panic::set_hook(Box::new(console_error_panic_hook::hook));
             console_log::init_with_level(log::Level::Info).expect("Could't initialize logger");
         } else {
-            env_logger::init();
+            console::ConsoleLogger::init().expect("Couldn't initialize logger");
         }
     }
     let event_loop = EventLoop::new().unwrap();
@@ -68,7 +90,7 @@ pub async fn run() {
     
         let title = env!("CARGO_PKG_NAME");
     
-        let mut state: WindowState<'_> = WindowState::new(&window).await; // NEW!
+        let mut state: WindowState<'_> = WindowState::new(&window, RenderSettings::default()).await; // NEW!
         let mut last_render_time = instant::Instant::now();
         let window = Arc::clone(&window);
         event_loop.run(move |event, control_flow| {
@@ -77,7 +99,7 @@ pub async fn run() {
                 Event::DeviceEvent {
                     event: DeviceEvent::MouseMotion{ delta, },
                     .. // We're not using device_id currently
-                } => if state.mouse_pressed {
+                } if state.wants_mouse_motion() => {
                     state.camera_controller.process_mouse(delta.0, delta.1)
                 }
                 // UPDATED!
@@ -87,16 +109,7 @@ pub async fn run() {
                 } if window_id == window.id() && !state.input(event) => {
                     match event {
                         #[cfg(not(target_arch="wasm32"))]
-                        WindowEvent::CloseRequested
-                        | WindowEvent::KeyboardInput {
-                            event:
-                                KeyEvent {
-                                    state: ElementState::Pressed,
-                                    physical_key: PhysicalKey::Code(KeyCode::Escape),
-                                    ..
-                                },
-                            ..
-                        } => control_flow.exit(),
+                        WindowEvent::CloseRequested => control_flow.exit(),
                         WindowEvent::Resized(physical_size) => {
                             state.resize(*physical_size);
                         }
@@ -107,6 +120,10 @@ pub async fn run() {
                             let dt = now - last_render_time;
                             last_render_time = now;
                             state.update(dt);
+                            // Gamepad actions arrive during update rather than as window events.
+                            if state.exit_requested {
+                                control_flow.exit();
+                            }
                             if let Err(e) = state.render() {
                                 match e {
                                     // Reconfigure the surface if it's lost or outdated
@@ -121,6 +138,9 @@ pub async fn run() {
                         _ => {}
                     }
                 }
+                // Action::Exit is consumed by state.input, so check for it afterwards.
+                #[cfg(not(target_arch="wasm32"))]
+                Event::WindowEvent { .. } if state.exit_requested => control_flow.exit(),
                 _ => {}
             }
         }).unwrap();
@@ -0,0 +1,397 @@
+use std::ops::Range;
+
+use cosmic_text::{AttrsList, Style, Weight};
+use glyphon::{Attrs, Buffer, Color, FontSystem, Shaping};
+
+// Longest `[...]` that's considered as a tag; anything longer is plain text.
+const MAX_TAG_LEN: usize = 32;
+
+// Quake-style `^0`..`^9` colors.
+const CARET_COLORS: [[u8; 3]; 10] = [
+    [0, 0, 0],
+    [255, 80, 80],
+    [80, 255, 80],
+    [255, 255, 80],
+    [80, 120, 255],
+    [80, 255, 255],
+    [255, 80, 255],
+    [255, 255, 255],
+    [255, 160, 60],
+    [160, 160, 160],
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SpanStyle {
+    // None keeps the text's default color.
+    pub color: Option<Color>,
+    pub bold: bool,
+    pub italic: bool,
+}
+
+impl SpanStyle {
+    pub fn attrs<'a>(&self, base: Attrs<'a>) -> Attrs<'a> {
+        let mut attrs = base;
+        if let Some(color) = self.color {
+            attrs = attrs.color(color);
+        }
+        if self.bold {
+            attrs = attrs.weight(Weight::BOLD);
+        }
+        if self.italic {
+            attrs = attrs.style(Style::Italic);
+        }
+        attrs
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub text: String,
+    pub style: SpanStyle,
+}
+
+// One line of plain text and the byte ranges of its styled runs.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct StyledLine {
+    pub text: String,
+    pub runs: Vec<(Range<usize>, SpanStyle)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TagKind {
+    Bold,
+    Italic,
+    Color,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Tag {
+    Open(TagKind, SpanStyle),
+    Close(TagKind),
+}
+
+struct Frame {
+    // None for the outermost frame, which can't be closed.
+    tag: Option<TagKind>,
+    style: SpanStyle,
+}
+
+struct Parser {
+    spans: Vec<Span>,
+    stack: Vec<Frame>,
+}
+
+impl Parser {
+    fn style(&self) -> SpanStyle {
+        self.stack.last().map_or(SpanStyle::default(), |frame| frame.style)
+    }
+
+    fn push_text(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        let style = self.style();
+        match self.spans.last_mut() {
+            Some(span) if span.style == style => span.text.push_str(text),
+            _ => self.spans.push(Span { text: text.to_string(), style }),
+        }
+    }
+
+    // False if there was nothing open to close, so the tag is shown as text.
+    fn apply(&mut self, tag: Tag) -> bool {
+        match tag {
+            Tag::Open(kind, style) => {
+                self.stack.push(Frame { tag: Some(kind), style });
+                true
+            }
+            // Closing an outer tag also closes anything opened inside it.
+            Tag::Close(kind) => match self.stack.iter().rposition(|frame| frame.tag == Some(kind)) {
+                Some(index) => {
+                    self.stack.truncate(index);
+                    true
+                }
+                None => false,
+            },
+        }
+    }
+}
+
+// Splits markup into styled spans:
+//   ^0..^9             Quake colors, until the enclosing tag closes; ^^ is a literal ^
+//   [b]..[/b]          bold
+//   [i]..[/i]          italic
+//   [color=X]..[/color] X is #rgb, #rrggbb, #rrggbbaa or a name like red
+//   \[ \^ \\           a literal [, ^ or \
+// Unknown or malformed tags, and closing tags with nothing to close, are kept
+// as text. Tags left open run to the end.
+pub fn parse(input: &str) -> Vec<Span> {
+    let mut parser = Parser {
+        spans: Vec::new(),
+        stack: vec![Frame { tag: None, style: SpanStyle::default() }],
+    };
+    let mut rest = input;
+    while let Some(c) = rest.chars().next() {
+        let next = rest[c.len_utf8()..].chars().next();
+        // Bytes used up; the two-character forms are all ASCII.
+        let consumed = match (c, next) {
+            ('\\', Some(escaped @ ('\\' | '[' | '^'))) => {
+                parser.push_text(escaped.encode_utf8(&mut [0; 4]));
+                2
+            }
+            ('^', Some('^')) => {
+                parser.push_text("^");
+                2
+            }
+            ('^', Some(digit @ '0'..='9')) => {
+                let [r, g, b] = CARET_COLORS[digit as usize - '0' as usize];
+                if let Some(frame) = parser.stack.last_mut() {
+                    frame.style.color = Some(Color::rgb(r, g, b));
+                }
+                2
+            }
+            ('[', _) => match parse_tag(rest, parser.style()) {
+                Some((tag, len)) if parser.apply(tag) => len,
+                _ => {
+                    parser.push_text("[");
+                    1
+                }
+            },
+            _ => {
+                parser.push_text(c.encode_utf8(&mut [0; 4]));
+                c.len_utf8()
+            }
+        };
+        rest = &rest[consumed..];
+    }
+    parser.spans
+}
+
+// Backslash-escapes `text` so parse shows it verbatim. For anything we didn't
+// write as markup: chat from other players, and log records, which can quote anything.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '[' | '^') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// Like str::lines on the plain text, keeping styles. Styles carry over line breaks.
+pub fn parse_lines(input: &str) -> Vec<StyledLine> {
+    let mut lines = vec![StyledLine::default()];
+    for span in parse(input) {
+        for (i, part) in span.text.split('\n').enumerate() {
+            if i > 0 {
+                lines.push(StyledLine::default());
+            }
+            let line = lines.last_mut().unwrap();
+            let part = part.strip_suffix('\r').unwrap_or(part);
+            if !part.is_empty() {
+                let start = line.text.len();
+                line.text.push_str(part);
+                line.runs.push((start..line.text.len(), span.style));
+            }
+        }
+    }
+    if lines.last().is_some_and(|line| line.text.is_empty()) {
+        lines.pop();
+    }
+    lines
+}
+
+// For building a BufferLine directly, as the console does.
+pub fn attrs_list(line: &StyledLine, base: Attrs) -> AttrsList {
+    let mut list = AttrsList::new(base);
+    for (range, style) in &line.runs {
+        if *style != SpanStyle::default() {
+            list.add_span(range.clone(), style.attrs(base));
+        }
+    }
+    list
+}
+
+// Buffer::set_rich_text with the spans parsed from `text`.
+pub fn set_rich_text(buffer: &mut Buffer, font_system: &mut FontSystem, text: &str, base: Attrs, shaping: Shaping) {
+    let spans = parse(text);
+    buffer.set_rich_text(
+        font_system,
+        spans.iter().map(|span| (span.text.as_str(), span.style.attrs(base))),
+        base,
+        shaping,
+    );
+}
+
+// `rest` starts with '['. Returns the tag and its length in bytes.
+fn parse_tag(rest: &str, current: SpanStyle) -> Option<(Tag, usize)> {
+    let end = rest.char_indices().take(MAX_TAG_LEN).find(|&(_, c)| c == ']')?.0;
+    let inner = &rest[1..end];
+    if inner.contains('[') {
+        return None;
+    }
+    let tag = match inner {
+        "b" => Tag::Open(TagKind::Bold, SpanStyle { bold: true, ..current }),
+        "/b" => Tag::Close(TagKind::Bold),
+        "i" => Tag::Open(TagKind::Italic, SpanStyle { italic: true, ..current }),
+        "/i" => Tag::Close(TagKind::Italic),
+        "/color" => Tag::Close(TagKind::Color),
+        _ => {
+            let color = parse_color(inner.strip_prefix("color=")?)?;
+            Tag::Open(TagKind::Color, SpanStyle { color: Some(color), ..current })
+        }
+    };
+    Some((tag, end + 1))
+}
+
+pub fn parse_color(value: &str) -> Option<Color> {
+    let Some(hex) = value.strip_prefix('#') else {
+        return named_color(value);
+    };
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let channel = |i: usize, len: usize| u8::from_str_radix(&hex[i * len..(i + 1) * len], 16).ok();
+    match hex.len() {
+        // #rgb: each digit doubled, so #f80 is #ff8800.
+        3 => Some(Color::rgb(channel(0, 1)? * 17, channel(1, 1)? * 17, channel(2, 1)? * 17)),
+        6 => Some(Color::rgb(channel(0, 2)?, channel(1, 2)?, channel(2, 2)?)),
+        8 => Some(Color::rgba(channel(0, 2)?, channel(1, 2)?, channel(2, 2)?, channel(3, 2)?)),
+        _ => None,
+    }
+}
+
+fn named_color(name: &str) -> Option<Color> {
+    let [r, g, b] = match name.to_ascii_lowercase().as_str() {
+        "black" => CARET_COLORS[0],
+        "red" => CARET_COLORS[1],
+        "green" => CARET_COLORS[2],
+        "yellow" => CARET_COLORS[3],
+        "blue" => CARET_COLORS[4],
+        "cyan" => CARET_COLORS[5],
+        "magenta" => CARET_COLORS[6],
+        "white" => CARET_COLORS[7],
+        "orange" => CARET_COLORS[8],
+        "gray" | "grey" => CARET_COLORS[9],
+        _ => return None,
+    };
+    Some(Color::rgb(r, g, b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(text: &str, style: SpanStyle) -> Span {
+        Span { text: text.to_string(), style }
+    }
+
+    fn plain(text: &str) -> Vec<Span> {
+        vec![span(text, SpanStyle::default())]
+    }
+
+    const BOLD: SpanStyle = SpanStyle { color: None, bold: true, italic: false };
+
+    #[test]
+    fn closing_outer_tag_closes_inner() {
+        let bold_italic = SpanStyle { italic: true, ..BOLD };
+        assert_eq!(
+            parse("[b][i]x[/b]y"),
+            vec![span("x", bold_italic), span("y", SpanStyle::default())]
+        );
+    }
+
+    #[test]
+    fn nested_tags_restore_outer_style() {
+        let red = Some(Color::rgb(255, 80, 80));
+        assert_eq!(
+            parse("[color=red]a[b]b[/b]c[/color]d"),
+            vec![
+                span("a", SpanStyle { color: red, ..SpanStyle::default() }),
+                span("b", SpanStyle { color: red, ..BOLD }),
+                span("c", SpanStyle { color: red, ..SpanStyle::default() }),
+                span("d", SpanStyle::default()),
+            ]
+        );
+    }
+
+    #[test]
+    fn caret_colors() {
+        let [r, g, b] = CARET_COLORS[2];
+        assert_eq!(
+            parse("a^2b"),
+            vec![
+                span("a", SpanStyle::default()),
+                span("b", SpanStyle { color: Some(Color::rgb(r, g, b)), ..SpanStyle::default() }),
+            ]
+        );
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(parse(r"\[b]x"), plain("[b]x"));
+        assert_eq!(parse(r"\^1x"), plain("^1x"));
+        assert_eq!(parse(r"a\\b"), plain(r"a\b"));
+        assert_eq!(parse("^^1"), plain("^1"));
+        // Anything else after a backslash is left alone.
+        assert_eq!(parse(r"\n"), plain(r"\n"));
+    }
+
+    #[test]
+    fn malformed_tags_are_text() {
+        assert_eq!(parse("[color="), plain("[color="));
+        assert_eq!(parse("[color=red"), plain("[color=red"));
+        assert_eq!(parse("[color=nope]x"), plain("[color=nope]x"));
+        assert_eq!(parse("[foo]x[/foo]"), plain("[foo]x[/foo]"));
+        assert_eq!(parse("[/b]x"), plain("[/b]x"));
+        assert_eq!(parse("[[b]x"), vec![span("[", SpanStyle::default()), span("x", BOLD)]);
+    }
+
+    #[test]
+    fn tag_longer_than_limit_is_text() {
+        let long = format!("[color={}]x", "a".repeat(MAX_TAG_LEN));
+        assert_eq!(parse(&long), plain(&long));
+    }
+
+    #[test]
+    fn caret_at_end_of_input() {
+        assert_eq!(parse("a^"), plain("a^"));
+        assert_eq!(parse("^"), plain("^"));
+    }
+
+    #[test]
+    fn multibyte_text_next_to_tags() {
+        assert_eq!(
+            parse("é[b]üñ[/b]日本^1"),
+            vec![span("é", SpanStyle::default()), span("üñ", BOLD), span("日本", SpanStyle::default())]
+        );
+    }
+
+    #[test]
+    fn escape_round_trips() {
+        for text in [r"[b]x[/b]", "^1red^^", r"back\slash\[", "plain"] {
+            assert_eq!(parse(&escape(text)), plain(text));
+        }
+    }
+
+    #[test]
+    fn lines_keep_styles_across_breaks() {
+        let lines = parse_lines("a[b]b\nc[/b]\n");
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].text, "ab");
+        assert_eq!(lines[0].runs, vec![(0..1, SpanStyle::default()), (1..2, BOLD)]);
+        assert_eq!(lines[1].text, "c");
+        assert_eq!(lines[1].runs, vec![(0..1, BOLD)]);
+    }
+
+    #[test]
+    fn colors() {
+        assert_eq!(parse_color("#f80"), Some(Color::rgb(255, 136, 0)));
+        assert_eq!(parse_color("#102030"), Some(Color::rgb(16, 32, 48)));
+        assert_eq!(parse_color("#10203040"), Some(Color::rgba(16, 32, 48, 64)));
+        assert_eq!(parse_color("Grey"), Some(Color::rgb(160, 160, 160)));
+        assert_eq!(parse_color("#12345"), None);
+        assert_eq!(parse_color("#ggg"), None);
+    }
+}
@@ -1,5 +1,6 @@
 use std::ops::Range;
 
+use crate::culling::BoundingSphere;
 use crate::{light, texture};
 
 pub trait Vertex {
@@ -41,14 +42,40 @@ impl Vertex for ModelVertex {
     }
 }
 
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MaterialUniform {
+    pub opacity: f32,
+    pub _padding: [f32; 3],
+}
+
+impl MaterialUniform {
+    pub fn new(opacity: f32) -> Self {
+        Self {
+            opacity,
+            _padding: [0.0; 3],
+        }
+    }
+}
+
 pub struct Material {
     #[allow(unused)]
     pub name: String,
     #[allow(unused)]
     pub diffuse_texture: texture::Texture,
+    // `d` from the MTL file, 1.0 is fully opaque.
+    pub opacity: f32,
+    #[allow(unused)]
+    pub uniform_buffer: wgpu::Buffer,
     pub bind_group: wgpu::BindGroup,
 }
 
+impl Material {
+    pub fn is_transparent(&self) -> bool {
+        self.opacity < 1.0 || self.diffuse_texture.has_transparency
+    }
+}
+
 pub struct Mesh {
     #[allow(unused)]
     pub name: String,
@@ -56,6 +83,8 @@ pub struct Mesh {
     pub index_buffer: wgpu::Buffer,
     pub num_elements: u32,
     pub material: usize,
+    // Object-space, computed from the vertices at load.
+    pub bounding_sphere: BoundingSphere,
 }
 
 pub struct Model {
@@ -122,6 +151,7 @@ where
         self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
         self.set_bind_group(0, &material.bind_group, &[]);
         self.set_bind_group(1, camera_bind_group, &[]);
+        self.set_bind_group(2, light_bind_group, &[]);
         self.draw_indexed(0..mesh.num_elements, 0, instances);
     }
 
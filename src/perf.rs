@@ -0,0 +1,193 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+// Frames kept for the min/avg/max figures and the graph.
+pub const FRAME_HISTORY: usize = 240;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameSummary {
+    pub fps: f32,
+    pub min_ms: f32,
+    pub avg_ms: f32,
+    pub max_ms: f32,
+}
+
+// Rolling window of frame times in milliseconds, oldest first.
+#[derive(Debug, Clone)]
+pub struct FrameStats {
+    frame_times: VecDeque<f32>,
+    capacity: usize,
+}
+
+impl Default for FrameStats {
+    fn default() -> Self {
+        Self::new(FRAME_HISTORY)
+    }
+}
+
+impl FrameStats {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            frame_times: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(1),
+        }
+    }
+
+    pub fn push(&mut self, dt: Duration) {
+        self.push_ms(dt.as_secs_f32() * 1000.0);
+    }
+
+    pub fn push_ms(&mut self, ms: f32) {
+        if self.frame_times.len() == self.capacity {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(ms);
+    }
+
+    pub fn frame_times(&self) -> impl ExactSizeIterator<Item = f32> + '_ {
+        self.frame_times.iter().copied()
+    }
+
+    // FPS is from the average frame time, so one long hitch doesn't swing it.
+    pub fn summary(&self) -> Option<FrameSummary> {
+        if self.frame_times.is_empty() {
+            return None;
+        }
+        let (min_ms, max_ms, total) = self
+            .frame_times
+            .iter()
+            .fold((f32::MAX, 0.0f32, 0.0f32), |(min, max, total), &ms| (min.min(ms), max.max(ms), total + ms));
+        let avg_ms = total / self.frame_times.len() as f32;
+        Some(FrameSummary {
+            fps: if avg_ms > 0.0 { 1000.0 / avg_ms } else { 0.0 },
+            min_ms,
+            avg_ms,
+            max_ms,
+        })
+    }
+}
+
+// What one frame submitted. Filled in while recording the render pass.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RenderCounters {
+    pub draw_calls: u32,
+    pub triangles: u64,
+    pub instances: u32,
+}
+
+impl RenderCounters {
+    // `index_count` is per instance, for a triangle list.
+    pub fn record_draw(&mut self, index_count: u32, instance_count: u32) {
+        self.draw_calls += 1;
+        self.triangles += (index_count / 3) as u64 * instance_count as u64;
+        self.instances += instance_count;
+    }
+}
+
+// Bytes for one texture including its mip chain, ignoring any driver padding.
+pub fn texture_bytes(width: u32, height: u32, format: wgpu::TextureFormat, sample_count: u32, mip_levels: u32) -> u64 {
+    let texel = format.block_copy_size(None).unwrap_or(4) as u64;
+    (0..mip_levels.max(1))
+        .map(|level| (width >> level).max(1) as u64 * (height >> level).max(1) as u64)
+        .sum::<u64>()
+        * texel
+        * sample_count.max(1) as u64
+}
+
+// 1.5 MiB style output for the overlay.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+pub fn wgpu_texture_bytes(texture: &wgpu::Texture) -> u64 {
+    texture_bytes(
+        texture.width(),
+        texture.height(),
+        texture.format(),
+        texture.sample_count(),
+        texture.mip_level_count(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary_of_frame_times() {
+        let mut stats = FrameStats::new(8);
+        assert_eq!(stats.summary(), None);
+
+        stats.push(Duration::from_millis(10));
+        stats.push_ms(20.0);
+        stats.push_ms(30.0);
+        let summary = stats.summary().unwrap();
+        assert_eq!(summary.min_ms, 10.0);
+        assert_eq!(summary.avg_ms, 20.0);
+        assert_eq!(summary.max_ms, 30.0);
+        assert_eq!(summary.fps, 50.0);
+    }
+
+    #[test]
+    fn oldest_frames_are_evicted_at_capacity() {
+        let mut stats = FrameStats::new(3);
+        for ms in [100.0, 1.0, 2.0, 3.0] {
+            stats.push_ms(ms);
+        }
+        assert_eq!(stats.frame_times().collect::<Vec<_>>(), [1.0, 2.0, 3.0]);
+        assert_eq!(stats.summary().unwrap().max_ms, 3.0);
+
+        // A zero capacity still keeps the latest frame.
+        let mut stats = FrameStats::new(0);
+        stats.push_ms(1.0);
+        stats.push_ms(2.0);
+        assert_eq!(stats.frame_times().collect::<Vec<_>>(), [2.0]);
+    }
+
+    #[test]
+    fn record_draw_counts_triangles_per_instance() {
+        let mut counters = RenderCounters::default();
+        counters.record_draw(36, 10);
+        counters.record_draw(6, 1);
+        assert_eq!(
+            counters,
+            RenderCounters {
+                draw_calls: 2,
+                triangles: 12 * 10 + 2,
+                instances: 11,
+            }
+        );
+    }
+
+    #[test]
+    fn texture_bytes_with_mips_and_msaa() {
+        use wgpu::TextureFormat;
+
+        assert_eq!(texture_bytes(4, 4, TextureFormat::Rgba8UnormSrgb, 1, 1), 64);
+        // 4x4 + 2x2 + 1x1 texels.
+        assert_eq!(texture_bytes(4, 4, TextureFormat::Rgba8UnormSrgb, 1, 3), 21 * 4);
+        // Mips stop shrinking at one texel on the short side.
+        assert_eq!(texture_bytes(4, 1, TextureFormat::Rgba8UnormSrgb, 1, 3), (4 + 2 + 1) * 4);
+        assert_eq!(texture_bytes(8, 8, TextureFormat::Depth32Float, 4, 1), 8 * 8 * 4 * 4);
+        // Zero counts are treated as one.
+        assert_eq!(texture_bytes(2, 2, TextureFormat::R8Unorm, 0, 0), 4);
+    }
+
+    #[test]
+    fn format_bytes_picks_a_unit() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024), "3.0 MiB");
+    }
+}
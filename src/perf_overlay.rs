@@ -0,0 +1,262 @@
+use wgpu::util::DeviceExt;
+
+use crate::culling::CullStats;
+use crate::hud::{Anchor, Hud, HudFont, TextStyle};
+use crate::perf::{self, FrameStats, RenderCounters, FRAME_HISTORY};
+use crate::pipeline::{BlendMode, PipelineBuilder, PipelineCache, PipelineKey};
+use crate::profiler::FrameProfile;
+use crate::rendering::RenderSettings;
+use crate::texture;
+
+// Pixel layout, measured from the top right corner below the REC indicator.
+const MARGIN: f32 = 10.0;
+const TOP: f32 = 40.0;
+const FONT_SIZE: f32 = 16.0;
+const GRAPH_WIDTH: f32 = FRAME_HISTORY as f32;
+const GRAPH_HEIGHT: f32 = 80.0;
+// Frame times at or above this touch the top of the graph.
+const GRAPH_MAX_MS: f32 = 50.0;
+// Border, plus the 60 and 30 FPS lines.
+const GUIDE_VERTICES: usize = 8 + 4;
+const MAX_VERTICES: usize = (FRAME_HISTORY - 1) * 2 + GUIDE_VERTICES;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LineVertex {
+    // Normalized device coordinates.
+    pub position: [f32; 2],
+    pub color: [f32; 4],
+}
+
+impl LineVertex {
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<LineVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 2]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+// Pixel rectangle the graph is drawn into.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GraphRect {
+    pub left: f32,
+    pub top: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+// Green under 60 FPS budget, yellow under 30, red above.
+fn frame_time_color(ms: f32) -> [f32; 4] {
+    if ms <= 1000.0 / 60.0 {
+        [0.3, 1.0, 0.3, 1.0]
+    } else if ms <= 1000.0 / 30.0 {
+        [1.0, 0.9, 0.2, 1.0]
+    } else {
+        [1.0, 0.3, 0.3, 1.0]
+    }
+}
+
+// Line list for the graph: a border, guide lines at 60 and 30 FPS, then one
+// segment per pair of consecutive frames. The newest frame is at the right edge.
+pub fn graph_vertices(frame_times: &[f32], rect: GraphRect, max_ms: f32, screen: [f32; 2]) -> Vec<LineVertex> {
+    let to_ndc = |x: f32, y: f32| [x / screen[0] * 2.0 - 1.0, 1.0 - y / screen[1] * 2.0];
+    let bottom = rect.top + rect.height;
+    let right = rect.left + rect.width;
+    let y_for = |ms: f32| bottom - (ms / max_ms).clamp(0.0, 1.0) * rect.height;
+    let mut vertices = Vec::with_capacity(frame_times.len() * 2 + GUIDE_VERTICES);
+    let mut line = |from: [f32; 2], to: [f32; 2], color: [f32; 4]| {
+        vertices.push(LineVertex { position: to_ndc(from[0], from[1]), color });
+        vertices.push(LineVertex { position: to_ndc(to[0], to[1]), color });
+    };
+
+    let border = [0.6, 0.6, 0.6, 0.8];
+    line([rect.left, rect.top], [right, rect.top], border);
+    line([right, rect.top], [right, bottom], border);
+    line([right, bottom], [rect.left, bottom], border);
+    line([rect.left, bottom], [rect.left, rect.top], border);
+    for fps in [60.0, 30.0] {
+        let y = y_for(1000.0 / fps);
+        line([rect.left, y], [right, y], [0.6, 0.6, 0.6, 0.4]);
+    }
+
+    if frame_times.len() >= 2 {
+        let step = rect.width / (FRAME_HISTORY - 1) as f32;
+        let start = right - step * (frame_times.len() - 1) as f32;
+        for (i, pair) in frame_times.windows(2).enumerate() {
+            let x = start + step * i as f32;
+            line([x, y_for(pair[0])], [x + step, y_for(pair[1])], frame_time_color(pair[1]));
+        }
+    }
+    vertices
+}
+
+// Toggleable frame timing readout: text through the HUD, the graph through its
+// own line pipeline. Frame times are always recorded so the graph is full when
+// it's switched on.
+pub struct PerfOverlay {
+    pub stats: FrameStats,
+    // From the last frame that was rendered.
+    pub counters: RenderCounters,
+    layout: wgpu::PipelineLayout,
+    pipeline: PipelineKey,
+    vertex_buffer: wgpu::Buffer,
+    vertex_count: u32,
+    // Lines of text queued this frame; the graph goes underneath.
+    text_lines: usize,
+}
+
+impl PerfOverlay {
+    pub fn new(
+        device: &wgpu::Device,
+        pipeline_cache: &mut PipelineCache,
+        color_format: wgpu::TextureFormat,
+        settings: &RenderSettings,
+    ) -> Self {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Perf Overlay Pipeline Layout"),
+            bind_group_layouts: &[],
+            push_constant_ranges: &[],
+        });
+        let pipeline = create_line_pipeline(device, pipeline_cache, &layout, color_format, settings);
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Perf Overlay VB"),
+            contents: bytemuck::cast_slice(&[LineVertex { position: [0.0; 2], color: [0.0; 4] }; MAX_VERTICES]),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+        Self {
+            stats: FrameStats::new(FRAME_HISTORY),
+            counters: RenderCounters::default(),
+            layout,
+            pipeline,
+            vertex_buffer,
+            vertex_count: 0,
+            text_lines: 0,
+        }
+    }
+
+    // The pipeline has to match the pass's sample count and depth mode.
+    pub fn rebuild_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        pipeline_cache: &mut PipelineCache,
+        color_format: wgpu::TextureFormat,
+        settings: &RenderSettings,
+    ) {
+        let pipeline = create_line_pipeline(device, pipeline_cache, &self.layout, color_format, settings);
+        if pipeline != self.pipeline {
+            pipeline_cache.remove(self.pipeline);
+            self.pipeline = pipeline;
+        }
+    }
+
+    pub fn queue_text(&mut self, hud: &mut Hud, cull: CullStats, gpu_memory: u64, profile: Option<&FrameProfile>) {
+        let Some(summary) = self.stats.summary() else {
+            return;
+        };
+        let counters = self.counters;
+        let mut text = format!(
+            "{:.0} FPS\n\
+             frame {:.2} / {:.2} / {:.2} ms\n\
+             draws {}  tris {}\n\
+             instances {} / {}\n\
+             chunks {} / {}\n\
+             gpu mem ~{}",
+            summary.fps,
+            summary.min_ms,
+            summary.avg_ms,
+            summary.max_ms,
+            counters.draw_calls,
+            counters.triangles,
+            cull.instances_visible,
+            cull.instances_total,
+            cull.chunks_visible,
+            cull.chunks_total,
+            perf::format_bytes(gpu_memory),
+        );
+        // CPU recording time per pass, and GPU time when timestamps are available.
+        for pass in profile.map_or(&[][..], |profile| &profile.passes[..]) {
+            text.push_str(&match pass.gpu_ms {
+                Some(gpu_ms) => format!("\n{:<11} gpu {:.2} cpu {:.2} ms", pass.name, gpu_ms, pass.cpu_ms),
+                None => format!("\n{:<11} cpu {:.2} ms", pass.name, pass.cpu_ms),
+            });
+        }
+        self.text_lines = text.lines().count();
+        let style = TextStyle::default()
+            .anchor(Anchor::TopRight)
+            .font(HudFont::Monospace)
+            .font_size(FONT_SIZE);
+        hud.draw_text([MARGIN, TOP], &text, style);
+    }
+
+    // Writes this frame's graph into the vertex buffer.
+    pub fn upload(&mut self, queue: &wgpu::Queue, screen_width: u32, screen_height: u32) {
+        let frame_times = self.stats.frame_times().collect::<Vec<_>>();
+        let rect = GraphRect {
+            left: screen_width as f32 - MARGIN - GRAPH_WIDTH,
+            top: TOP + self.text_lines as f32 * FONT_SIZE * 1.3 + MARGIN,
+            width: GRAPH_WIDTH,
+            height: GRAPH_HEIGHT,
+        };
+        let vertices = graph_vertices(
+            &frame_times,
+            rect,
+            GRAPH_MAX_MS,
+            [screen_width as f32, screen_height as f32],
+        );
+        let vertices = &vertices[..vertices.len().min(MAX_VERTICES)];
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(vertices));
+        self.vertex_count = vertices.len() as u32;
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, pipeline_cache: &'a PipelineCache) {
+        let Some(pipeline) = pipeline_cache.get(self.pipeline) else {
+            return;
+        };
+        if self.vertex_count == 0 {
+            return;
+        }
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw(0..self.vertex_count, 0..1);
+    }
+}
+
+// Drawn inside the main pass, so it carries the depth attachment but ignores it.
+fn create_line_pipeline(
+    device: &wgpu::Device,
+    pipeline_cache: &mut PipelineCache,
+    layout: &wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    settings: &RenderSettings,
+) -> PipelineKey {
+    let vertex_layouts = [LineVertex::desc()];
+    let shader = wgpu::ShaderModuleDescriptor {
+        label: Some("Line Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("shaders/lines.wgsl").into()),
+    };
+    let builder = PipelineBuilder::new(layout, shader, &vertex_layouts, color_format)
+        .label("Line Pipeline")
+        .topology(wgpu::PrimitiveTopology::LineList)
+        .cull_mode(None)
+        .blend(BlendMode::Alpha)
+        .depth_format(Some(texture::Texture::DEPTH_FORMAT))
+        .depth_write(false)
+        .depth_compare(wgpu::CompareFunction::Always)
+        .sample_count(settings.sample_count)
+        .depth_mode(settings.depth_mode);
+    pipeline_cache.get_or_create(device, builder)
+}
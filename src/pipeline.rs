@@ -0,0 +1,363 @@
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use log::{info, warn};
+
+use crate::rendering::{DepthMode, RenderSettings};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlendMode {
+    Replace,
+    Alpha,
+}
+
+impl BlendMode {
+    pub fn to_blend_state(self) -> wgpu::BlendState {
+        match self {
+            BlendMode::Replace => wgpu::BlendState::REPLACE,
+            BlendMode::Alpha => wgpu::BlendState::ALPHA_BLENDING,
+        }
+    }
+}
+
+// Everything that can vary between our render pipelines. Defaults match what
+// create_render_pipeline used to hardcode: opaque, back-face culled, depth Less.
+pub struct PipelineBuilder<'a> {
+    label: &'a str,
+    layout: &'a wgpu::PipelineLayout,
+    shader: wgpu::ShaderModuleDescriptor<'a>,
+    vertex_layouts: &'a [wgpu::VertexBufferLayout<'a>],
+    color_format: wgpu::TextureFormat,
+    depth_format: Option<wgpu::TextureFormat>,
+    blend: BlendMode,
+    cull_mode: Option<wgpu::Face>,
+    topology: wgpu::PrimitiveTopology,
+    polygon_mode: wgpu::PolygonMode,
+    depth_write_enabled: bool,
+    depth_compare: wgpu::CompareFunction,
+    depth_mode: DepthMode,
+    sample_count: u32,
+}
+
+impl<'a> PipelineBuilder<'a> {
+    pub fn new(
+        layout: &'a wgpu::PipelineLayout,
+        shader: wgpu::ShaderModuleDescriptor<'a>,
+        vertex_layouts: &'a [wgpu::VertexBufferLayout<'a>],
+        color_format: wgpu::TextureFormat,
+    ) -> Self {
+        Self {
+            label: "Render Pipeline",
+            layout,
+            shader,
+            vertex_layouts,
+            color_format,
+            depth_format: None,
+            blend: BlendMode::Replace,
+            cull_mode: Some(wgpu::Face::Back),
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            depth_mode: DepthMode::Standard,
+            sample_count: 1,
+        }
+    }
+
+    pub fn label(mut self, label: &'a str) -> Self {
+        self.label = label;
+        self
+    }
+
+    pub fn depth_format(mut self, depth_format: Option<wgpu::TextureFormat>) -> Self {
+        self.depth_format = depth_format;
+        self
+    }
+
+    pub fn blend(mut self, blend: BlendMode) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    pub fn cull_mode(mut self, cull_mode: Option<wgpu::Face>) -> Self {
+        self.cull_mode = cull_mode;
+        self
+    }
+
+    pub fn topology(mut self, topology: wgpu::PrimitiveTopology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    // PolygonMode::Line needs Features::POLYGON_MODE_LINE on the device.
+    pub fn polygon_mode(mut self, polygon_mode: wgpu::PolygonMode) -> Self {
+        self.polygon_mode = polygon_mode;
+        self
+    }
+
+    pub fn depth_write(mut self, enabled: bool) -> Self {
+        self.depth_write_enabled = enabled;
+        self
+    }
+
+    // Written as for standard depth; flipped at build time under reverse-Z.
+    pub fn depth_compare(mut self, compare: wgpu::CompareFunction) -> Self {
+        self.depth_compare = compare;
+        self
+    }
+
+    pub fn depth_mode(mut self, depth_mode: DepthMode) -> Self {
+        self.depth_mode = depth_mode;
+        self
+    }
+
+    pub fn sample_count(mut self, sample_count: u32) -> Self {
+        self.sample_count = sample_count;
+        self
+    }
+
+    // Sample count and depth mode must agree across every pipeline in a pass.
+    pub fn settings(self, settings: &RenderSettings) -> Self {
+        let polygon_mode = if settings.wireframe { wgpu::PolygonMode::Line } else { wgpu::PolygonMode::Fill };
+        self.sample_count(settings.sample_count)
+            .depth_mode(settings.depth_mode)
+            .polygon_mode(polygon_mode)
+    }
+
+    // Owned copy of everything that goes into the pipeline, used as the cache
+    // key. The shader is keyed by its WGSL source so an edited shader never
+    // reuses a stale pipeline.
+    pub fn desc(&self) -> PipelineDesc {
+        let mut layout_hasher = DefaultHasher::new();
+        self.layout.hash(&mut layout_hasher);
+        PipelineDesc {
+            label: self.label.to_string(),
+            layout: layout_hasher.finish(),
+            shader_label: self.shader.label.map(str::to_string),
+            shader_source: match &self.shader.source {
+                wgpu::ShaderSource::Wgsl(source) => Some(source.to_string()),
+                _ => None,
+            },
+            vertex_layouts: self
+                .vertex_layouts
+                .iter()
+                .map(|layout| (layout.array_stride, layout.step_mode, layout.attributes.to_vec()))
+                .collect(),
+            color_format: self.color_format,
+            depth_format: self.depth_format,
+            blend: self.blend,
+            cull_mode: self.cull_mode,
+            topology: self.topology,
+            polygon_mode: self.polygon_mode,
+            depth_write_enabled: self.depth_write_enabled,
+            depth_compare: self.depth_compare,
+            depth_mode: self.depth_mode,
+            sample_count: self.sample_count,
+        }
+    }
+
+    pub fn build(self, device: &wgpu::Device) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(self.shader);
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(self.label),
+            layout: Some(self.layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: self.vertex_layouts,
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: self.color_format,
+                    blend: Some(self.blend.to_blend_state()),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: self.topology,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: self.cull_mode,
+                polygon_mode: self.polygon_mode,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: self.depth_format.map(|format| wgpu::DepthStencilState {
+                format,
+                depth_write_enabled: self.depth_write_enabled,
+                depth_compare: self.depth_mode.compare(self.depth_compare),
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: self.sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+}
+
+// The layout is kept as a hash of the layout object rather than a reference, so
+// the descriptor can outlive the builder.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PipelineDesc {
+    label: String,
+    layout: u64,
+    shader_label: Option<String>,
+    shader_source: Option<String>,
+    vertex_layouts: Vec<(wgpu::BufferAddress, wgpu::VertexStepMode, Vec<wgpu::VertexAttribute>)>,
+    color_format: wgpu::TextureFormat,
+    depth_format: Option<wgpu::TextureFormat>,
+    blend: BlendMode,
+    cull_mode: Option<wgpu::Face>,
+    topology: wgpu::PrimitiveTopology,
+    polygon_mode: wgpu::PolygonMode,
+    depth_write_enabled: bool,
+    depth_compare: wgpu::CompareFunction,
+    depth_mode: DepthMode,
+    sample_count: u32,
+}
+
+impl PipelineDesc {
+    pub fn key(&self) -> PipelineKey {
+        let mut hasher = DefaultHasher::new();
+        self.hash(&mut hasher);
+        PipelineKey(hasher.finish())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PipelineKey(u64);
+
+struct CachedPipeline {
+    desc: PipelineDesc,
+    pipeline: wgpu::RenderPipeline,
+}
+
+#[derive(Default)]
+pub struct PipelineCache {
+    pipelines: HashMap<PipelineKey, CachedPipeline>,
+}
+
+impl PipelineCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Builds the pipeline only if an identical descriptor hasn't been built before.
+    // Two descriptors hashing to the same key get neighbouring keys instead.
+    pub fn get_or_create(&mut self, device: &wgpu::Device, builder: PipelineBuilder) -> PipelineKey {
+        let desc = builder.desc();
+        let mut key = desc.key();
+        loop {
+            match self.pipelines.get(&key) {
+                Some(cached) if cached.desc == desc => return key,
+                Some(_) => {
+                    warn!("Pipeline key collision for '{}' ({:?})", builder.label, key);
+                    key = PipelineKey(key.0.wrapping_add(1));
+                }
+                None => break,
+            }
+        }
+        info!("Creating pipeline '{}' ({:?})", builder.label, key);
+        let pipeline = builder.build(device);
+        self.pipelines.insert(key, CachedPipeline { desc, pipeline });
+        key
+    }
+
+    // None if the key was removed, e.g. by a rebuild that replaced it.
+    pub fn get(&self, key: PipelineKey) -> Option<&wgpu::RenderPipeline> {
+        self.pipelines.get(&key).map(|cached| &cached.pipeline)
+    }
+
+    pub fn remove(&mut self, key: PipelineKey) -> Option<wgpu::RenderPipeline> {
+        self.pipelines.remove(&key).map(|cached| cached.pipeline)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHADER: &str = "
+        @vertex fn vs_main(@builtin(vertex_index) i: u32) -> @builtin(position) vec4<f32> {
+            return vec4<f32>(f32(i), 0.0, 0.0, 1.0);
+        }
+        @fragment fn fs_main() -> @location(0) vec4<f32> {
+            return vec4<f32>(1.0);
+        }
+    ";
+
+    // None when the machine has no adapter at all, in which case the tests
+    // have nothing to check against.
+    fn device() -> Option<wgpu::Device> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+        let adapter = pollster::block_on(instance.request_adapter(&Default::default()))?;
+        let (device, _queue) = pollster::block_on(adapter.request_device(&Default::default(), None)).ok()?;
+        Some(device)
+    }
+
+    fn layout(device: &wgpu::Device) -> wgpu::PipelineLayout {
+        device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Test Layout"),
+            bind_group_layouts: &[],
+            push_constant_ranges: &[],
+        })
+    }
+
+    fn builder(layout: &wgpu::PipelineLayout) -> PipelineBuilder<'_> {
+        let shader = wgpu::ShaderModuleDescriptor {
+            label: Some("Test Shader"),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        };
+        PipelineBuilder::new(layout, shader, &[], wgpu::TextureFormat::Rgba8Unorm)
+            .depth_format(Some(wgpu::TextureFormat::Depth32Float))
+    }
+
+    #[test]
+    fn keys_follow_settings() {
+        let Some(device) = device() else {
+            eprintln!("no adapter, skipping");
+            return;
+        };
+        let layout = layout(&device);
+        let key = builder(&layout).desc().key();
+        assert_eq!(key, builder(&layout).desc().key());
+
+        let variants = [
+            builder(&layout).blend(BlendMode::Alpha),
+            builder(&layout).cull_mode(None),
+            builder(&layout).sample_count(4),
+            builder(&layout).depth_mode(DepthMode::ReverseZ),
+        ];
+        for variant in variants {
+            assert_ne!(key, variant.desc().key());
+        }
+    }
+
+    #[test]
+    fn cache_reuses_matching_pipelines() {
+        let Some(device) = device() else {
+            eprintln!("no adapter, skipping");
+            return;
+        };
+        let layout = layout(&device);
+        let mut cache = PipelineCache::new();
+        let opaque = cache.get_or_create(&device, builder(&layout));
+        assert_eq!(opaque, cache.get_or_create(&device, builder(&layout)));
+        let blended = cache.get_or_create(&device, builder(&layout).blend(BlendMode::Alpha));
+        assert_ne!(opaque, blended);
+
+        assert!(cache.remove(opaque).is_some());
+        assert!(cache.get(opaque).is_none());
+        assert!(cache.get(blended).is_some());
+    }
+}
@@ -0,0 +1,311 @@
+use cgmath::{Point3, Vector3, Zero};
+
+use crate::camera::Camera;
+use crate::culling::Aabb;
+use crate::structs::block::{self, Chunk};
+
+// Player box size in blocks, measured from the feet.
+pub const PLAYER_WIDTH: f32 = 0.6;
+pub const PLAYER_HEIGHT: f32 = 1.8;
+pub const EYE_HEIGHT: f32 = 1.62;
+const GRAVITY: f32 = 25.0;
+const JUMP_SPEED: f32 = 8.0;
+const TERMINAL_VELOCITY: f32 = 50.0;
+// Keeps the box from sitting exactly on a block face, where rounding would
+// count the neighbouring block as overlapping.
+const SKIN: f32 = 1e-3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayerMode {
+    // Gravity and collision against solid blocks.
+    Walk,
+    // Free flight through everything.
+    Noclip,
+}
+
+// What the controls ask for this frame. `direction` is the horizontal velocity
+// in world space, already scaled to the wanted speed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlayerInput {
+    pub direction: Vector3<f32>,
+    pub jump: bool,
+    pub crouch: bool,
+    // Noclip flies up and down at this speed while jump or crouch is held.
+    pub climb_speed: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Player {
+    // Center of the bottom face of the box.
+    pub position: Point3<f32>,
+    pub velocity: Vector3<f32>,
+    pub on_ground: bool,
+    pub mode: PlayerMode,
+}
+
+impl Player {
+    pub fn new(position: Point3<f32>) -> Self {
+        Self {
+            position,
+            velocity: Vector3::zero(),
+            on_ground: false,
+            mode: PlayerMode::Walk,
+        }
+    }
+
+    // Puts the player's eyes where the camera is.
+    pub fn from_camera(camera: &Camera) -> Self {
+        Self::new(camera.position - Vector3::unit_y() * EYE_HEIGHT)
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        player_aabb(self.position)
+    }
+
+    pub fn eye_position(&self) -> Point3<f32> {
+        self.position + Vector3::unit_y() * EYE_HEIGHT
+    }
+
+    pub fn toggle_noclip(&mut self) {
+        self.mode = match self.mode {
+            PlayerMode::Walk => PlayerMode::Noclip,
+            PlayerMode::Noclip => PlayerMode::Walk,
+        };
+        self.velocity = Vector3::zero();
+        self.on_ground = false;
+    }
+
+    pub fn update(&mut self, chunks: &[Chunk], input: PlayerInput, dt: f32) {
+        match self.mode {
+            PlayerMode::Noclip => {
+                let climb = input.jump as i32 - input.crouch as i32;
+                self.velocity = input.direction + Vector3::unit_y() * input.climb_speed * climb as f32;
+                self.position += self.velocity * dt;
+            }
+            PlayerMode::Walk => {
+                self.velocity.x = input.direction.x;
+                self.velocity.z = input.direction.z;
+                if input.jump && self.on_ground {
+                    self.velocity.y = JUMP_SPEED;
+                }
+                self.velocity.y = (self.velocity.y - GRAVITY * dt).max(-TERMINAL_VELOCITY);
+
+                let (moved, hit) = move_and_collide(chunks, &self.aabb(), self.velocity * dt);
+                self.position += moved;
+                self.on_ground = hit[1] && self.velocity.y <= 0.0;
+                for (axis, blocked) in hit.into_iter().enumerate() {
+                    if blocked {
+                        self.velocity[axis] = 0.0;
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn attach_camera(&self, camera: &mut Camera) {
+        camera.position = self.eye_position();
+    }
+}
+
+pub fn player_aabb(feet: Point3<f32>) -> Aabb {
+    let half = PLAYER_WIDTH * 0.5;
+    Aabb::new(
+        Point3::new(feet.x - half, feet.y, feet.z - half),
+        Point3::new(feet.x + half, feet.y + PLAYER_HEIGHT, feet.z + half),
+    )
+}
+
+// Moves the box by `delta` one axis at a time (Y first so landing wins over
+// sliding into walls), stopping at solid blocks. Returns the distance actually
+// moved and which axes were blocked.
+pub fn move_and_collide(chunks: &[Chunk], aabb: &Aabb, delta: Vector3<f32>) -> (Vector3<f32>, [bool; 3]) {
+    let mut aabb = *aabb;
+    let mut moved = Vector3::zero();
+    let mut hit = [false; 3];
+    for axis in [1, 0, 2] {
+        let allowed = sweep_axis(chunks, &aabb, axis, delta[axis]);
+        hit[axis] = allowed != delta[axis];
+        aabb.min[axis] += allowed;
+        aabb.max[axis] += allowed;
+        moved[axis] = allowed;
+    }
+    (moved, hit)
+}
+
+// How far the box can travel along one axis before touching a solid block.
+// Blocks the box already overlaps are ignored so a stuck player can get out.
+pub fn sweep_axis(chunks: &[Chunk], aabb: &Aabb, axis: usize, delta: f32) -> f32 {
+    if delta == 0.0 {
+        return 0.0;
+    }
+    let mut min = aabb.min;
+    let mut max = aabb.max;
+    if delta > 0.0 {
+        max[axis] += delta;
+    } else {
+        min[axis] += delta;
+    }
+
+    let range = |i: usize| (min[i].floor() as i32)..=((max[i] - SKIN).floor() as i32);
+    let mut allowed = delta;
+    for y in range(1) {
+        for z in range(2) {
+            for x in range(0) {
+                if !block::is_solid_at(chunks, x, y, z) {
+                    continue;
+                }
+                let block_min = [x as f32, y as f32, z as f32];
+                // Must actually overlap on the other two axes, not just touch.
+                let overlaps = (0..3).filter(|&i| i != axis).all(|i| {
+                    aabb.max[i] - SKIN > block_min[i] && aabb.min[i] + SKIN < block_min[i] + 1.0
+                });
+                if !overlaps {
+                    continue;
+                }
+                if delta > 0.0 {
+                    let gap = block_min[axis] - aabb.max[axis];
+                    if gap >= -SKIN {
+                        allowed = allowed.min((gap - SKIN).max(0.0));
+                    }
+                } else {
+                    let gap = block_min[axis] + 1.0 - aabb.min[axis];
+                    if gap <= SKIN {
+                        allowed = allowed.max((gap + SKIN).min(0.0));
+                    }
+                }
+            }
+        }
+    }
+    allowed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::block::{Block, BlockType};
+
+    const DT: f32 = 1.0 / 60.0;
+    const FLOOR_TOP: f32 = 5.0;
+    const WALL_X: f32 = 10.0;
+    const STEP_Z: f32 = 12.0;
+
+    // One chunk: a floor whose top is at y = 5, a wall three blocks high along
+    // x = 10, and a one block step along z = 12.
+    fn world() -> Vec<Chunk> {
+        let mut chunk = Chunk::new((0, 0));
+        let solid = Block::solid(BlockType::BlockType_Grass);
+        for a in 0..16 {
+            for b in 0..16 {
+                chunk.set_block(a, 4, b, solid);
+            }
+            for y in 5..8 {
+                chunk.set_block(WALL_X as usize, y, a, solid);
+            }
+            chunk.set_block(a, 5, STEP_Z as usize, solid);
+        }
+        vec![chunk]
+    }
+
+    fn walk(direction: [f32; 3]) -> PlayerInput {
+        PlayerInput {
+            direction: Vector3::from(direction),
+            jump: false,
+            crouch: false,
+            climb_speed: 2.0,
+        }
+    }
+
+    fn run(player: &mut Player, chunks: &[Chunk], input: PlayerInput, seconds: f32) {
+        for _ in 0..(seconds / DT).round() as usize {
+            player.update(chunks, input, DT);
+        }
+    }
+
+    fn grounded(x: f32, z: f32, chunks: &[Chunk]) -> Player {
+        let mut player = Player::new(Point3::new(x, FLOOR_TOP + 0.5, z));
+        run(&mut player, chunks, walk([0.0; 3]), 0.5);
+        assert!(player.on_ground);
+        player
+    }
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 0.01, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn lands_and_stays_grounded() {
+        let chunks = world();
+        let mut player = Player::new(Point3::new(5.5, 9.0, 5.5));
+        run(&mut player, &chunks, walk([0.0; 3]), 2.0);
+        assert!(player.on_ground);
+        assert_close(player.position.y, FLOOR_TOP);
+        assert_eq!(player.velocity.y, 0.0);
+
+        let landed = player.position;
+        for _ in 0..60 {
+            player.update(&chunks, walk([0.0; 3]), DT);
+            assert!(player.on_ground);
+        }
+        assert_eq!(player.position, landed);
+    }
+
+    #[test]
+    fn stops_flush_against_a_wall_and_slides() {
+        let chunks = world();
+        let mut player = grounded(5.5, 5.5, &chunks);
+        run(&mut player, &chunks, walk([5.0, 0.0, 0.0]), 2.0);
+        assert_close(player.aabb().max.x, WALL_X);
+        assert!(player.aabb().max.x <= WALL_X);
+
+        // Pushing diagonally into the wall keeps the z component.
+        let before = player.position;
+        run(&mut player, &chunks, walk([5.0, 0.0, 5.0]), 0.5);
+        assert_close(player.position.x, before.x);
+        assert_close(player.position.z, before.z + 2.5);
+        assert!(player.on_ground);
+    }
+
+    #[test]
+    fn step_blocks_walking_but_not_jumping() {
+        let chunks = world();
+        let mut player = grounded(3.5, 9.5, &chunks);
+        run(&mut player, &chunks, walk([0.0, 0.0, 4.0]), 2.0);
+        assert_close(player.aabb().max.z, STEP_Z);
+        assert_close(player.position.y, FLOOR_TOP);
+
+        let jump = PlayerInput { jump: true, ..walk([0.0, 0.0, 4.0]) };
+        player.update(&chunks, jump, DT);
+        assert!(!player.on_ground);
+        // The step is one block deep, so stop as soon as we land on it.
+        for _ in 0..60 {
+            player.update(&chunks, walk([0.0, 0.0, 4.0]), DT);
+            if player.on_ground {
+                break;
+            }
+        }
+        assert!(player.on_ground);
+        assert_close(player.position.y, FLOOR_TOP + 1.0);
+        assert!(player.aabb().min.z > STEP_Z);
+    }
+
+    #[test]
+    fn no_tunnelling_at_large_dt() {
+        let chunks = world();
+        let mut player = Player::new(Point3::new(5.5, 40.0, 5.5));
+        player.velocity.y = -TERMINAL_VELOCITY;
+        // A full second at terminal velocity moves well past the floor.
+        player.update(&chunks, walk([0.0; 3]), 1.0);
+        assert!(player.on_ground);
+        assert_close(player.position.y, FLOOR_TOP);
+    }
+
+    #[test]
+    fn noclip_ignores_blocks() {
+        let chunks = world();
+        let mut player = grounded(5.5, 5.5, &chunks);
+        player.toggle_noclip();
+        let down = PlayerInput { crouch: true, ..walk([0.0; 3]) };
+        run(&mut player, &chunks, down, 1.0);
+        assert_close(player.position.y, FLOOR_TOP - 2.0);
+    }
+}
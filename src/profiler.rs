@@ -0,0 +1,401 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context;
+use instant::Instant;
+use log::{info, warn};
+use serde_json::json;
+
+// Render passes that can be timed in one frame; each uses two queries.
+pub const MAX_PASSES: u32 = 8;
+// Longest trace kept in memory, about three minutes at 60 FPS.
+const MAX_TRACE_FRAMES: usize = 10_000;
+pub const TRACE_FILE: &str = "trace.json";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PassTiming {
+    pub name: &'static str,
+    // Microseconds since the profiler was created.
+    pub cpu_start_us: f64,
+    // Time spent recording the pass on the CPU.
+    pub cpu_ms: f32,
+    // Offset from the first timed pass in the frame; None without timestamp queries.
+    pub gpu_start_ms: Option<f32>,
+    pub gpu_ms: Option<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FrameProfile {
+    pub frame: u64,
+    pub start_us: f64,
+    pub passes: Vec<PassTiming>,
+}
+
+impl FrameProfile {
+    pub fn has_gpu_timing(&self) -> bool {
+        self.passes.iter().any(|pass| pass.gpu_ms.is_some())
+    }
+}
+
+// Returned by begin_pass and handed back to end_pass.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PassScope {
+    index: usize,
+    // Whether this pass writes timestamps.
+    gpu: bool,
+}
+
+struct Timestamps {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    // Nanoseconds per timestamp tick.
+    period: f32,
+    // Set by the map_async callback once the readback buffer can be read.
+    mapped: Arc<Mutex<Option<Result<(), wgpu::BufferAsyncError>>>>,
+    // The readback buffer holds or is waiting for a frame's results.
+    in_flight: bool,
+}
+
+// Per-pass timings for the frame being recorded. GPU timings use timestamp
+// queries written at the start and end of each pass and arrive a frame or so
+// later; without TIMESTAMP_QUERY, or on software adapters where the numbers
+// mean little, only CPU recording times are kept.
+pub struct GpuProfiler {
+    timestamps: Option<Timestamps>,
+    epoch: Instant,
+    frame: u64,
+    current: FrameProfile,
+    // Frame recorded with timestamps, waiting for the readback.
+    pending: Option<FrameProfile>,
+    // Last frame with everything filled in. With timestamp queries only frames
+    // that got GPU timings, so the overlay doesn't flicker to CPU-only numbers.
+    latest: Option<FrameProfile>,
+    pass_start: Option<Instant>,
+    // Frames kept for a Chrome trace while a capture is running.
+    trace: Option<Vec<FrameProfile>>,
+}
+
+impl GpuProfiler {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, adapter_info: &wgpu::AdapterInfo) -> Self {
+        let timestamps = if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            info!("Timestamp queries unavailable, profiling CPU time only");
+            None
+        } else if adapter_info.device_type == wgpu::DeviceType::Cpu {
+            info!("Software adapter '{}', profiling CPU time only", adapter_info.name);
+            None
+        } else {
+            Some(Timestamps::new(device, queue))
+        };
+        Self {
+            timestamps,
+            epoch: Instant::now(),
+            frame: 0,
+            current: FrameProfile::default(),
+            pending: None,
+            latest: None,
+            pass_start: None,
+            trace: None,
+        }
+    }
+
+    pub fn gpu_timing(&self) -> bool {
+        self.timestamps.is_some()
+    }
+
+    // Picks up results from earlier frames and starts a new one.
+    pub fn begin_frame(&mut self, device: &wgpu::Device) {
+        self.collect(device);
+        self.frame += 1;
+        self.current = FrameProfile {
+            frame: self.frame,
+            start_us: self.elapsed_us(),
+            passes: Vec::new(),
+        };
+    }
+
+    pub fn begin_pass(&mut self, name: &'static str) -> PassScope {
+        let index = self.current.passes.len();
+        // Skip the queries while last frame's results are still being read back.
+        let gpu = index < MAX_PASSES as usize
+            && self.timestamps.as_ref().is_some_and(|timestamps| !timestamps.in_flight);
+        self.current.passes.push(PassTiming {
+            name,
+            cpu_start_us: self.elapsed_us(),
+            cpu_ms: 0.0,
+            gpu_start_ms: None,
+            gpu_ms: None,
+        });
+        self.pass_start = Some(Instant::now());
+        PassScope { index, gpu }
+    }
+
+    // For the pass's RenderPassDescriptor.
+    pub fn timestamp_writes(&self, scope: PassScope) -> Option<wgpu::RenderPassTimestampWrites<'_>> {
+        let timestamps = self.timestamps.as_ref().filter(|_| scope.gpu)?;
+        Some(wgpu::RenderPassTimestampWrites {
+            query_set: &timestamps.query_set,
+            beginning_of_pass_write_index: Some(scope.index as u32 * 2),
+            end_of_pass_write_index: Some(scope.index as u32 * 2 + 1),
+        })
+    }
+
+    // Call once the pass has been dropped.
+    pub fn end_pass(&mut self, scope: PassScope) {
+        if let (Some(start), Some(pass)) = (self.pass_start.take(), self.current.passes.get_mut(scope.index)) {
+            pass.cpu_ms = start.elapsed().as_secs_f32() * 1000.0;
+        }
+    }
+
+    // Resolves this frame's queries into the readback buffer. Call before finishing the encoder.
+    pub fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let passes = self.timed_passes();
+        match &mut self.timestamps {
+            Some(timestamps) if passes > 0 && !timestamps.in_flight => {
+                let count = passes * 2;
+                encoder.resolve_query_set(&timestamps.query_set, 0..count, &timestamps.resolve_buffer, 0);
+                encoder.copy_buffer_to_buffer(
+                    &timestamps.resolve_buffer,
+                    0,
+                    &timestamps.readback_buffer,
+                    0,
+                    count as u64 * wgpu::QUERY_SIZE as u64,
+                );
+            }
+            _ => {}
+        }
+    }
+
+    // Call after the encoder is submitted.
+    pub fn end_frame(&mut self) {
+        let current = std::mem::take(&mut self.current);
+        let passes = self.timed_passes_in(&current);
+        match &mut self.timestamps {
+            Some(timestamps) if passes > 0 && !timestamps.in_flight => {
+                timestamps.in_flight = true;
+                let mapped = timestamps.mapped.clone();
+                let size = passes as u64 * 2 * wgpu::QUERY_SIZE as u64;
+                timestamps
+                    .readback_buffer
+                    .slice(..size)
+                    .map_async(wgpu::MapMode::Read, move |result| *mapped.lock().unwrap() = Some(result));
+                self.pending = Some(current);
+            }
+            _ => self.finish(current),
+        }
+    }
+
+    pub fn latest(&self) -> Option<&FrameProfile> {
+        self.latest.as_ref()
+    }
+
+    pub fn start_trace(&mut self) {
+        self.trace = Some(Vec::new());
+    }
+
+    // Frames captured since start_trace, or None if no capture was running.
+    pub fn stop_trace(&mut self) -> Option<Vec<FrameProfile>> {
+        self.trace.take()
+    }
+
+    fn timed_passes(&self) -> u32 {
+        self.timed_passes_in(&self.current)
+    }
+
+    // Passes are timed from the start of the frame until one isn't, see begin_pass.
+    fn timed_passes_in(&self, frame: &FrameProfile) -> u32 {
+        if self.timestamps.as_ref().is_none_or(|timestamps| timestamps.in_flight) {
+            return 0;
+        }
+        frame.passes.len().min(MAX_PASSES as usize) as u32
+    }
+
+    fn collect(&mut self, device: &wgpu::Device) {
+        let Some(timestamps) = &mut self.timestamps else {
+            return;
+        };
+        if !timestamps.in_flight {
+            return;
+        }
+        device.poll(wgpu::Maintain::Poll);
+        let Some(result) = timestamps.mapped.lock().unwrap().take() else {
+            return;
+        };
+        let mut frame = self.pending.take().unwrap_or_default();
+        match result {
+            Ok(()) => {
+                let passes = frame.passes.len().min(MAX_PASSES as usize);
+                let size = passes as u64 * 2 * wgpu::QUERY_SIZE as u64;
+                {
+                    let data = timestamps.readback_buffer.slice(..size).get_mapped_range();
+                    let ticks: &[u64] = bytemuck::cast_slice(&data);
+                    apply_timestamps(&mut frame, ticks, timestamps.period);
+                }
+                timestamps.readback_buffer.unmap();
+            }
+            Err(e) => warn!("Failed to read GPU timestamps: {}", e),
+        }
+        timestamps.in_flight = false;
+        self.finish(frame);
+    }
+
+    fn finish(&mut self, frame: FrameProfile) {
+        if let Some(trace) = &mut self.trace {
+            if trace.len() < MAX_TRACE_FRAMES {
+                insert_by_frame(trace, frame.clone());
+            }
+        }
+        if self.timestamps.is_none() || frame.has_gpu_timing() {
+            self.latest = Some(frame);
+        }
+    }
+
+    fn elapsed_us(&self) -> f64 {
+        self.epoch.elapsed().as_secs_f64() * 1_000_000.0
+    }
+}
+
+impl Timestamps {
+    fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let count = MAX_PASSES * 2;
+        let size = count as u64 * wgpu::QUERY_SIZE as u64;
+        Self {
+            query_set: device.create_query_set(&wgpu::QuerySetDescriptor {
+                label: Some("Pass Timestamps"),
+                ty: wgpu::QueryType::Timestamp,
+                count,
+            }),
+            resolve_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Timestamp Resolve Buffer"),
+                size,
+                usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            }),
+            readback_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Timestamp Readback Buffer"),
+                size,
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            period: queue.get_timestamp_period(),
+            mapped: Arc::new(Mutex::new(None)),
+            in_flight: false,
+        }
+    }
+}
+
+// Frames with timestamps finish a frame or so late, after the CPU-only frames
+// recorded meanwhile, so keep the trace ordered by frame number.
+fn insert_by_frame(frames: &mut Vec<FrameProfile>, frame: FrameProfile) {
+    let index = frames.partition_point(|other| other.frame <= frame.frame);
+    frames.insert(index, frame);
+}
+
+// Fills in gpu_start_ms and gpu_ms from begin/end tick pairs, one pair per pass.
+// A pair that runs backwards (some drivers wrap or reset) is left as None.
+pub fn apply_timestamps(frame: &mut FrameProfile, ticks: &[u64], period_ns: f32) {
+    let Some(&first) = ticks.first() else {
+        return;
+    };
+    let to_ms = |ticks: u64| (ticks as f64 * period_ns as f64 / 1_000_000.0) as f32;
+    for (pass, pair) in frame.passes.iter_mut().zip(ticks.chunks_exact(2)) {
+        if pair[1] >= pair[0] && pair[0] >= first {
+            pass.gpu_start_ms = Some(to_ms(pair[0] - first));
+            pass.gpu_ms = Some(to_ms(pair[1] - pair[0]));
+        }
+    }
+}
+
+// Chrome's trace event format (chrome://tracing, Perfetto): one complete event
+// per pass, CPU recording on one track and GPU execution on another. GPU events
+// are placed relative to their frame's CPU start, since the clocks differ.
+pub fn chrome_trace(frames: &[FrameProfile]) -> serde_json::Value {
+    let mut events = vec![
+        json!({ "name": "thread_name", "ph": "M", "pid": 1, "tid": 1, "args": { "name": "CPU" } }),
+        json!({ "name": "thread_name", "ph": "M", "pid": 1, "tid": 2, "args": { "name": "GPU" } }),
+    ];
+    for frame in frames {
+        for pass in &frame.passes {
+            events.push(json!({
+                "name": pass.name,
+                "cat": "cpu",
+                "ph": "X",
+                "pid": 1,
+                "tid": 1,
+                "ts": pass.cpu_start_us,
+                "dur": pass.cpu_ms as f64 * 1000.0,
+                "args": { "frame": frame.frame },
+            }));
+            if let (Some(start), Some(duration)) = (pass.gpu_start_ms, pass.gpu_ms) {
+                events.push(json!({
+                    "name": pass.name,
+                    "cat": "gpu",
+                    "ph": "X",
+                    "pid": 1,
+                    "tid": 2,
+                    "ts": frame.start_us + start as f64 * 1000.0,
+                    "dur": duration as f64 * 1000.0,
+                    "args": { "frame": frame.frame },
+                }));
+            }
+        }
+    }
+    json!({ "traceEvents": events, "displayTimeUnit": "ms" })
+}
+
+pub fn write_chrome_trace<P: AsRef<Path>>(path: P, frames: &[FrameProfile]) -> anyhow::Result<()> {
+    let path = path.as_ref();
+    let text = serde_json::to_string(&chrome_trace(frames))?;
+    std::fs::write(path, text).with_context(|| format!("Failed to write trace {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(frame: u64, gpu_ms: &[Option<f32>]) -> FrameProfile {
+        FrameProfile {
+            frame,
+            start_us: 0.0,
+            passes: gpu_ms
+                .iter()
+                .map(|&gpu_ms| PassTiming {
+                    name: "pass",
+                    cpu_start_us: 0.0,
+                    cpu_ms: 1.0,
+                    gpu_start_ms: gpu_ms.map(|_| 0.0),
+                    gpu_ms,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn late_frames_are_inserted_in_order() {
+        let mut frames = Vec::new();
+        for number in [2, 3, 1, 5, 4] {
+            insert_by_frame(&mut frames, frame(number, &[]));
+        }
+        assert_eq!(frames.iter().map(|frame| frame.frame).collect::<Vec<_>>(), [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn gpu_timing_needs_at_least_one_timed_pass() {
+        assert!(!frame(1, &[]).has_gpu_timing());
+        assert!(!frame(1, &[None, None]).has_gpu_timing());
+        assert!(frame(1, &[None, Some(0.5)]).has_gpu_timing());
+    }
+
+    #[test]
+    fn timestamps_become_offsets_and_durations() {
+        let mut profile = frame(1, &[None, None, None]);
+        // 2 ns per tick; the last pair runs backwards.
+        apply_timestamps(&mut profile, &[1_000_000, 1_500_000, 2_000_000, 3_000_000, 900, 800], 2.0);
+        let timings = profile
+            .passes
+            .iter()
+            .map(|pass| (pass.gpu_start_ms, pass.gpu_ms))
+            .collect::<Vec<_>>();
+        assert_eq!(timings, [(Some(0.0), Some(1.0)), (Some(2.0), Some(2.0)), (None, None)]);
+    }
+}
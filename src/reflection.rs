@@ -0,0 +1,403 @@
+use wgpu::naga;
+
+use crate::model::{self, Vertex};
+use crate::rendering;
+use crate::shader_reload::{self, ShaderFile};
+use crate::vertex::InstanceRaw;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReflectedBinding {
+    pub name: String,
+    pub group: u32,
+    pub binding: u32,
+    pub ty: wgpu::BindingType,
+    // Stages whose entry points actually touch the binding.
+    pub visibility: wgpu::ShaderStages,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReflectedVertexInput {
+    pub name: String,
+    pub location: u32,
+    pub kind: naga::ScalarKind,
+    pub components: u32,
+}
+
+#[derive(Debug, Default)]
+pub struct ShaderReflection {
+    pub bindings: Vec<ReflectedBinding>,
+    // Inputs of each vertex entry point, keyed by entry point name.
+    pub vertex_inputs: Vec<(String, Vec<ReflectedVertexInput>)>,
+}
+
+impl ShaderReflection {
+    pub fn group(&self, group: u32) -> impl Iterator<Item = &ReflectedBinding> {
+        self.bindings.iter().filter(move |b| b.group == group)
+    }
+
+    pub fn vertex_inputs(&self, entry_point: &str) -> Option<&[ReflectedVertexInput]> {
+        self.vertex_inputs
+            .iter()
+            .find(|(name, _)| name == entry_point)
+            .map(|(_, inputs)| inputs.as_slice())
+    }
+
+    // Layout entries that would satisfy the shader, for groups we don't want to write by hand.
+    #[allow(unused)]
+    pub fn derive_bind_group_layout_entries(&self, group: u32) -> Vec<wgpu::BindGroupLayoutEntry> {
+        let mut entries = self
+            .group(group)
+            .map(|b| wgpu::BindGroupLayoutEntry {
+                binding: b.binding,
+                visibility: b.visibility,
+                ty: b.ty,
+                count: None,
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|e| e.binding);
+        entries
+    }
+}
+
+pub fn reflect_wgsl(source: &str) -> Result<ShaderReflection, String> {
+    let (module, info) = shader_reload::validate_wgsl(source)?;
+    let mut reflection = ShaderReflection::default();
+
+    for (handle, var) in module.global_variables.iter() {
+        let Some(resource) = &var.binding else {
+            continue;
+        };
+        let ty = binding_type(&module, var)
+            .ok_or_else(|| format!("unsupported binding type for {:?}", var.name))?;
+        let mut visibility = wgpu::ShaderStages::NONE;
+        for (i, entry_point) in module.entry_points.iter().enumerate() {
+            if !info.get_entry_point(i)[handle].is_empty() {
+                visibility |= shader_stage(entry_point.stage);
+            }
+        }
+        reflection.bindings.push(ReflectedBinding {
+            name: var.name.clone().unwrap_or_default(),
+            group: resource.group,
+            binding: resource.binding,
+            ty,
+            visibility,
+        });
+    }
+
+    for entry_point in &module.entry_points {
+        if entry_point.stage != naga::ShaderStage::Vertex {
+            continue;
+        }
+        let mut inputs = Vec::new();
+        for argument in &entry_point.function.arguments {
+            let name = argument.name.clone().unwrap_or_default();
+            collect_vertex_inputs(&module, &name, argument.ty, argument.binding.as_ref(), &mut inputs);
+        }
+        inputs.sort_by_key(|input| input.location);
+        reflection.vertex_inputs.push((entry_point.name.clone(), inputs));
+    }
+
+    Ok(reflection)
+}
+
+fn shader_stage(stage: naga::ShaderStage) -> wgpu::ShaderStages {
+    match stage {
+        naga::ShaderStage::Vertex => wgpu::ShaderStages::VERTEX,
+        naga::ShaderStage::Fragment => wgpu::ShaderStages::FRAGMENT,
+        naga::ShaderStage::Compute => wgpu::ShaderStages::COMPUTE,
+    }
+}
+
+fn binding_type(module: &naga::Module, var: &naga::GlobalVariable) -> Option<wgpu::BindingType> {
+    match var.space {
+        naga::AddressSpace::Uniform => Some(wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        }),
+        naga::AddressSpace::Storage { access } => Some(wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage {
+                read_only: !access.contains(naga::StorageAccess::STORE),
+            },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        }),
+        naga::AddressSpace::Handle => match module.types[var.ty].inner {
+            naga::TypeInner::Sampler { comparison } => Some(wgpu::BindingType::Sampler(if comparison {
+                wgpu::SamplerBindingType::Comparison
+            } else {
+                wgpu::SamplerBindingType::Filtering
+            })),
+            naga::TypeInner::Image { dim, arrayed, class } => {
+                let view_dimension = match (dim, arrayed) {
+                    (naga::ImageDimension::D1, _) => wgpu::TextureViewDimension::D1,
+                    (naga::ImageDimension::D2, false) => wgpu::TextureViewDimension::D2,
+                    (naga::ImageDimension::D2, true) => wgpu::TextureViewDimension::D2Array,
+                    (naga::ImageDimension::D3, _) => wgpu::TextureViewDimension::D3,
+                    (naga::ImageDimension::Cube, false) => wgpu::TextureViewDimension::Cube,
+                    (naga::ImageDimension::Cube, true) => wgpu::TextureViewDimension::CubeArray,
+                };
+                match class {
+                    naga::ImageClass::Sampled { kind, multi } => Some(wgpu::BindingType::Texture {
+                        sample_type: match kind {
+                            naga::ScalarKind::Sint => wgpu::TextureSampleType::Sint,
+                            naga::ScalarKind::Uint => wgpu::TextureSampleType::Uint,
+                            _ => wgpu::TextureSampleType::Float { filterable: !multi },
+                        },
+                        view_dimension,
+                        multisampled: multi,
+                    }),
+                    naga::ImageClass::Depth { multi } => Some(wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension,
+                        multisampled: multi,
+                    }),
+                    naga::ImageClass::Storage { .. } => None,
+                }
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn collect_vertex_inputs(
+    module: &naga::Module,
+    name: &str,
+    ty: naga::Handle<naga::Type>,
+    binding: Option<&naga::Binding>,
+    inputs: &mut Vec<ReflectedVertexInput>,
+) {
+    match (&module.types[ty].inner, binding) {
+        (naga::TypeInner::Struct { members, .. }, None) => {
+            for member in members {
+                let name = member.name.clone().unwrap_or_default();
+                collect_vertex_inputs(module, &name, member.ty, member.binding.as_ref(), inputs);
+            }
+        }
+        (inner, Some(naga::Binding::Location { location, .. })) => {
+            let (kind, components) = match *inner {
+                naga::TypeInner::Scalar(scalar) => (scalar.kind, 1),
+                naga::TypeInner::Vector { size, scalar } => (scalar.kind, size as u32),
+                _ => return,
+            };
+            inputs.push(ReflectedVertexInput {
+                name: name.to_string(),
+                location: *location,
+                kind,
+                components,
+            });
+        }
+        // Builtins like vertex_index don't come from a vertex buffer.
+        _ => {}
+    }
+}
+
+fn same_binding_kind(shader: &wgpu::BindingType, layout: &wgpu::BindingType) -> bool {
+    use wgpu::{BindingType, SamplerBindingType, TextureSampleType};
+    match (shader, layout) {
+        (BindingType::Buffer { ty: a, .. }, BindingType::Buffer { ty: b, .. }) => a == b,
+        (BindingType::Sampler(a), BindingType::Sampler(b)) => {
+            (*a == SamplerBindingType::Comparison) == (*b == SamplerBindingType::Comparison)
+        }
+        (
+            BindingType::Texture { sample_type: a, view_dimension: da, multisampled: ma },
+            BindingType::Texture { sample_type: b, view_dimension: db, multisampled: mb },
+        ) => {
+            let same_sample_type = matches!(
+                (a, b),
+                (TextureSampleType::Float { .. }, TextureSampleType::Float { .. })
+                    | (TextureSampleType::Depth, TextureSampleType::Depth)
+                    | (TextureSampleType::Sint, TextureSampleType::Sint)
+                    | (TextureSampleType::Uint, TextureSampleType::Uint)
+            );
+            same_sample_type && da == db && ma == mb
+        }
+        _ => false,
+    }
+}
+
+// Every binding the shader declares in `group` must exist in `entries` with a
+// matching type and visibility. Extra layout entries are allowed.
+pub fn check_bind_group_layout(
+    reflection: &ShaderReflection,
+    group: u32,
+    entries: &[wgpu::BindGroupLayoutEntry],
+) -> Vec<String> {
+    let mut errors = Vec::new();
+    for binding in reflection.group(group) {
+        let Some(entry) = entries.iter().find(|e| e.binding == binding.binding) else {
+            errors.push(format!(
+                "@group({}) @binding({}) '{}' is missing from the layout",
+                group, binding.binding, binding.name
+            ));
+            continue;
+        };
+        if !same_binding_kind(&binding.ty, &entry.ty) {
+            errors.push(format!(
+                "@group({}) @binding({}) '{}' is {:?} in WGSL but {:?} in the layout",
+                group, binding.binding, binding.name, binding.ty, entry.ty
+            ));
+        }
+        if !entry.visibility.contains(binding.visibility) {
+            errors.push(format!(
+                "@group({}) @binding({}) '{}' is used in {:?} but only visible to {:?}",
+                group, binding.binding, binding.name, binding.visibility, entry.visibility
+            ));
+        }
+    }
+    errors
+}
+
+// Every shader input location must be fed by some attribute of the same scalar kind.
+pub fn check_vertex_layouts(
+    reflection: &ShaderReflection,
+    entry_point: &str,
+    layouts: &[wgpu::VertexBufferLayout],
+) -> Vec<String> {
+    let Some(inputs) = reflection.vertex_inputs(entry_point) else {
+        return vec![format!("no vertex entry point named '{}'", entry_point)];
+    };
+    let mut errors = Vec::new();
+    for input in inputs {
+        let attribute = layouts
+            .iter()
+            .flat_map(|layout| layout.attributes.iter())
+            .find(|attribute| attribute.shader_location == input.location);
+        let Some(attribute) = attribute else {
+            errors.push(format!(
+                "@location({}) '{}' has no matching vertex attribute",
+                input.location, input.name
+            ));
+            continue;
+        };
+        if vertex_format_kind(attribute.format) != Some(input.kind) {
+            errors.push(format!(
+                "@location({}) '{}' is {:?} in WGSL but {:?} in the vertex layout",
+                input.location, input.name, input.kind, attribute.format
+            ));
+        }
+    }
+    errors
+}
+
+fn vertex_format_kind(format: wgpu::VertexFormat) -> Option<naga::ScalarKind> {
+    use wgpu::VertexFormat::*;
+    match format {
+        Uint8x2 | Uint8x4 | Uint16x2 | Uint16x4 | Uint32 | Uint32x2 | Uint32x3 | Uint32x4 => {
+            Some(naga::ScalarKind::Uint)
+        }
+        Sint8x2 | Sint8x4 | Sint16x2 | Sint16x4 | Sint32 | Sint32x2 | Sint32x3 | Sint32x4 => {
+            Some(naga::ScalarKind::Sint)
+        }
+        Float64 | Float64x2 | Float64x3 | Float64x4 => None,
+        _ => Some(naga::ScalarKind::Float),
+    }
+}
+
+// Checks a shader against the bind group layouts (in group order) and vertex
+// buffers of the pipeline it is built into.
+pub fn check_shader(
+    source: &str,
+    groups: &[&[wgpu::BindGroupLayoutEntry]],
+    vertex_layouts: &[wgpu::VertexBufferLayout],
+) -> Vec<String> {
+    let reflection = match reflect_wgsl(source) {
+        Ok(reflection) => reflection,
+        Err(e) => return vec![e],
+    };
+    let mut errors = Vec::new();
+    for (group, entries) in groups.iter().enumerate() {
+        errors.extend(check_bind_group_layout(&reflection, group as u32, entries));
+    }
+    if let Some(binding) = reflection.bindings.iter().find(|b| b.group as usize >= groups.len()) {
+        errors.push(format!("@group({}) '{}' has no bind group layout", binding.group, binding.name));
+    }
+    errors.extend(check_vertex_layouts(&reflection, "vs_main", vertex_layouts));
+    errors
+}
+
+pub fn check_scene_shader(source: &str) -> Vec<String> {
+    check_shader(
+        source,
+        &[
+            rendering::TEXTURE_BIND_GROUP_LAYOUT_ENTRIES,
+            rendering::CAMERA_BIND_GROUP_LAYOUT_ENTRIES,
+            rendering::LIGHT_BIND_GROUP_LAYOUT_ENTRIES,
+        ],
+        &[model::ModelVertex::desc(), InstanceRaw::desc()],
+    )
+}
+
+pub fn check_light_shader(source: &str) -> Vec<String> {
+    check_shader(
+        source,
+        &[
+            rendering::CAMERA_BIND_GROUP_LAYOUT_ENTRIES,
+            rendering::LIGHT_BIND_GROUP_LAYOUT_ENTRIES,
+        ],
+        &[model::ModelVertex::desc()],
+    )
+}
+
+pub fn check_shader_file(file: ShaderFile, source: &str) -> Vec<String> {
+    match file {
+        ShaderFile::Scene => check_scene_shader(source),
+        ShaderFile::Light => check_light_shader(source),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCENE_SHADER: &str = include_str!("shaders/shader.wgsl");
+    const LIGHT_SHADER: &str = include_str!("shaders/light.wgsl");
+
+    fn edited(from: &str, to: &str) -> String {
+        assert!(SCENE_SHADER.contains(from), "shader no longer contains {:?}", from);
+        SCENE_SHADER.replacen(from, to, 1)
+    }
+
+    #[test]
+    fn shipped_shaders_match_their_layouts() {
+        assert_eq!(check_scene_shader(SCENE_SHADER), Vec::<String>::new());
+        assert_eq!(check_light_shader(LIGHT_SHADER), Vec::<String>::new());
+    }
+
+    #[test]
+    fn changed_binding_type_is_reported() {
+        let errors = check_scene_shader(&edited("var<uniform> material", "var<storage, read> material"));
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert!(errors[0].starts_with("@group(0) @binding(2) 'material' is Buffer"), "{}", errors[0]);
+    }
+
+    #[test]
+    fn changed_binding_index_is_reported() {
+        let errors = check_scene_shader(&edited("@group(0) @binding(2)", "@group(0) @binding(3)"));
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert!(errors[0].contains("'material' is missing from the layout"), "{}", errors[0]);
+    }
+
+    #[test]
+    fn derived_layouts_match_the_shipped_ones() {
+        let reflection = reflect_wgsl(SCENE_SHADER).unwrap();
+        let shipped = [
+            rendering::TEXTURE_BIND_GROUP_LAYOUT_ENTRIES,
+            rendering::CAMERA_BIND_GROUP_LAYOUT_ENTRIES,
+            rendering::LIGHT_BIND_GROUP_LAYOUT_ENTRIES,
+        ];
+        for (group, entries) in shipped.iter().enumerate() {
+            // The shipped layouts are sometimes visible to more stages than use them.
+            let derived = reflection.derive_bind_group_layout_entries(group as u32);
+            let derived = derived.iter().map(|e| (e.binding, e.ty)).collect::<Vec<_>>();
+            let shipped = entries.iter().map(|e| (e.binding, e.ty)).collect::<Vec<_>>();
+            assert_eq!(derived, shipped, "group {}", group);
+        }
+    }
+
+    #[test]
+    fn invalid_wgsl_is_reported() {
+        assert_eq!(check_scene_shader(&edited("fn fs_main", "fn fs_main(")).len(), 1);
+    }
+}
@@ -0,0 +1,261 @@
+use cgmath::{MetricSpace, Point3};
+
+use crate::culling::{self, BoundingSphere, CullStats, Frustum};
+use crate::model::Model;
+use crate::structs::block::Chunk;
+use crate::vertex::Instanced;
+
+// One transparent mesh drawn for one instance. Transparent geometry has to be
+// drawn one instance at a time so it can be ordered against everything else.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransparentDraw {
+    pub mesh: usize,
+    // Slot in the culled instance buffer, not the index into the full instance list.
+    pub instance: u32,
+    pub distance_sq: f32,
+}
+
+// What culling and sorting need from a mesh, so the queue can be built
+// without any GPU resources.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MeshInfo {
+    // None when the model has no such material; the mesh is then never queued.
+    pub material: Option<usize>,
+    pub transparent: bool,
+    pub bounding_sphere: BoundingSphere,
+}
+
+#[derive(Debug, Default)]
+pub struct RenderQueue {
+    // Instances that survived culling, in the order they are uploaded to the instance buffer.
+    pub visible_instances: Vec<usize>,
+    // Opaque meshes are drawn instanced over every visible instance, grouped by
+    // material so consecutive draws share a bind group.
+    pub opaque: Vec<usize>,
+    // Sorted back-to-front, drawn after the opaque pass.
+    pub transparent: Vec<TransparentDraw>,
+    pub visible_chunks: Vec<usize>,
+    pub stats: CullStats,
+}
+
+impl RenderQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.visible_instances.clear();
+        self.opaque.clear();
+        self.transparent.clear();
+        self.visible_chunks.clear();
+        self.stats = CullStats::default();
+    }
+
+    pub fn build(&mut self, model: &Model, instances: &[Instanced], eye: Point3<f32>, frustum: &Frustum) {
+        let meshes = model
+            .meshes
+            .iter()
+            .map(|mesh| {
+                let material = model.materials.get(mesh.material);
+                MeshInfo {
+                    material: material.map(|_| mesh.material),
+                    transparent: material.is_some_and(|material| material.is_transparent()),
+                    bounding_sphere: mesh.bounding_sphere,
+                }
+            })
+            .collect::<Vec<_>>();
+        self.build_meshes(&meshes, instances, eye, frustum);
+    }
+
+    pub fn build_meshes(&mut self, meshes: &[MeshInfo], instances: &[Instanced], eye: Point3<f32>, frustum: &Frustum) {
+        self.clear();
+
+        // Whole-model test per instance first, then each mesh against the survivors.
+        let model_sphere = meshes
+            .iter()
+            .map(|mesh| mesh.bounding_sphere)
+            .reduce(|a, b| a.union(&b));
+        if let Some(model_sphere) = model_sphere {
+            self.visible_instances = instances
+                .iter()
+                .enumerate()
+                .filter(|(_, instance)| {
+                    frustum.intersects_sphere(&model_sphere.transformed(instance.position, instance.rotation))
+                })
+                .map(|(i, _)| i)
+                .collect();
+        }
+
+        let mut transparent = Vec::new();
+        for (mesh_index, mesh) in meshes.iter().enumerate() {
+            if mesh.material.is_none() {
+                continue;
+            }
+            let mut mesh_visible = false;
+            for (slot, &instance_index) in self.visible_instances.iter().enumerate() {
+                let instance = &instances[instance_index];
+                let sphere = mesh.bounding_sphere.transformed(instance.position, instance.rotation);
+                if !frustum.intersects_sphere(&sphere) {
+                    continue;
+                }
+                mesh_visible = true;
+                if !mesh.transparent {
+                    // Opaque meshes are drawn over all visible instances anyway.
+                    break;
+                }
+                transparent.push((mesh_index, slot as u32, sphere.center));
+            }
+            if mesh_visible {
+                self.stats.meshes_visible += 1;
+                if !mesh.transparent {
+                    self.opaque.push(mesh_index);
+                }
+            }
+        }
+        // Stable, so meshes sharing a material stay in model order.
+        self.opaque.sort_by_key(|&mesh_index| meshes[mesh_index].material);
+        self.transparent = queue_transparent(transparent, eye);
+
+        self.stats.instances_total = instances.len() as u32;
+        self.stats.instances_visible = self.visible_instances.len() as u32;
+        self.stats.meshes_total = meshes.len() as u32;
+    }
+
+    pub fn cull_chunks(&mut self, chunks: &[Chunk], frustum: &Frustum) {
+        let bounds = chunks.iter().map(Chunk::bounds).collect::<Vec<_>>();
+        self.visible_chunks = culling::cull_aabbs(frustum, &bounds);
+        self.stats.chunks_total = chunks.len() as u32;
+        self.stats.chunks_visible = self.visible_chunks.len() as u32;
+    }
+}
+
+// Turns (mesh, instance slot, world-space center) triples into draws sorted so
+// the farthest comes first.
+pub fn queue_transparent(
+    items: impl IntoIterator<Item = (usize, u32, Point3<f32>)>,
+    eye: Point3<f32>,
+) -> Vec<TransparentDraw> {
+    let mut draws = items
+        .into_iter()
+        .map(|(mesh, instance, center)| TransparentDraw {
+            mesh,
+            instance,
+            distance_sq: center.distance2(eye),
+        })
+        .collect::<Vec<_>>();
+    sort_back_to_front(&mut draws);
+    draws
+}
+
+// Stable so meshes at the same distance keep their model order.
+pub fn sort_back_to_front(draws: &mut [TransparentDraw]) {
+    draws.sort_by(|a, b| b.distance_sq.total_cmp(&a.distance_sq));
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Quaternion, Vector3, Zero};
+
+    use super::*;
+    use crate::culling::Plane;
+
+    fn mesh(material: usize, transparent: bool, center: [f32; 3]) -> MeshInfo {
+        MeshInfo {
+            material: Some(material),
+            transparent,
+            bounding_sphere: BoundingSphere {
+                center: Point3::from(center),
+                radius: 0.5,
+            },
+        }
+    }
+
+    fn instance(x: f32, z: f32) -> Instanced {
+        Instanced {
+            position: Vector3::new(x, 0.0, z),
+            rotation: Quaternion::from_sv(1.0, Vector3::zero()),
+        }
+    }
+
+    // No planes, so nothing is culled.
+    fn everything() -> Frustum {
+        Frustum { planes: Vec::new() }
+    }
+
+    #[test]
+    fn opaque_meshes_are_grouped_by_material() {
+        let meshes = [
+            mesh(2, false, [0.0; 3]),
+            mesh(0, false, [0.0; 3]),
+            mesh(2, false, [0.0; 3]),
+            mesh(1, true, [0.0; 3]),
+            mesh(0, false, [0.0; 3]),
+        ];
+        let mut queue = RenderQueue::new();
+        queue.build_meshes(&meshes, &[instance(0.0, 0.0)], Point3::new(0.0, 0.0, 5.0), &everything());
+        assert_eq!(queue.opaque, vec![1, 4, 0, 2]);
+        assert_eq!(queue.transparent.len(), 1);
+        assert_eq!(queue.stats.meshes_visible, 5);
+    }
+
+    #[test]
+    fn meshes_without_a_material_are_skipped() {
+        let meshes = [
+            mesh(0, false, [0.0; 3]),
+            MeshInfo { material: None, ..mesh(0, false, [0.0; 3]) },
+            MeshInfo { material: None, ..mesh(0, true, [0.0; 3]) },
+        ];
+        let mut queue = RenderQueue::new();
+        queue.build_meshes(&meshes, &[instance(0.0, 0.0)], Point3::new(0.0, 0.0, 5.0), &everything());
+        assert_eq!(queue.opaque, vec![0]);
+        assert!(queue.transparent.is_empty());
+        assert_eq!(queue.stats.meshes_visible, 1);
+        assert_eq!(queue.stats.meshes_total, 3);
+    }
+
+    #[test]
+    fn transparent_draws_are_back_to_front() {
+        let meshes = [mesh(0, true, [0.0; 3]), mesh(1, true, [0.0, 0.0, 1.0])];
+        let instances = [instance(0.0, 0.0), instance(0.0, -10.0), instance(0.0, -5.0)];
+        let mut queue = RenderQueue::new();
+        queue.build_meshes(&meshes, &instances, Point3::new(0.0, 0.0, 10.0), &everything());
+        let order = queue
+            .transparent
+            .iter()
+            .map(|draw| (draw.mesh, draw.instance))
+            .collect::<Vec<_>>();
+        assert_eq!(order, vec![(0, 1), (1, 1), (0, 2), (1, 2), (0, 0), (1, 0)]);
+        assert!(queue.opaque.is_empty());
+    }
+
+    #[test]
+    fn culled_instances_are_skipped() {
+        // Keeps z <= 0.
+        let frustum = Frustum {
+            planes: vec![Plane { normal: Vector3::new(0.0, 0.0, -1.0), d: 0.0 }],
+        };
+        let meshes = [mesh(0, true, [0.0; 3])];
+        let instances = [instance(0.0, 10.0), instance(0.0, -10.0)];
+        let mut queue = RenderQueue::new();
+        queue.build_meshes(&meshes, &instances, Point3::new(0.0, 0.0, 0.0), &frustum);
+        assert_eq!(queue.visible_instances, vec![1]);
+        // The slot in the culled instance buffer, not the original index.
+        assert_eq!(queue.transparent.iter().map(|draw| draw.instance).collect::<Vec<_>>(), vec![0]);
+        assert_eq!(queue.stats.instances_visible, 1);
+        assert_eq!(queue.stats.instances_total, 2);
+    }
+
+    #[test]
+    fn equal_distances_keep_their_order() {
+        let eye = Point3::new(0.0, 0.0, 0.0);
+        let items = [
+            (0, 0, Point3::new(1.0, 0.0, 0.0)),
+            (1, 0, Point3::new(0.0, 3.0, 0.0)),
+            (2, 0, Point3::new(-1.0, 0.0, 0.0)),
+            (3, 0, Point3::new(0.0, 0.0, 1.0)),
+        ];
+        let draws = queue_transparent(items, eye);
+        assert_eq!(draws.iter().map(|draw| draw.mesh).collect::<Vec<_>>(), vec![1, 0, 2, 3]);
+        assert_eq!(draws[0].distance_sq, 9.0);
+    }
+}
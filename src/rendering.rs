@@ -2,8 +2,153 @@ use wgpu::naga::Block;
 
 // use crate::wig_geo::block_vertex::{BlockVertex, VertexDesc};
 
+use log::warn;
+
+use crate::pipeline::PipelineBuilder;
 use crate::{texture};
 
+// Bind group layouts shared by the scene and light shaders. Kept as consts so
+// reflection::check_scene_shader can compare them against the WGSL.
+pub const TEXTURE_BIND_GROUP_LAYOUT_ENTRIES: &[wgpu::BindGroupLayoutEntry] = &[
+    wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false,
+        },
+        count: None,
+    },
+    wgpu::BindGroupLayoutEntry {
+        binding: 1,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+        count: None,
+    },
+    wgpu::BindGroupLayoutEntry {
+        binding: 2,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    },
+];
+
+pub const CAMERA_BIND_GROUP_LAYOUT_ENTRIES: &[wgpu::BindGroupLayoutEntry] = &[
+    wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    },
+];
+
+pub const LIGHT_BIND_GROUP_LAYOUT_ENTRIES: &[wgpu::BindGroupLayoutEntry] = &[
+    wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    },
+];
+
+pub const SUPPORTED_SAMPLE_COUNTS: [u32; 4] = [1, 2, 4, 8];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DepthMode {
+    // Depth 0 at the near plane, 1 at the far plane, compare Less.
+    Standard,
+    // Depth 1 at the near plane, 0 at infinity, compare Greater. Float depth
+    // precision is then spread evenly, which avoids z-fighting far away.
+    ReverseZ,
+}
+
+impl DepthMode {
+    pub fn from_reverse_z(reverse_z: bool) -> Self {
+        if reverse_z {
+            DepthMode::ReverseZ
+        } else {
+            DepthMode::Standard
+        }
+    }
+
+    pub fn clear_value(self) -> f32 {
+        match self {
+            DepthMode::Standard => 1.0,
+            DepthMode::ReverseZ => 0.0,
+        }
+    }
+
+    // Maps a compare function written for standard depth onto this mode.
+    pub fn compare(self, compare: wgpu::CompareFunction) -> wgpu::CompareFunction {
+        use wgpu::CompareFunction::*;
+        match (self, compare) {
+            (DepthMode::Standard, compare) => compare,
+            (DepthMode::ReverseZ, Less) => Greater,
+            (DepthMode::ReverseZ, LessEqual) => GreaterEqual,
+            (DepthMode::ReverseZ, Greater) => Less,
+            (DepthMode::ReverseZ, GreaterEqual) => LessEqual,
+            (DepthMode::ReverseZ, compare) => compare,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderSettings {
+    // Requested MSAA sample count; clamped to what the adapter supports.
+    pub sample_count: u32,
+    pub depth_mode: DepthMode,
+    // Draw with PolygonMode::Line; needs Features::POLYGON_MODE_LINE.
+    pub wireframe: bool,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            sample_count: 4,
+            depth_mode: DepthMode::Standard,
+            wireframe: false,
+        }
+    }
+}
+
+// Picks the highest sample count <= `requested` that both the color and depth
+// formats support on this adapter. Falls back to 1 (no MSAA).
+pub fn validate_sample_count(
+    adapter: &wgpu::Adapter,
+    color_format: wgpu::TextureFormat,
+    depth_format: wgpu::TextureFormat,
+    requested: u32,
+) -> u32 {
+    let color_flags = adapter.get_texture_format_features(color_format).flags;
+    let depth_flags = adapter.get_texture_format_features(depth_format).flags;
+    let count = SUPPORTED_SAMPLE_COUNTS
+        .iter()
+        .rev()
+        .copied()
+        .filter(|&count| count <= requested)
+        .find(|&count| color_flags.sample_count_supported(count) && depth_flags.sample_count_supported(count))
+        .unwrap_or(1);
+    if count != requested {
+        warn!("MSAA sample count {} not supported, using {}", requested, count);
+    }
+    count
+}
+
+// Opaque pipeline with the default state. For anything else (blending, culling,
+// wireframe...) use pipeline::PipelineBuilder directly.
 pub fn create_render_pipeline(
     device: &wgpu::Device,
     layout: &wgpu::PipelineLayout,
@@ -11,54 +156,12 @@ pub fn create_render_pipeline(
     depth_format: Option<wgpu::TextureFormat>,
     vertex_layouts: &[wgpu::VertexBufferLayout],
     shader: wgpu::ShaderModuleDescriptor,
+    settings: &RenderSettings,
 ) -> wgpu::RenderPipeline {
-    let shader = device.create_shader_module(shader);
-    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-        label: Some("Render Pipeline"),
-        layout: Some(layout),
-        vertex: wgpu::VertexState {
-            module: &shader,
-            entry_point: Some("vs_main"),
-            buffers: vertex_layouts,
-            compilation_options: Default::default(),
-        },
-        fragment: Some(wgpu::FragmentState {
-            module: &shader,
-            entry_point: Some("fs_main"),
-            targets: &[Some(wgpu::ColorTargetState {
-                format: color_format,
-                blend: Some(wgpu::BlendState {
-                    alpha: wgpu::BlendComponent::REPLACE,
-                    color: wgpu::BlendComponent::REPLACE,
-                }),
-                write_mask: wgpu::ColorWrites::ALL,
-            })],
-            compilation_options: Default::default(),
-        }),
-        primitive: wgpu::PrimitiveState {
-            topology: wgpu::PrimitiveTopology::TriangleList,
-            strip_index_format: None,
-            front_face: wgpu::FrontFace::Ccw,
-            cull_mode: Some(wgpu::Face::Back),
-            polygon_mode: wgpu::PolygonMode::Fill,
-            unclipped_depth: false,
-            conservative: false,
-        },
-        depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
-            format,
-            depth_write_enabled: true,
-            depth_compare: wgpu::CompareFunction::Less,
-            stencil: wgpu::StencilState::default(),
-            bias: wgpu::DepthBiasState::default(),
-        }),
-        multisample: wgpu::MultisampleState {
-            count: 1,
-            mask: !0,
-            alpha_to_coverage_enabled: false,
-        },
-        multiview: None,
-        cache: None,
-    })
+    PipelineBuilder::new(layout, shader, vertex_layouts, color_format)
+        .depth_format(depth_format)
+        .settings(settings)
+        .build(device)
 }
 
 pub fn create_voxel_pipeline(
@@ -66,6 +169,7 @@ pub fn create_voxel_pipeline(
     texture_format: wgpu::TextureFormat,
     light_bind_group_layout: &wgpu::BindGroupLayout,
     camera_bind_group_layout: &wgpu::BindGroupLayout,
+    settings: &RenderSettings,
 ) -> wgpu::RenderPipeline {
     let visibility = wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT;
     let camera_bind_group_layout = camera_bind_group_layout;
@@ -100,6 +204,7 @@ pub fn create_voxel_pipeline(
         Some(texture::Texture::DEPTH_FORMAT),
         &[BlockVertex::desc()], 
         shader,
+        settings,
         );
 
     render_pipeline
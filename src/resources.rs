@@ -3,6 +3,7 @@ use cfg_if::cfg_if;
 use wgpu::util::DeviceExt;
 use log::info;
 use crate::common::utils::IsNullOrEmpty;
+use crate::culling::Aabb;
 use crate::{model::{self, Mesh}, texture};
 
 #[cfg(target_arch = "wasm32")]
@@ -166,6 +167,12 @@ pub async fn load_model(
             load_texture(&m.diffuse_texture, device, queue).await?
         };
         //let diffuse_texture = load_texture(&m.diffuse_texture, device, queue).await?;
+        let opacity = m.dissolve;
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Material Buffer", m.name)),
+            contents: bytemuck::cast_slice(&[model::MaterialUniform::new(opacity)]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout,
             entries: &[
@@ -177,6 +184,10 @@ pub async fn load_model(
                     binding: 1,
                     resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
             ],
             label: None,
         });
@@ -184,6 +195,8 @@ pub async fn load_model(
         materials.push(model::Material {
             name: m.name,
             diffuse_texture,
+            opacity,
+            uniform_buffer,
             bind_group,
         })
     }
@@ -226,6 +239,8 @@ pub async fn load_model(
                 contents: bytemuck::cast_slice(&vertices),
                 usage: wgpu::BufferUsages::VERTEX,
             });
+            let bounds = Aabb::from_points(vertices.iter().map(|v| v.position.into()));
+
             let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some(&format!("{:?} Index Buffer", file_name)),
                 contents: bytemuck::cast_slice(&m.mesh.indices),
@@ -238,6 +253,7 @@ pub async fn load_model(
                 index_buffer,
                 num_elements: m.mesh.indices.len() as u32,
                 material: m.mesh.material_id.unwrap_or(0),
+                bounding_sphere: bounds.bounding_sphere(),
             }
         })
         .collect::<Vec<_>>();
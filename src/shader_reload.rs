@@ -0,0 +1,120 @@
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use log::{info, warn};
+use wgpu::naga;
+
+// Set this env var to load shaders from src/shaders at runtime and rebuild the
+// pipelines whenever a file is saved.
+pub const HOT_RELOAD_ENV: &str = "WIG_SHADER_HOT_RELOAD";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShaderFile {
+    Scene,
+    Light,
+}
+
+impl ShaderFile {
+    pub const ALL: [ShaderFile; 2] = [ShaderFile::Scene, ShaderFile::Light];
+
+    pub fn file_name(self) -> &'static str {
+        match self {
+            ShaderFile::Scene => "shader.wgsl",
+            ShaderFile::Light => "light.wgsl",
+        }
+    }
+
+    // The copy baked into the binary, used when hot reload is off or the file can't be read.
+    pub fn embedded(self) -> &'static str {
+        match self {
+            ShaderFile::Scene => include_str!("shaders/shader.wgsl"),
+            ShaderFile::Light => include_str!("shaders/light.wgsl"),
+        }
+    }
+
+    pub fn path(self) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src")
+            .join("shaders")
+            .join(self.file_name())
+    }
+}
+
+pub fn hot_reload_enabled() -> bool {
+    cfg!(not(target_arch = "wasm32")) && std::env::var_os(HOT_RELOAD_ENV).is_some()
+}
+
+pub fn read_shader(file: ShaderFile) -> std::io::Result<String> {
+    std::fs::read_to_string(file.path())
+}
+
+// Source used when first building pipelines. Falls back to the embedded copy if
+// the file on disk is missing or doesn't validate, so startup never panics.
+pub fn load_shader(file: ShaderFile) -> String {
+    if !hot_reload_enabled() {
+        return file.embedded().to_string();
+    }
+    match read_shader(file) {
+        Ok(source) => match validate_wgsl(&source) {
+            Ok(_) => source,
+            Err(e) => {
+                warn!("{} failed to validate, using embedded shader:\n{}", file.file_name(), e);
+                file.embedded().to_string()
+            }
+        },
+        Err(e) => {
+            warn!("Failed to read {:?}, using embedded shader: {}", file.path(), e);
+            file.embedded().to_string()
+        }
+    }
+}
+
+// Parses and validates WGSL with naga, returning a printable error on failure.
+pub fn validate_wgsl(source: &str) -> Result<(naga::Module, naga::valid::ModuleInfo), String> {
+    let module = naga::front::wgsl::parse_str(source).map_err(|e| e.emit_to_string(source))?;
+    let info = naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all())
+        .validate(&module)
+        .map_err(|e| e.emit_to_string(source))?;
+    Ok((module, info))
+}
+
+struct WatchedShader {
+    file: ShaderFile,
+    modified: Option<SystemTime>,
+}
+
+// Polls shader file modification times. Cheap enough to call once per frame.
+pub struct ShaderWatcher {
+    shaders: Vec<WatchedShader>,
+}
+
+impl ShaderWatcher {
+    pub fn new() -> Self {
+        let shaders = ShaderFile::ALL
+            .iter()
+            .map(|&file| WatchedShader {
+                file,
+                modified: modified_time(&file.path()),
+            })
+            .collect();
+        info!("Shader hot reload enabled, watching {:?}", ShaderFile::Scene.path().parent());
+        Self { shaders }
+    }
+
+    // Returns every shader whose file changed since the last poll.
+    pub fn poll(&mut self) -> Vec<ShaderFile> {
+        let mut changed = Vec::new();
+        for shader in &mut self.shaders {
+            let modified = modified_time(&shader.file.path());
+            if modified.is_some() && modified != shader.modified {
+                shader.modified = modified;
+                changed.push(shader.file);
+            }
+        }
+        changed
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
@@ -72,6 +72,12 @@ var t_diffuse: texture_2d<f32>;
 @group(0)@binding(1)
 var s_diffuse: sampler;
 
+struct Material {
+    opacity: f32,
+}
+@group(0) @binding(2)
+var<uniform> material: Material;
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords);
@@ -89,6 +95,6 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let specular_strength = pow(max(dot(view_dir, reflect_dir), 0.0), 32.0);
     let specular_color = specular_strength * light.color;
     let result = (ambient_color + diffuse_color + specular_color) * object_color.xyz;
-    return vec4<f32>(result, object_color.a);
+    return vec4<f32>(result, object_color.a * material.opacity);
     // return textureSample(t_diffuse, s_diffuse, in.tex_coords);
 }
\ No newline at end of file
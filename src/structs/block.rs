@@ -1,17 +1,153 @@
-struct Block {
+use cgmath::Point3;
+
+use crate::culling::Aabb;
+
+// Chunks are CHUNK_SIZE x CHUNK_HEIGHT x CHUNK_SIZE blocks of 1 world unit each.
+pub const CHUNK_SIZE: usize = 16;
+pub const CHUNK_HEIGHT: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Block {
     pub is_active: bool,
     pub is_solid: bool,
     pub is_transparent: bool,
     pub block_type: BlockType,
 }
 
+impl Block {
+    pub fn air() -> Self {
+        Self {
+            is_active: false,
+            is_solid: false,
+            is_transparent: true,
+            block_type: BlockType::BlockType_Default,
+        }
+    }
+
+    pub fn solid(block_type: BlockType) -> Self {
+        Self {
+            is_active: true,
+            is_solid: true,
+            is_transparent: false,
+            block_type,
+        }
+    }
+}
 
-enum BlockType {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockType {
     BlockType_Default = 0,
     BlockType_Grass = 1,
 }
 
-struct Chunk {
+pub struct Chunk {
     pub blocks: Vec<Block>,
     pub chunk_pos: (i32, i32),
-}
\ No newline at end of file
+}
+
+impl Chunk {
+    // A chunk of air.
+    pub fn new(chunk_pos: (i32, i32)) -> Self {
+        Self {
+            blocks: vec![Block::air(); CHUNK_SIZE * CHUNK_HEIGHT * CHUNK_SIZE],
+            chunk_pos,
+        }
+    }
+
+    // Somewhere to walk until there's terrain: a grass floor one block thick,
+    // four steps climbing in +x, and walls along the z = 0 and far x edges.
+    pub fn test_course(chunk_pos: (i32, i32)) -> Self {
+        let mut chunk = Self::new(chunk_pos);
+        let grass = Block::solid(BlockType::BlockType_Grass);
+        let stone = Block::solid(BlockType::BlockType_Default);
+        for a in 0..CHUNK_SIZE {
+            for b in 0..CHUNK_SIZE {
+                chunk.set_block(a, 0, b, grass);
+            }
+            for y in 1..=3 {
+                chunk.set_block(a, y, 0, stone);
+                chunk.set_block(CHUNK_SIZE - 1, y, a, stone);
+            }
+        }
+        for step in 0..4 {
+            for y in 1..=step + 1 {
+                for z in 6..10 {
+                    chunk.set_block(4 + step, y, z, stone);
+                }
+            }
+        }
+        chunk
+    }
+
+    // Blocks are stored x fastest, then z, then y. Coordinates are local to the chunk.
+    pub fn block_index(x: usize, y: usize, z: usize) -> usize {
+        x + z * CHUNK_SIZE + y * CHUNK_SIZE * CHUNK_SIZE
+    }
+
+    pub fn block(&self, x: usize, y: usize, z: usize) -> Option<&Block> {
+        if x >= CHUNK_SIZE || y >= CHUNK_HEIGHT || z >= CHUNK_SIZE {
+            return None;
+        }
+        self.blocks.get(Self::block_index(x, y, z))
+    }
+
+    pub fn set_block(&mut self, x: usize, y: usize, z: usize, block: Block) {
+        if x < CHUNK_SIZE && y < CHUNK_HEIGHT && z < CHUNK_SIZE {
+            self.blocks[Self::block_index(x, y, z)] = block;
+        }
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        let min = Point3::new(
+            (self.chunk_pos.0 * CHUNK_SIZE as i32) as f32,
+            0.0,
+            (self.chunk_pos.1 * CHUNK_SIZE as i32) as f32,
+        );
+        let max = Point3::new(min.x + CHUNK_SIZE as f32, CHUNK_HEIGHT as f32, min.z + CHUNK_SIZE as f32);
+        Aabb::new(min, max)
+    }
+}
+
+// Whether the block at world coordinates is solid. Chunks that aren't loaded
+// are empty, except that everything below y = 0 is solid so nothing falls out of the world.
+pub fn is_solid_at(chunks: &[Chunk], x: i32, y: i32, z: i32) -> bool {
+    if y < 0 {
+        return true;
+    }
+    if y >= CHUNK_HEIGHT as i32 {
+        return false;
+    }
+    let size = CHUNK_SIZE as i32;
+    let chunk_pos = (x.div_euclid(size), z.div_euclid(size));
+    chunks
+        .iter()
+        .find(|chunk| chunk.chunk_pos == chunk_pos)
+        .and_then(|chunk| chunk.block(x.rem_euclid(size) as usize, y as usize, z.rem_euclid(size) as usize))
+        .is_some_and(|block| block.is_solid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_course_blocks_are_found_in_world_coordinates() {
+        let chunks = [Chunk::test_course((1, -1))];
+        let (x0, z0) = (CHUNK_SIZE as i32, -(CHUNK_SIZE as i32));
+        // Floor, and air above it.
+        assert!(is_solid_at(&chunks, x0 + 2, 0, z0 + 3));
+        assert!(!is_solid_at(&chunks, x0 + 2, 1, z0 + 3));
+        // Walls.
+        assert!(is_solid_at(&chunks, x0 + 8, 3, z0));
+        assert!(!is_solid_at(&chunks, x0 + 8, 4, z0));
+        assert!(is_solid_at(&chunks, x0 + CHUNK_SIZE as i32 - 1, 2, z0 + 8));
+        // Steps get a block taller each column.
+        for step in 0..4 {
+            assert!(is_solid_at(&chunks, x0 + 4 + step, step + 1, z0 + 7));
+            assert!(!is_solid_at(&chunks, x0 + 4 + step, step + 2, z0 + 7));
+        }
+        // Outside the chunk only the world floor is solid.
+        assert!(!is_solid_at(&chunks, x0 - 1, 0, z0 + 3));
+        assert!(is_solid_at(&chunks, x0 - 1, -1, z0 + 3));
+    }
+}
@@ -6,6 +6,8 @@ pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
     pub sampler: wgpu::Sampler,
+    // True if any texel has alpha < 255, used to route materials to the transparent queue.
+    pub has_transparency: bool,
 }
 impl Texture {
     pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
@@ -33,6 +35,7 @@ impl Texture {
         label: Option<&str>,
     ) -> Result<Self, anyhow::Error> {
         let rgba = img.to_rgba8();
+        let has_transparency = rgba.pixels().any(|p| p.0[3] < 255);
         let dimensions = img.dimensions();
         let size = wgpu::Extent3d {
             width: dimensions.0,
@@ -78,12 +81,13 @@ impl Texture {
             texture,
             view,
             sampler,
+            has_transparency,
         })
     }
 
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float; // 1.
     
-    pub fn create_depth_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, label: &str) -> Self {
+    pub fn create_depth_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, sample_count: u32, label: &str) -> Self {
         let size = wgpu::Extent3d { // 2.
             width: config.width.max(1),
             height: config.height.max(1),
@@ -93,7 +97,7 @@ impl Texture {
             label: Some(label),
             size,
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
             format: Self::DEPTH_FORMAT,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT // 3.
@@ -118,6 +122,30 @@ impl Texture {
             }
         );
 
-        Self { texture, view, sampler }
+        Self { texture, view, sampler, has_transparency: false }
+    }
+
+    // Color target that gets resolved into the surface texture at the end of the pass.
+    // Returns None when MSAA is off so the pass can draw straight to the surface.
+    pub fn create_multisampled_framebuffer(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, sample_count: u32) -> Option<wgpu::TextureView> {
+        if sample_count <= 1 {
+            return None;
+        }
+        let size = wgpu::Extent3d {
+            width: config.width.max(1),
+            height: config.height.max(1),
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("msaa_framebuffer"),
+            size,
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format: config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        Some(texture.create_view(&wgpu::TextureViewDescriptor::default()))
     }
 }
\ No newline at end of file
@@ -1,5 +1,24 @@
 use crate::camera::{self, Camera, CameraUniform};
-use crate::{cameracontroller, light, model, rendering, resources, texture};
+use crate::{cameracontroller, light, markup, model, reflection, rendering, resources, texture};
+use crate::pipeline::{BlendMode, PipelineBuilder, PipelineCache, PipelineKey};
+use crate::camera_path::{self, CameraPath, CameraPlayer, CameraRecorder};
+use crate::chat::{self, ChatClient, ChatEvent, ChatServer};
+use crate::commands::{Args, CommandRegistry, ConsoleCompleter};
+use crate::console::Console;
+use crate::culling::Frustum;
+use crate::hud::{Anchor, Hud, TextStyle};
+use crate::perf::{self, RenderCounters};
+use crate::perf_overlay::PerfOverlay;
+use crate::profiler::GpuProfiler;
+use crate::world_text::{WorldLabelStyle, WorldText};
+use crate::cvars::{self, CvarRegistry, CvarValue};
+use crate::player::Player;
+use crate::structs::block::{Chunk, CHUNK_HEIGHT, CHUNK_SIZE};
+use crate::gamepad::{GamepadMapper, PadEvent};
+use crate::input::{self, Action, ActionEvent, InputMap, Trigger};
+use crate::render_queue::RenderQueue;
+use crate::rendering::{DepthMode, RenderSettings};
+use crate::shader_reload::{self, ShaderFile, ShaderWatcher};
 use crate::vertex::{Instanced, InstanceRaw};
 use crate::model::{DrawLight, DrawModel, Model, ModelVertex, Vertex};
 use crate::common::utils::IsNullOrEmpty;
@@ -8,6 +27,7 @@ use glyphon::{Attrs, Buffer, Cache, Color, Family, FontSystem, Metrics, Resoluti
 use wgpu::util::RenderEncoder;
 use instant::Duration;
 use winit::event::MouseButton;
+use log::{error, info, warn};
 
 use std::collections::btree_map::Range;
 use std::sync::Arc;
@@ -17,8 +37,8 @@ use winit::{
     dpi::LogicalSize,
     event::{DeviceEvent, ElementState, Event, KeyEvent, WindowEvent},
     event_loop::EventLoop,
-    keyboard::{KeyCode, PhysicalKey},
-    window::{Window, WindowBuilder},
+    keyboard::{KeyCode, ModifiersState, PhysicalKey},
+    window::{CursorGrabMode, Window, WindowBuilder},
 };
 use crate::cameracontroller::CameraController;
 
@@ -45,26 +65,59 @@ pub struct WindowState<'a> {
     pub viewport: Viewport,
     pub atlas: TextAtlas,
     pub text_renderer: TextRenderer,
-    pub text_buffer: Buffer,
-    pub chat_text: String,
-    pub render_pipeline: wgpu::RenderPipeline,
+    pub console: Console,
+    pub commands: CommandRegistry,
+    pub cvars: CvarRegistry,
+    // Set by the `host` command; chat clients anywhere can connect to it.
+    pub chat_server: Option<ChatServer>,
+    pub chat: Option<ChatClient>,
+    // Queue HUD text from anywhere during update; it's drawn and cleared by render.
+    pub hud: Hud,
+    // Same, for labels anchored in the world. Drawn depth tested, before the HUD.
+    pub world_text: WorldText,
+    world_text_renderer: TextRenderer,
+    pub pipeline_cache: PipelineCache,
+    pub perf_overlay: PerfOverlay,
+    pub profiler: GpuProfiler,
+    render_pipeline_layout: wgpu::PipelineLayout,
+    light_pipeline_layout: wgpu::PipelineLayout,
+    shader_watcher: Option<ShaderWatcher>,
+    pub render_pipeline: PipelineKey,
+    pub transparent_pipeline: PipelineKey,
+    pub render_queue: RenderQueue,
     pub index_buffer: wgpu::Buffer,
     pub num_indices: u32,
     pub camera: Camera,
     pub camera_controller: CameraController,
+    // While set, drives the camera instead of camera_controller.
+    pub camera_player: Option<CameraPlayer>,
+    pub camera_recorder: Option<CameraRecorder>,
+    // While set, the camera rides at the player's eye height.
+    pub player: Option<Player>,
+    pub chunks: Vec<Chunk>,
     pub camera_uniform: CameraUniform,
     pub camera_buffer: wgpu::Buffer,
     pub camera_bind_group: wgpu::BindGroup,
     pub diffuse_bind_group: wgpu::BindGroup,
     pub diffuse_texture: crate::texture::Texture,
     depth_texture: texture::Texture,
+    msaa_view: Option<wgpu::TextureView>,
+    pub settings: RenderSettings,
     light_buffer: wgpu::Buffer,
     light_uniform: light::LightUniform,
     light_bind_group_layout: wgpu::BindGroupLayout,
     light_bind_group: wgpu::BindGroup,
-    light_render_pipeline: wgpu::RenderPipeline,
+    light_render_pipeline: PipelineKey,
     pub obj_model: Model,
     pub mouse_pressed: bool,
+    cursor_grabbed: bool,
+    pub input_map: InputMap,
+    modifiers: ModifiersState,
+    // Set by Action::Exit; run() closes the window when it sees it.
+    pub exit_requested: bool,
+    // None when gilrs couldn't start; keyboard and mouse still work.
+    gilrs: Option<gilrs::Gilrs>,
+    pub gamepad: GamepadMapper,
     projection: camera::Projection,    
 }
 
@@ -102,7 +155,7 @@ const VERTICES: &[ModelVertex] = &[
 ];
 
 impl<'a> WindowState<'a> {
-    pub async fn new(window: &'a Arc<Window>) -> Self {
+    pub async fn new(window: &'a Arc<Window>, mut settings: RenderSettings) -> Self {
         let physical_size = window.inner_size();
         let scale_factor = window.scale_factor();
 
@@ -119,8 +172,22 @@ impl<'a> WindowState<'a> {
             .request_adapter(&RequestAdapterOptions::default())
             .await.unwrap();
 
+        // Line polygon mode is only for the wireframe command and timestamps only
+        // for the profiler, so both are optional.
+        let optional_features = wgpu::Features::POLYGON_MODE_LINE | wgpu::Features::TIMESTAMP_QUERY;
         let (device, queue) = adapter
-            .request_device(&DeviceDescriptor::default(), None).await.unwrap();
+            .request_device(&DeviceDescriptor {
+                required_features: adapter.features() & optional_features,
+                ..Default::default()
+            }, None).await.unwrap();
+
+        let mut cvars = CvarRegistry::with_defaults();
+        if let Err(e) = cvars.load(cvars::CVAR_FILE) {
+            error!("{:?}", e);
+        }
+        if cvars.bool("reverse_z") {
+            settings.depth_mode = DepthMode::ReverseZ;
+        }
 
         let surface_caps = surface.get_capabilities(&adapter);
         let surface_format = surface_caps
@@ -135,7 +202,7 @@ impl<'a> WindowState<'a> {
             format: surface_format,
             width: physical_size.width,
             height: physical_size.height,
-            present_mode: surface_caps.present_modes[0],
+            present_mode: present_mode(cvars.bool("vsync")),
             alpha_mode: surface_caps.alpha_modes[0],
             view_formats: vec![],
             desired_maximum_frame_latency: 2,
@@ -143,31 +210,28 @@ impl<'a> WindowState<'a> {
 
         surface.configure(&device, &surface_config);
 
+        settings.sample_count = rendering::validate_sample_count(
+            &adapter,
+            surface_config.format,
+            texture::Texture::DEPTH_FORMAT,
+            settings.sample_count,
+        );
+
         let texture_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        multisampled: false,
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                    count: None,
-                },
-            ],
+            entries: rendering::TEXTURE_BIND_GROUP_LAYOUT_ENTRIES,
             label: Some("texture_bind_group_layout"),
         });
 
         let camera = camera::Camera::new((0.0, 5.0, 10.0), cgmath::Deg(-90.0), cgmath::Deg(-20.0));
-        let projection = camera::Projection::new(surface_config.width, surface_config.height, cgmath::Deg(45.0), 0.1, 100.0);
-        let camera_controller = cameracontroller::CameraController::new(4.0, 0.4);
+        let mut projection = camera::Projection::new(surface_config.width, surface_config.height, cgmath::Deg(cvars.float("fov")), 0.1, 100.0);
+        projection.set_reverse_z(settings.depth_mode == DepthMode::ReverseZ);
+        let camera_controller = cameracontroller::CameraController::new(cvars.float("speed"), cvars.float("sensitivity"));
+        let camera_player = std::env::var_os(camera_path::CAMERA_PATH_ENV).and_then(|path| {
+            CameraPath::load(&path)
+                .map_err(|e| error!("{:?}", e))
+                .ok()
+                .map(CameraPlayer::new)
+        });
 
         let mut camera_uniform = CameraUniform::new();
         camera_uniform.update_view_proj(&camera, &projection);
@@ -198,22 +262,12 @@ impl<'a> WindowState<'a> {
         let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Instance Buffer"),
             contents: bytemuck::cast_slice(&instance_data),
-            usage: wgpu::BufferUsages::VERTEX,
+            // Rewritten every frame with only the instances that pass culling.
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
         });
 
         let camera_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }
-            ],
+            entries: rendering::CAMERA_BIND_GROUP_LAYOUT_ENTRIES,
             label: Some("camera_bind_group_layout"),
         });
 
@@ -232,7 +286,7 @@ impl<'a> WindowState<'a> {
         let light_uniform = light::LightUniform {
             position: [2.0, 2.0, 2.0],
             _padding: 0,
-            color: [1.0, 1.0, 1.0],
+            color: cvars.color("light_color"),
             _padding2: 0,
         };
 
@@ -244,18 +298,7 @@ impl<'a> WindowState<'a> {
 
         let light_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("Light Bind Group Layout"),
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-            ],
+            entries: rendering::LIGHT_BIND_GROUP_LAYOUT_ENTRIES,
         });
 
         let light_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
@@ -277,6 +320,12 @@ impl<'a> WindowState<'a> {
         let diffuse_bytes = include_bytes!("./assets/cretin.png");
         let diffuse_texture = texture::Texture::from_bytes(&device, &queue, diffuse_bytes, "cretin.png").unwrap();
 
+        let diffuse_material_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Diffuse Material Buffer"),
+            contents: bytemuck::cast_slice(&[model::MaterialUniform::new(1.0)]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
         let diffuse_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &texture_bind_group_layout,
             entries: &[
@@ -287,7 +336,11 @@ impl<'a> WindowState<'a> {
                 wgpu::BindGroupEntry {
                     binding: 1,
                     resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                }
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: diffuse_material_buffer.as_entire_binding(),
+                },
             ],
             label: Some("diffuse_bind_group"),
         });
@@ -302,40 +355,48 @@ impl<'a> WindowState<'a> {
             push_constant_ranges: &[],
         });
 
-        let render_pipeline = {
-            let shader = wgpu::ShaderModuleDescriptor {
-                label: Some("Normal Shader"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("shaders/shader.wgsl").into()),
-            };
-            rendering::create_render_pipeline(
-                &device,
-                &render_pipeline_layout,
-                surface_config.format,
-                Some(texture::Texture::DEPTH_FORMAT),
-                &[model::ModelVertex::desc(), InstanceRaw::desc()],
-                shader,
-            )
-        };
+        let light_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Light Pipeline Layout"),
+            bind_group_layouts: &[&camera_bind_group_layout, &light_bind_group_layout],
+            push_constant_ranges: &[],
+        });
 
-        let light_render_pipeline = {
-            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Light Pipeline Layout"),
-                bind_group_layouts: &[&camera_bind_group_layout, &light_bind_group_layout],
-                push_constant_ranges: &[],
-            });
-            let shader = wgpu::ShaderModuleDescriptor {
-                label: Some("Light Shader"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("shaders/light.wgsl").into()),
-            };
-            rendering::create_render_pipeline(
-                &device,
-                &layout,
-                surface_config.format,
-                Some(texture::Texture::DEPTH_FORMAT),
-                &[model::ModelVertex::desc()],
-                shader,
-            )
-        };
+        let scene_shader = shader_reload::load_shader(ShaderFile::Scene);
+        let light_shader = shader_reload::load_shader(ShaderFile::Light);
+
+        // Catch a shader and its hand-written layout drifting apart before wgpu does.
+        if cfg!(debug_assertions) {
+            for (file, source) in [(ShaderFile::Scene, &scene_shader), (ShaderFile::Light, &light_shader)] {
+                for e in reflection::check_shader_file(file, source) {
+                    error!("{}: {}", file.file_name(), e);
+                }
+            }
+        }
+
+        let mut pipeline_cache = PipelineCache::new();
+
+        let (render_pipeline, transparent_pipeline) = create_scene_pipelines(
+            &device,
+            &mut pipeline_cache,
+            &render_pipeline_layout,
+            surface_config.format,
+            &settings,
+            &scene_shader,
+        );
+
+        let light_render_pipeline = create_light_pipeline(
+            &device,
+            &mut pipeline_cache,
+            &light_pipeline_layout,
+            surface_config.format,
+            &settings,
+            &light_shader,
+        );
+
+        let profiler = GpuProfiler::new(&device, &queue, &adapter.get_info());
+        let perf_overlay = PerfOverlay::new(&device, &mut pipeline_cache, surface_config.format, &settings);
+
+        let shader_watcher = shader_reload::hot_reload_enabled().then(ShaderWatcher::new);
 
         let mut font_system = FontSystem::new();
         let swash_cache = SwashCache::new();
@@ -343,7 +404,11 @@ impl<'a> WindowState<'a> {
         let viewport = Viewport::new(&device, &cache);
         let mut atlas = TextAtlas::new(&device, &queue, &cache, surface_config.format);
         let text_renderer = TextRenderer::new(
-            &mut atlas, &device, wgpu::MultisampleState::default(),
+            &mut atlas, &device,
+            wgpu::MultisampleState {
+                count: settings.sample_count,
+                ..Default::default()
+            },
             Some(wgpu::DepthStencilState {
                 format: texture::Texture::DEPTH_FORMAT,
                 depth_write_enabled: false,
@@ -352,22 +417,12 @@ impl<'a> WindowState<'a> {
                 bias: wgpu::DepthBiasState::default(),
             })
         );
+        let world_text_renderer = create_world_text_renderer(&mut atlas, &device, &settings);
 
-        let mut text_buffer = Buffer::new(&mut font_system, Metrics::new(30.0, 42.0));
-        let physical_width = (physical_size.width as f64 * scale_factor) as u32;
-        let physical_height = (physical_size.height as f64 * scale_factor) as u32;
-
-        text_buffer.set_size(
-            &mut font_system,
-            Some(physical_width as f32),
-            Some(physical_height as f32)
-        );
-
-        let mut chat_text = "Hello world! 👋\nThis is rendered with 🦅 glyphon 🦁\nThe text below should be partially clipped.\na b c d e f g h i j k l m n o p q r s t u v w x y z".to_string();
-        text_buffer.set_text(&mut font_system, &chat_text, Attrs::new().family(Family::SansSerif), Shaping::Advanced); 
-        text_buffer.shape_until_scroll(&mut font_system, false);
+        let console = Console::new(&mut font_system, physical_size.width as f32);
 
-        let depth_texture = texture::Texture::create_depth_texture(&device, &surface_config, "depth_texture");
+        let depth_texture = texture::Texture::create_depth_texture(&device, &surface_config, settings.sample_count, "depth_texture");
+        let msaa_view = texture::Texture::create_multisampled_framebuffer(&device, &surface_config, settings.sample_count);
 
         Self {
             device,
@@ -379,9 +434,23 @@ impl<'a> WindowState<'a> {
             viewport,
             atlas,
             text_renderer,
-            text_buffer,
-            chat_text,
+            console,
+            commands: CommandRegistry::with_builtins(),
+            cvars,
+            chat_server: None,
+            chat: None,
+            hud: Hud::new(),
+            world_text: WorldText::new(),
+            world_text_renderer,
+            pipeline_cache,
+            perf_overlay,
+            profiler,
+            render_pipeline_layout,
+            light_pipeline_layout,
+            shader_watcher,
             render_pipeline,
+            transparent_pipeline,
+            render_queue: RenderQueue::new(),
             index_buffer,
             num_indices,
             window,
@@ -390,6 +459,11 @@ impl<'a> WindowState<'a> {
             camera_buffer,
             camera_bind_group,
             camera_controller,
+            camera_player,
+            camera_recorder: None,
+            player: None,
+            // Off to the side of the model grid, ahead of the starting camera.
+            chunks: vec![Chunk::test_course((0, -2))],
             diffuse_bind_group,
             diffuse_texture,
             instances,
@@ -400,42 +474,426 @@ impl<'a> WindowState<'a> {
             light_bind_group_layout,
             light_render_pipeline,
             depth_texture,
+            msaa_view,
+            settings,
             instance_buffer,
             projection,
             mouse_pressed: false,
+            cursor_grabbed: false,
+            input_map: InputMap::load_or_default(input::INPUT_CONFIG_FILE),
+            modifiers: ModifiersState::empty(),
+            exit_requested: false,
+            gilrs: gilrs::Gilrs::new()
+                .map_err(|e| warn!("Gamepad support unavailable: {}", e))
+                .ok(),
+            gamepad: GamepadMapper::default(),
         }
     }
 
     // Remaining methods unchanged for brevity
-    pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) { /* ... */ }
+    pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        if new_size.width == 0 || new_size.height == 0 {
+            return;
+        }
+        self.surface_config.width = new_size.width;
+        self.surface_config.height = new_size.height;
+        self.surface.configure(&self.device, &self.surface_config);
+        self.projection.resize(new_size.width, new_size.height);
+        self.depth_texture = texture::Texture::create_depth_texture(&self.device, &self.surface_config, self.settings.sample_count, "depth_texture");
+        self.msaa_view = texture::Texture::create_multisampled_framebuffer(&self.device, &self.surface_config, self.settings.sample_count);
+        self.console.resize(&mut self.font_system, new_size.width as f32);
+    }
     pub fn input(&mut self, event: &WindowEvent) -> bool {
+        if self.console.open {
+            if let WindowEvent::KeyboardInput { event: key_event, .. } = event {
+                self.console_key(key_event);
+                return true;
+            }
+        }
         match event {
+            WindowEvent::Focused(false) => {
+                // Alt-tabbing away must give the cursor back.
+                self.set_cursor_grabbed(false);
+                self.mouse_pressed = false;
+                false
+            }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = modifiers.state();
+                false
+            }
             WindowEvent::KeyboardInput {
                 event:
                     KeyEvent {
                         physical_key: PhysicalKey::Code(key),
                         state,
+                        repeat,
                         ..
                     },
                 ..
-            } => self.camera_controller.process_keyboard(*key, *state),
+            } => self.trigger_input(Trigger::Key(*key), *state, *repeat),
+            WindowEvent::MouseInput { button, state, .. } => self.trigger_input(Trigger::Mouse(*button), *state, false),
             WindowEvent::MouseWheel { delta, .. } => {
                 self.camera_controller.process_scroll(delta);
                 true
             }
-            WindowEvent::MouseInput {
-                button: MouseButton::Left,
-                state,
-                ..
-            } => {
-                self.mouse_pressed = *state == ElementState::Pressed;
+            _ => false,
+        }
+    }
+
+    // While open the console gets every key; only its toggle (and Escape) close it.
+    fn console_key(&mut self, event: &KeyEvent) {
+        if event.state == ElementState::Pressed && !event.repeat {
+            if let PhysicalKey::Code(key) = event.physical_key {
+                let closes = key == KeyCode::Escape
+                    || self
+                        .input_map
+                        .actions_for(Trigger::Key(key), event.state, &self.modifiers)
+                        .iter()
+                        .any(|e| e.action == Action::ToggleConsole);
+                if closes {
+                    self.console.toggle();
+                    return;
+                }
+            }
+        }
+        if let Some(line) = self.console.process_key(event, &ConsoleCompleter { commands: &self.commands, cvars: &self.cvars }) {
+            self.execute_command(&line);
+        }
+    }
+
+    pub fn execute_command(&mut self, line: &str) {
+        self.console.write_to_console(&format!("> {}", line));
+        let Some(args) = Args::parse(line) else {
+            return;
+        };
+        // A bare cvar name prints it, with arguments it sets it.
+        if self.commands.get(&args.name).is_none() && self.cvars.get(&args.name).is_some() {
+            let result = if args.is_empty() {
+                Ok(())
+            } else {
+                self.set_cvar_from_args(&args.name, &args.args)
+            };
+            let message = match result {
+                Ok(()) => self.describe_cvar(&args.name),
+                Err(e) => format!("{}", e),
+            };
+            self.console.write_to_console(&message);
+            return;
+        }
+        let Some(command) = self.commands.get(&args.name).copied() else {
+            self.console.write_to_console(&format!("Unknown command '{}', try help", args.name));
+            return;
+        };
+        match (command.handler)(self, &args) {
+            Ok(Some(output)) => self.console.write_to_console(&output),
+            Ok(None) => {}
+            Err(e) => self.console.write_to_console(&format!("{}: {}\nusage: {}", command.name, e, command.usage)),
+        }
+    }
+
+    fn trigger_input(&mut self, trigger: Trigger, state: ElementState, repeat: bool) -> bool {
+        let mut handled = false;
+        for ActionEvent { action, pressed } in self.input_map.actions_for(trigger, state, &self.modifiers) {
+            if repeat && action.is_trigger() {
+                handled = true;
+                continue;
+            }
+            handled |= self.handle_action(action, pressed);
+        }
+        handled
+    }
+
+    pub fn handle_action(&mut self, action: Action, pressed: bool) -> bool {
+        match action {
+            Action::ToggleRecording => {
+                if pressed {
+                    self.toggle_camera_recording();
+                }
                 true
             }
-            _ => false,
+            // Stops a playing path, otherwise plays camera_path::RECORDING_FILE.
+            Action::PlayCameraPath => {
+                if pressed && !self.stop_camera_path() {
+                    match CameraPath::load(camera_path::RECORDING_FILE) {
+                        Ok(path) => self.play_camera_path(path),
+                        Err(e) => error!("{:?}", e),
+                    }
+                }
+                true
+            }
+            Action::GrabCursor => {
+                if pressed {
+                    self.set_cursor_grabbed(!self.cursor_grabbed);
+                }
+                true
+            }
+            Action::TogglePlayer => {
+                if pressed {
+                    self.player = match self.player {
+                        Some(_) => None,
+                        None => Some(Player::from_camera(&self.camera)),
+                    };
+                }
+                true
+            }
+            Action::ToggleNoclip => {
+                if pressed {
+                    if let Some(player) = &mut self.player {
+                        player.toggle_noclip();
+                        info!("Player mode: {:?}", player.mode);
+                    }
+                }
+                true
+            }
+            Action::ToggleConsole => {
+                if pressed {
+                    self.console.toggle();
+                    if self.console.open {
+                        // Typing mustn't move the camera, and the cursor has to be usable.
+                        self.set_cursor_grabbed(false);
+                        self.mouse_pressed = false;
+                        self.camera_controller.reset_input();
+                    }
+                }
+                true
+            }
+            Action::TogglePerfOverlay => {
+                if pressed {
+                    let show = !self.cvars.bool("perf_overlay");
+                    if let Err(e) = self.set_cvar("perf_overlay", CvarValue::Bool(show)) {
+                        error!("{:?}", e);
+                    }
+                }
+                true
+            }
+            Action::Look => {
+                self.mouse_pressed = pressed;
+                true
+            }
+            Action::Exit => {
+                if pressed {
+                    self.exit_requested = true;
+                }
+                true
+            }
+            _ => {
+                if pressed && action.is_movement() {
+                    self.stop_camera_path();
+                }
+                self.camera_controller.process_action(action, pressed)
+            }
+        }
+    }
+
+    // Relative mouse mode. Locked keeps the cursor in place, but not every
+    // platform has it (X11, Windows), so fall back to confining it to the window.
+    pub fn set_cursor_grabbed(&mut self, grabbed: bool) {
+        if grabbed == self.cursor_grabbed {
+            return;
+        }
+        let result = if grabbed {
+            self.window
+                .set_cursor_grab(CursorGrabMode::Locked)
+                .or_else(|_| self.window.set_cursor_grab(CursorGrabMode::Confined))
+        } else {
+            self.window.set_cursor_grab(CursorGrabMode::None)
+        };
+        if let Err(e) = result {
+            warn!("Couldn't change cursor grab: {}", e);
+            return;
+        }
+        self.window.set_cursor_visible(!grabbed);
+        self.cursor_grabbed = grabbed;
+    }
+
+    // Whether raw mouse motion should turn the camera.
+    pub fn wants_mouse_motion(&self) -> bool {
+        self.cursor_grabbed || self.mouse_pressed || self.camera_controller.is_panning()
+    }
+
+    // Drains gilrs and feeds the events through the same action handling as keys.
+    fn poll_gamepad(&mut self) {
+        let mut events = Vec::new();
+        if let Some(gilrs) = &mut self.gilrs {
+            while let Some(gilrs::Event { event, .. }) = gilrs.next_event() {
+                events.extend(PadEvent::from_gilrs(&event));
+            }
+        }
+        for event in events {
+            for ActionEvent { action, pressed } in self.gamepad.process(event, &self.input_map) {
+                self.handle_action(action, pressed);
+            }
+        }
+        self.camera_controller.set_analog(self.gamepad.movement(), self.gamepad.look());
+    }
+
+    pub fn play_camera_path(&mut self, path: CameraPath) {
+        info!("Playing camera path ({} keyframes, {:.1}s)", path.keyframes.len(), path.duration());
+        self.camera_player = Some(CameraPlayer::new(path));
+    }
+
+    // Returns whether a path was playing.
+    pub fn stop_camera_path(&mut self) -> bool {
+        let stopped = self.camera_player.take().is_some();
+        if stopped {
+            info!("Camera path stopped");
         }
+        stopped
     }
+
+    // Starts recording the live camera, or stops and saves to camera_path::RECORDING_FILE.
+    pub fn toggle_camera_recording(&mut self) {
+        match self.camera_recorder.take() {
+            Some(recorder) => {
+                let path = recorder.finish(&self.camera);
+                match path.save(camera_path::RECORDING_FILE) {
+                    Ok(()) => info!("Saved {} keyframes to {}", path.keyframes.len(), camera_path::RECORDING_FILE),
+                    Err(e) => error!("{:?}", e),
+                }
+            }
+            None => {
+                info!("Recording camera path");
+                self.camera_recorder = Some(CameraRecorder::new(&self.camera));
+            }
+        }
+    }
+
+    // Rebuilds every depth-tested pipeline so the compare function, the clear value
+    // and the projection all switch together.
+    pub fn set_depth_mode(&mut self, depth_mode: DepthMode) {
+        if self.settings.depth_mode == depth_mode {
+            return;
+        }
+        self.settings.depth_mode = depth_mode;
+        self.projection.set_reverse_z(depth_mode == DepthMode::ReverseZ);
+        self.rebuild_pipelines();
+        info!("Depth mode set to {:?}", depth_mode);
+    }
+
+    pub fn set_wireframe(&mut self, wireframe: bool) -> anyhow::Result<()> {
+        if wireframe && !self.device.features().contains(wgpu::Features::POLYGON_MODE_LINE) {
+            anyhow::bail!("this device doesn't support line polygon mode");
+        }
+        if self.settings.wireframe != wireframe {
+            self.settings.wireframe = wireframe;
+            self.rebuild_pipelines();
+        }
+        Ok(())
+    }
+
+    // Recreates the scene and light pipelines from self.settings.
+    fn rebuild_pipelines(&mut self) {
+        let old_keys = [self.render_pipeline, self.transparent_pipeline, self.light_render_pipeline];
+        (self.render_pipeline, self.transparent_pipeline) = create_scene_pipelines(
+            &self.device,
+            &mut self.pipeline_cache,
+            &self.render_pipeline_layout,
+            self.surface_config.format,
+            &self.settings,
+            &shader_reload::load_shader(ShaderFile::Scene),
+        );
+        self.light_render_pipeline = create_light_pipeline(
+            &self.device,
+            &mut self.pipeline_cache,
+            &self.light_pipeline_layout,
+            self.surface_config.format,
+            &self.settings,
+            &shader_reload::load_shader(ShaderFile::Light),
+        );
+        let new_keys = [self.render_pipeline, self.transparent_pipeline, self.light_render_pipeline];
+        for key in old_keys.iter().filter(|key| !new_keys.contains(key)) {
+            self.pipeline_cache.remove(*key);
+        }
+        self.perf_overlay.rebuild_pipeline(&self.device, &mut self.pipeline_cache, self.surface_config.format, &self.settings);
+        self.world_text_renderer = create_world_text_renderer(&mut self.atlas, &self.device, &self.settings);
+    }
+
+    // Validates, stores, runs the change callback and saves to cvars::CVAR_FILE.
+    pub fn set_cvar(&mut self, name: &str, value: CvarValue) -> anyhow::Result<()> {
+        if let Some(callback) = self.cvars.set(name, value)? {
+            callback(self, value);
+        }
+        if let Err(e) = self.cvars.save(cvars::CVAR_FILE) {
+            error!("{:?}", e);
+        }
+        Ok(())
+    }
+
+    pub fn set_cvar_from_args(&mut self, name: &str, args: &[String]) -> anyhow::Result<()> {
+        let cvar = self
+            .cvars
+            .get(name)
+            .ok_or_else(|| anyhow::anyhow!("unknown cvar '{}'", name))?;
+        let value = cvar.default.parse_like(args)?;
+        self.set_cvar(name, value)
+    }
+
+    pub fn describe_cvar(&self, name: &str) -> String {
+        match self.cvars.get(name) {
+            Some(cvar) => format!("{} = {} (default {})", cvar.name, cvar.value, cvar.default),
+            None => format!("unknown cvar '{}'", name),
+        }
+    }
+
+    // Only changes the perspective FOV; orthographic keeps its own framing.
+    pub fn set_fov(&mut self, fov: cgmath::Deg<f32>) {
+        if let camera::ProjectionKind::Perspective { .. } = self.projection.kind() {
+            self.projection.set_kind(camera::ProjectionKind::Perspective { fovy: fov.into() });
+        }
+    }
+
+    pub fn set_vsync(&mut self, vsync: bool) {
+        self.surface_config.present_mode = present_mode(vsync);
+        self.surface.configure(&self.device, &self.surface_config);
+    }
+
+    // Moves the camera, and the player with it if there is one.
+    pub fn teleport(&mut self, position: cgmath::Point3<f32>) {
+        self.camera.position = position;
+        if let Some(player) = &mut self.player {
+            *player = Player::from_camera(&self.camera);
+        }
+    }
+
+    pub fn set_light_color(&mut self, color: [f32; 3]) {
+        // Uploaded with the rest of the light uniform in update().
+        self.light_uniform.color = color;
+    }
+
     pub fn update(&mut self, dt: instant::Duration) {
-        self.camera_controller.update_camera(&mut self.camera, dt);
+        self.perf_overlay.stats.push(dt);
+        self.reload_changed_shaders();
+        self.poll_gamepad();
+        self.console.drain_log();
+        self.poll_chat();
+        let playing = match &mut self.camera_player {
+            Some(player) => player.update(&mut self.camera, dt),
+            None => false,
+        };
+        if !playing {
+            if self.camera_player.take().is_some() {
+                info!("Camera path finished");
+            }
+            match &mut self.player {
+                Some(player) => {
+                    let input = self.camera_controller.update_player(&mut self.camera, dt);
+                    player.update(&self.chunks, input, dt.as_secs_f32());
+                    player.attach_camera(&mut self.camera);
+                }
+                None => self.camera_controller.update_camera(&mut self.camera, dt),
+            }
+        }
+        if self.camera_recorder.is_some() {
+            let style = TextStyle::default()
+                .anchor(Anchor::TopRight)
+                .color(Color::rgb(255, 60, 60))
+                .font_size(24.0);
+            self.hud.draw_text([10.0, 10.0], "REC", style);
+        }
+        if let Some(recorder) = &mut self.camera_recorder {
+            recorder.record(&self.camera, dt);
+        }
+        self.camera_controller.update_projection(&mut self.projection);
         self.camera_uniform.update_view_proj(&self.camera, &self.projection);
         self.queue.write_buffer(
             &self.camera_buffer,
@@ -450,9 +908,108 @@ impl<'a> WindowState<'a> {
     
         self.queue.write_buffer(&self.light_buffer, 0, bytemuck::cast_slice(&[self.light_uniform]));
 
+        let frustum = Frustum::from_view_proj(&self.camera_uniform.view_proj);
+        self.render_queue.build(&self.obj_model, &self.instances, self.camera.position, &frustum);
+        self.render_queue.cull_chunks(&self.chunks, &frustum);
+        if self.cvars.bool("chunk_labels") {
+            self.draw_chunk_labels();
+        }
+        if self.cvars.bool("light_label") {
+            self.draw_light_label();
+        }
+        if self.cvars.bool("perf_overlay") {
+            let gpu_memory = self.gpu_memory_estimate();
+            self.perf_overlay
+                .queue_text(&mut self.hud, self.render_queue.stats, gpu_memory, self.profiler.latest());
+        }
+        let instance_data = self
+            .render_queue
+            .visible_instances
+            .iter()
+            .map(|&i| self.instances[i].to_raw())
+            .collect::<Vec<_>>();
+        if !instance_data.is_empty() {
+            self.queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&instance_data));
+        }
+
 
     }    
+    // Rebuilds pipelines for any shader edited on disk. A shader that fails naga
+    // validation or pipeline creation is logged and the old pipeline is kept.
+    fn reload_changed_shaders(&mut self) {
+        let changed = match &mut self.shader_watcher {
+            Some(watcher) => watcher.poll(),
+            None => return,
+        };
+        for file in changed {
+            let source = match shader_reload::read_shader(file) {
+                Ok(source) => source,
+                Err(e) => {
+                    warn!("Failed to read {:?}: {}", file.path(), e);
+                    continue;
+                }
+            };
+            let errors = reflection::check_shader_file(file, &source);
+            if !errors.is_empty() {
+                error!("{} failed to validate, keeping previous pipeline:\n{}", file.file_name(), errors.join("\n"));
+                continue;
+            }
+
+            self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+            let (old_keys, new_keys) = match file {
+                ShaderFile::Scene => {
+                    let (render, transparent) = create_scene_pipelines(
+                        &self.device,
+                        &mut self.pipeline_cache,
+                        &self.render_pipeline_layout,
+                        self.surface_config.format,
+                        &self.settings,
+                        &source,
+                    );
+                    (vec![self.render_pipeline, self.transparent_pipeline], vec![render, transparent])
+                }
+                ShaderFile::Light => {
+                    let light = create_light_pipeline(
+                        &self.device,
+                        &mut self.pipeline_cache,
+                        &self.light_pipeline_layout,
+                        self.surface_config.format,
+                        &self.settings,
+                        &source,
+                    );
+                    (vec![self.light_render_pipeline], vec![light])
+                }
+            };
+            if let Some(e) = pollster::block_on(self.device.pop_error_scope()) {
+                error!("{} failed to build, keeping previous pipeline: {}", file.file_name(), e);
+                for key in new_keys.iter().filter(|key| !old_keys.contains(key)) {
+                    self.pipeline_cache.remove(*key);
+                }
+                continue;
+            }
+
+            for key in old_keys.iter().filter(|key| !new_keys.contains(key)) {
+                self.pipeline_cache.remove(*key);
+            }
+            match file {
+                ShaderFile::Scene => {
+                    self.render_pipeline = new_keys[0];
+                    self.transparent_pipeline = new_keys[1];
+                }
+                ShaderFile::Light => self.light_render_pipeline = new_keys[0],
+            }
+            info!("Reloaded {}", file.file_name());
+        }
+    }
+
     pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        self.profiler.begin_frame(&self.device);
+        self.prepare_text();
+        let show_perf = self.cvars.bool("perf_overlay");
+        if show_perf {
+            self.perf_overlay.upload(&self.queue, self.surface_config.width, self.surface_config.height);
+        }
+        let mut counters = RenderCounters::default();
         let output = self.surface.get_current_texture()?;
         let view = output
             .texture
@@ -464,17 +1021,26 @@ impl<'a> WindowState<'a> {
                 label: Some("Render Encoder"),
             });
 
+        let clear_color = self.cvars.color("clear_color");
+        // With MSAA we draw into the multisampled target and only the last pass
+        // resolves into the surface.
+        let (color_view, resolve_target) = match &self.msaa_view {
+            Some(msaa_view) => (msaa_view, Some(&view)),
+            None => (&view, None),
+        };
+        // Separate passes so each one gets its own timestamps.
+        let scope = self.profiler.begin_pass("opaque");
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
+                label: Some("Opaque Pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
+                    view: color_view,
                     resolve_target: None,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
+                            r: clear_color[0] as f64,
+                            g: clear_color[1] as f64,
+                            b: clear_color[2] as f64,
                             a: 1.0,
                         }),
                         store: wgpu::StoreOp::Store,
@@ -483,34 +1049,373 @@ impl<'a> WindowState<'a> {
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
                     view: &self.depth_texture.view,
                     depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
+                        load: wgpu::LoadOp::Clear(self.settings.depth_mode.clear_value()),
                         store: wgpu::StoreOp::Store,
                     }),
                     stencil_ops: None,
                 }),
                 occlusion_query_set: None,
-                timestamp_writes: None,
+                timestamp_writes: self.profiler.timestamp_writes(scope),
             });
 
             render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
             render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-            render_pass.set_pipeline(&self.light_render_pipeline);
-            render_pass.draw_light_model(
-                &self.obj_model,
-                &self.camera_bind_group,
-                &self.light_bind_group,
-            );
+            if let Some(pipeline) = self.pipeline_cache.get(self.light_render_pipeline) {
+                render_pass.set_pipeline(pipeline);
+                render_pass.draw_light_model(
+                    &self.obj_model,
+                    &self.camera_bind_group,
+                    &self.light_bind_group,
+                );
+                for mesh in &self.obj_model.meshes {
+                    counters.record_draw(mesh.num_elements, 1);
+                }
+            }
+
+            // Every instance at once, any order.
+            if let Some(pipeline) = self.pipeline_cache.get(self.render_pipeline) {
+                render_pass.set_pipeline(pipeline);
+                for &mesh_index in &self.render_queue.opaque {
+                    let mesh = &self.obj_model.meshes[mesh_index];
+                    let Some(material) = self.obj_model.materials.get(mesh.material) else {
+                        continue;
+                    };
+                    let instances = self.render_queue.visible_instances.len() as u32;
+                    render_pass.draw_mesh_instanced(
+                        mesh,
+                        material,
+                        0..instances,
+                        &self.camera_bind_group,
+                        &self.light_bind_group,
+                    );
+                    counters.record_draw(mesh.num_elements, instances);
+                }
+            }
+        }
+        self.profiler.end_pass(scope);
+
+        let scope = self.profiler.begin_pass("transparent");
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Transparent Pass"),
+                color_attachments: &[Some(load_color_attachment(color_view, None))],
+                depth_stencil_attachment: Some(load_depth_attachment(&self.depth_texture.view)),
+                occlusion_query_set: None,
+                timestamp_writes: self.profiler.timestamp_writes(scope),
+            });
 
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_bind_group(0, &self.diffuse_bind_group, &[]); // Ensure this line is present
-            render_pass.set_bind_group(1, &self.camera_bind_group, &[]); // Ensure this line is present
-            render_pass.set_bind_group(2, &self.light_bind_group, &[]); // Ensure this line is present
-            render_pass.draw_indexed(0..self.num_indices, 0, 0..self.instances.len() as u32); 
+            // One instance at a time, back to front.
+            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+            if let Some(pipeline) = self.pipeline_cache.get(self.transparent_pipeline) {
+                render_pass.set_pipeline(pipeline);
+                for draw in &self.render_queue.transparent {
+                    let mesh = &self.obj_model.meshes[draw.mesh];
+                    let Some(material) = self.obj_model.materials.get(mesh.material) else {
+                        continue;
+                    };
+                    render_pass.draw_mesh_instanced(
+                        mesh,
+                        material,
+                        draw.instance..draw.instance + 1,
+                        &self.camera_bind_group,
+                        &self.light_bind_group,
+                    );
+                    counters.record_draw(mesh.num_elements, 1);
+                }
+            }
+        }
+        self.profiler.end_pass(scope);
 
+        let scope = self.profiler.begin_pass("text");
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Text Pass"),
+                color_attachments: &[Some(load_color_attachment(color_view, resolve_target))],
+                depth_stencil_attachment: Some(load_depth_attachment(&self.depth_texture.view)),
+                occlusion_query_set: None,
+                timestamp_writes: self.profiler.timestamp_writes(scope),
+            });
+
+            if show_perf {
+                self.perf_overlay.draw(&mut render_pass, &self.pipeline_cache);
+            }
+            if let Err(e) = self.world_text_renderer.render(&self.atlas, &self.viewport, &mut render_pass) {
+                error!("Failed to render world text: {:?}", e);
+            }
             &self.text_renderer.render(&self.atlas, &self.viewport, &mut render_pass).unwrap();
         }
+        self.profiler.end_pass(scope);
+
+        self.profiler.resolve(&mut encoder);
         self.queue.submit(iter::once(encoder.finish()));
+        self.profiler.end_frame();
         output.present();
+        self.atlas.trim();
+        self.perf_overlay.counters = counters;
         Ok(())
     }
-}
\ No newline at end of file
+
+    fn prepare_text(&mut self) {
+        self.viewport.update(
+            &self.queue,
+            Resolution {
+                width: self.surface_config.width,
+                height: self.surface_config.height,
+            },
+        );
+        self.hud.prepare(&mut self.font_system);
+        let mut text_areas = self.hud.text_areas(self.surface_config.width, self.surface_config.height);
+        if self.console.open {
+            self.console.prepare(&mut self.font_system);
+            text_areas.extend(self.console.text_areas(10.0, 10.0, self.surface_config.width));
+        }
+        if let Err(e) = self.text_renderer.prepare(
+            &self.device,
+            &self.queue,
+            &mut self.font_system,
+            &mut self.atlas,
+            &self.viewport,
+            text_areas,
+            &mut self.swash_cache,
+        ) {
+            error!("Failed to prepare text: {:?}", e);
+        }
+        // The renderer has its own copy now, so the next frame can start queueing.
+        self.hud.end_frame();
+
+        let (width, height) = (self.surface_config.width, self.surface_config.height);
+        self.world_text.prepare(
+            &mut self.font_system,
+            &self.camera_uniform.view_proj,
+            self.camera.position,
+            width,
+            height,
+        );
+        let world_text = &self.world_text;
+        if let Err(e) = self.world_text_renderer.prepare_with_depth(
+            &self.device,
+            &self.queue,
+            &mut self.font_system,
+            &mut self.atlas,
+            &self.viewport,
+            world_text.text_areas(width, height),
+            &mut self.swash_cache,
+            |metadata| world_text.depth(metadata),
+        ) {
+            error!("Failed to prepare world text: {:?}", e);
+        }
+        self.world_text.end_frame();
+    }
+
+    // Prints whatever arrived since last frame. The client is dropped once the
+    // server goes away.
+    fn poll_chat(&mut self) {
+        let Some(client) = &self.chat else {
+            return;
+        };
+        let mut disconnected = false;
+        for event in client.poll() {
+            match event {
+                ChatEvent::Message(message) => {
+                    let [r, g, b] = chat::name_color(&message.name);
+                    let line = format!(
+                        "[{}] {}: {}",
+                        chat::format_time(message.timestamp_ms),
+                        markup::escape(&message.name),
+                        markup::escape(&message.text)
+                    );
+                    self.console.write_colored(&line, Color::rgb(r, g, b));
+                }
+                ChatEvent::Notice { timestamp_ms, text } => {
+                    let line = format!("[{}] * {}", chat::format_time(timestamp_ms), markup::escape(&text));
+                    self.console.write_colored(&line, Color::rgb(170, 170, 170));
+                }
+                ChatEvent::Disconnected => disconnected = true,
+            }
+        }
+        if disconnected {
+            self.chat = None;
+            self.console.write_colored("Disconnected from chat", Color::rgb(255, 200, 60));
+        }
+    }
+
+    // Buffers and textures we created ourselves; the glyph atlas and the surface
+    // swapchain aren't counted.
+    fn gpu_memory_estimate(&self) -> u64 {
+        let buffers = [&self.instance_buffer, &self.index_buffer, &self.camera_buffer, &self.light_buffer]
+            .iter()
+            .map(|buffer| buffer.size())
+            .sum::<u64>();
+        let meshes = self
+            .obj_model
+            .meshes
+            .iter()
+            .map(|mesh| mesh.vertex_buffer.size() + mesh.index_buffer.size())
+            .sum::<u64>();
+        let materials = self
+            .obj_model
+            .materials
+            .iter()
+            .map(|material| material.uniform_buffer.size() + perf::wgpu_texture_bytes(&material.diffuse_texture.texture))
+            .sum::<u64>();
+        let targets = perf::wgpu_texture_bytes(&self.depth_texture.texture)
+            + perf::wgpu_texture_bytes(&self.diffuse_texture.texture)
+            + if self.settings.sample_count > 1 {
+                perf::texture_bytes(
+                    self.surface_config.width,
+                    self.surface_config.height,
+                    self.surface_config.format,
+                    self.settings.sample_count,
+                    1,
+                )
+            } else {
+                0
+            };
+        buffers + meshes + materials + targets
+    }
+
+    // Names the light just above its cube, with its color.
+    fn draw_light_label(&mut self) {
+        let [r, g, b] = self.light_uniform.color.map(|c| (c.clamp(0.0, 1.0) * 255.0) as u8);
+        let [x, y, z] = self.light_uniform.position;
+        let style = WorldLabelStyle {
+            color: Color::rgb(r, g, b),
+            font_size: 20.0,
+            ..Default::default()
+        };
+        self.world_text.draw_label(cgmath::Point3::new(x, y + 0.5, z), "Light", style);
+    }
+
+    // Chunk coordinates floating above each visible chunk's center.
+    fn draw_chunk_labels(&mut self) {
+        let style = WorldLabelStyle {
+            color: Color::rgb(255, 230, 120),
+            fade_start: 40.0,
+            fade_end: 80.0,
+            ..Default::default()
+        };
+        for &index in &self.render_queue.visible_chunks {
+            let (x, z) = self.chunks[index].chunk_pos;
+            let half = CHUNK_SIZE as f32 * 0.5;
+            let position = cgmath::Point3::new(
+                x as f32 * CHUNK_SIZE as f32 + half,
+                CHUNK_HEIGHT as f32 + 1.0,
+                z as f32 * CHUNK_SIZE as f32 + half,
+            );
+            self.world_text.draw_label(position, &format!("{}, {}", x, z), style);
+        }
+    }
+}
+
+// Auto modes fall back to what the surface supports.
+fn present_mode(vsync: bool) -> wgpu::PresentMode {
+    if vsync {
+        wgpu::PresentMode::AutoVsync
+    } else {
+        wgpu::PresentMode::AutoNoVsync
+    }
+}
+
+// Opaque and transparent pipelines for the lit scene shader.
+fn create_scene_pipelines(
+    device: &wgpu::Device,
+    pipeline_cache: &mut PipelineCache,
+    layout: &wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    settings: &RenderSettings,
+    source: &str,
+) -> (PipelineKey, PipelineKey) {
+    let vertex_layouts = [model::ModelVertex::desc(), InstanceRaw::desc()];
+
+    let shader = wgpu::ShaderModuleDescriptor {
+        label: Some("Normal Shader"),
+        source: wgpu::ShaderSource::Wgsl(source.into()),
+    };
+    let builder = PipelineBuilder::new(layout, shader, &vertex_layouts, color_format)
+        .label("Render Pipeline")
+        .depth_format(Some(texture::Texture::DEPTH_FORMAT))
+        .settings(settings);
+    let render_pipeline = pipeline_cache.get_or_create(device, builder);
+
+    // Same shader as the opaque pipeline, but blended and without depth writes
+    // so transparent surfaces don't hide whatever is drawn behind them later.
+    let shader = wgpu::ShaderModuleDescriptor {
+        label: Some("Transparent Shader"),
+        source: wgpu::ShaderSource::Wgsl(source.into()),
+    };
+    let builder = PipelineBuilder::new(layout, shader, &vertex_layouts, color_format)
+        .label("Transparent Pipeline")
+        .depth_format(Some(texture::Texture::DEPTH_FORMAT))
+        .blend(BlendMode::Alpha)
+        .depth_write(false)
+        .settings(settings);
+    let transparent_pipeline = pipeline_cache.get_or_create(device, builder);
+
+    (render_pipeline, transparent_pipeline)
+}
+
+// For passes that continue drawing into what an earlier pass left.
+fn load_color_attachment<'a>(
+    view: &'a wgpu::TextureView,
+    resolve_target: Option<&'a wgpu::TextureView>,
+) -> wgpu::RenderPassColorAttachment<'a> {
+    wgpu::RenderPassColorAttachment {
+        view,
+        resolve_target,
+        ops: wgpu::Operations {
+            load: wgpu::LoadOp::Load,
+            store: wgpu::StoreOp::Store,
+        },
+    }
+}
+
+fn load_depth_attachment(view: &wgpu::TextureView) -> wgpu::RenderPassDepthStencilAttachment<'_> {
+    wgpu::RenderPassDepthStencilAttachment {
+        view,
+        depth_ops: Some(wgpu::Operations {
+            load: wgpu::LoadOp::Load,
+            store: wgpu::StoreOp::Store,
+        }),
+        stencil_ops: None,
+    }
+}
+
+// Depth tested against the scene but never written, so overlapping labels don't
+// clip each other.
+fn create_world_text_renderer(atlas: &mut TextAtlas, device: &wgpu::Device, settings: &RenderSettings) -> TextRenderer {
+    TextRenderer::new(
+        atlas,
+        device,
+        wgpu::MultisampleState {
+            count: settings.sample_count,
+            ..Default::default()
+        },
+        Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: settings.depth_mode.compare(wgpu::CompareFunction::LessEqual),
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+    )
+}
+
+fn create_light_pipeline(
+    device: &wgpu::Device,
+    pipeline_cache: &mut PipelineCache,
+    layout: &wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    settings: &RenderSettings,
+    source: &str,
+) -> PipelineKey {
+    let vertex_layouts = [model::ModelVertex::desc()];
+    let shader = wgpu::ShaderModuleDescriptor {
+        label: Some("Light Shader"),
+        source: wgpu::ShaderSource::Wgsl(source.into()),
+    };
+    let builder = PipelineBuilder::new(layout, shader, &vertex_layouts, color_format)
+        .label("Light Pipeline")
+        .depth_format(Some(texture::Texture::DEPTH_FORMAT))
+        .settings(settings);
+    pipeline_cache.get_or_create(device, builder)
+}
@@ -0,0 +1,323 @@
+use std::collections::HashMap;
+
+use cgmath::{Matrix4, MetricSpace, Point3, Vector4};
+use glyphon::{Attrs, Buffer, Color, Family, FontSystem, Metrics, Shaping, TextArea, TextBounds};
+
+const EVICT_AFTER_FRAMES: u64 = 120;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WorldLabelStyle {
+    pub color: Color,
+    pub font_size: f32,
+    // Full size at `reference_distance`, shrinking further away within the scale limits.
+    pub reference_distance: f32,
+    pub min_scale: f32,
+    pub max_scale: f32,
+    // Fully opaque until fade_start, invisible from fade_end.
+    pub fade_start: f32,
+    pub fade_end: f32,
+}
+
+impl Default for WorldLabelStyle {
+    fn default() -> Self {
+        Self {
+            color: Color::rgb(255, 255, 255),
+            font_size: 24.0,
+            reference_distance: 5.0,
+            min_scale: 0.4,
+            max_scale: 1.5,
+            fade_start: 30.0,
+            fade_end: 50.0,
+        }
+    }
+}
+
+impl WorldLabelStyle {
+    pub fn scale_at(&self, distance: f32) -> f32 {
+        (self.reference_distance / distance.max(1e-3)).clamp(self.min_scale, self.max_scale)
+    }
+
+    pub fn alpha_at(&self, distance: f32) -> f32 {
+        if distance <= self.fade_start {
+            1.0
+        } else if distance >= self.fade_end {
+            0.0
+        } else {
+            1.0 - (distance - self.fade_start) / (self.fade_end - self.fade_start)
+        }
+    }
+}
+
+// A world position in window pixels, plus its depth buffer value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScreenPoint {
+    pub x: f32,
+    pub y: f32,
+    pub depth: f32,
+}
+
+// None when the point is behind the camera or outside the depth range.
+pub fn project(view_proj: &[[f32; 4]; 4], position: Point3<f32>, width: u32, height: u32) -> Option<ScreenPoint> {
+    let clip = Matrix4::from(*view_proj) * Vector4::new(position.x, position.y, position.z, 1.0);
+    if clip.w <= 1e-6 {
+        return None;
+    }
+    let ndc = clip.truncate() / clip.w;
+    if !(0.0..=1.0).contains(&ndc.z) {
+        return None;
+    }
+    Some(ScreenPoint {
+        x: (ndc.x * 0.5 + 0.5) * width as f32,
+        y: (0.5 - ndc.y * 0.5) * height as f32,
+        depth: ndc.z,
+    })
+}
+
+// Shaping depends on the text and size; the occurrence index keeps labels with
+// the same text apart, since each buffer's glyphs carry one depth.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct LabelKey {
+    text: String,
+    font_size_bits: u32,
+    occurrence: usize,
+}
+
+struct CachedLabel {
+    // Stored as the glyphs' metadata so prepare_with_depth can find the label's depth.
+    id: usize,
+    buffer: Buffer,
+    size: [f32; 2],
+    last_used: u64,
+}
+
+struct QueuedLabel {
+    key: LabelKey,
+    position: Point3<f32>,
+    style: WorldLabelStyle,
+}
+
+struct PlacedLabel {
+    key: LabelKey,
+    left: f32,
+    top: f32,
+    scale: f32,
+    color: Color,
+}
+
+// Text anchored to world positions, drawn with a depth test against the scene.
+// Like the HUD it's immediate mode: queue labels each frame with draw_label.
+#[derive(Default)]
+pub struct WorldText {
+    queued: Vec<QueuedLabel>,
+    placed: Vec<PlacedLabel>,
+    pool: HashMap<LabelKey, CachedLabel>,
+    next_id: usize,
+    // CachedLabel::id to this frame's depth, for prepare_with_depth.
+    depths: HashMap<usize, f32>,
+    frame: u64,
+}
+
+impl WorldText {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The label's bottom center sits on `position`.
+    pub fn draw_label(&mut self, position: Point3<f32>, text: &str, style: WorldLabelStyle) {
+        if text.is_empty() {
+            return;
+        }
+        let font_size_bits = style.font_size.to_bits();
+        let occurrence = self
+            .queued
+            .iter()
+            .filter(|q| q.key.text == text && q.key.font_size_bits == font_size_bits)
+            .count();
+        self.queued.push(QueuedLabel {
+            key: LabelKey { text: text.to_string(), font_size_bits, occurrence },
+            position,
+            style,
+        });
+    }
+
+    // Projects, culls and shapes this frame's labels. Depth comes straight from
+    // the view-projection, so it matches the scene under either depth mode.
+    pub fn prepare(
+        &mut self,
+        font_system: &mut FontSystem,
+        view_proj: &[[f32; 4]; 4],
+        eye: Point3<f32>,
+        width: u32,
+        height: u32,
+    ) {
+        self.placed.clear();
+        self.depths.clear();
+        for queued in self.queued.drain(..) {
+            let distance = queued.position.distance(eye);
+            let alpha = queued.style.alpha_at(distance);
+            if alpha <= 0.0 {
+                continue;
+            }
+            let Some(point) = project(view_proj, queued.position, width, height) else {
+                continue;
+            };
+            let scale = queued.style.scale_at(distance);
+            let next_id = &mut self.next_id;
+            let cached = self.pool.entry(queued.key.clone()).or_insert_with(|| {
+                *next_id += 1;
+                shape(font_system, &queued.key, *next_id)
+            });
+            cached.last_used = self.frame;
+
+            let [w, h] = [cached.size[0] * scale, cached.size[1] * scale];
+            let (left, top) = (point.x - w * 0.5, point.y - h);
+            // Off-screen entirely
+            if left > width as f32 || top > height as f32 || left + w < 0.0 || top + h < 0.0 {
+                continue;
+            }
+            let color = queued.style.color;
+            self.placed.push(PlacedLabel {
+                key: queued.key,
+                left,
+                top,
+                scale,
+                color: Color::rgba(color.r(), color.g(), color.b(), (color.a() as f32 * alpha) as u8),
+            });
+            self.depths.insert(cached.id, point.depth);
+        }
+    }
+
+    pub fn text_areas(&self, width: u32, height: u32) -> Vec<TextArea<'_>> {
+        self.placed
+            .iter()
+            .filter_map(|placed| {
+                let cached = self.pool.get(&placed.key)?;
+                Some(TextArea {
+                    buffer: &cached.buffer,
+                    left: placed.left,
+                    top: placed.top,
+                    scale: placed.scale,
+                    bounds: TextBounds {
+                        left: 0,
+                        top: 0,
+                        right: width as i32,
+                        bottom: height as i32,
+                    },
+                    default_color: placed.color,
+                    custom_glyphs: &[],
+                })
+            })
+            .collect()
+    }
+
+    pub fn depth(&self, metadata: usize) -> f32 {
+        self.depths.get(&metadata).copied().unwrap_or(0.0)
+    }
+
+    pub fn end_frame(&mut self) {
+        let frame = self.frame;
+        self.pool.retain(|_, cached| frame - cached.last_used < EVICT_AFTER_FRAMES);
+        self.frame += 1;
+    }
+}
+
+fn shape(font_system: &mut FontSystem, key: &LabelKey, id: usize) -> CachedLabel {
+    let font_size = f32::from_bits(key.font_size_bits);
+    let mut buffer = Buffer::new(font_system, Metrics::new(font_size, font_size * 1.3));
+    buffer.set_size(font_system, None, None);
+    buffer.set_text(
+        font_system,
+        &key.text,
+        Attrs::new().family(Family::SansSerif).metadata(id),
+        Shaping::Advanced,
+    );
+    buffer.shape_until_scroll(font_system, false);
+    let (width, height) = buffer
+        .layout_runs()
+        .fold((0.0f32, 0.0f32), |(width, _), run| (width.max(run.line_w), run.line_top + run.line_height));
+    CachedLabel {
+        id,
+        buffer,
+        size: [width, height],
+        last_used: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Deg;
+
+    use super::*;
+    use crate::camera::{Camera, CameraUniform, Projection};
+
+    const WIDTH: u32 = 800;
+    const HEIGHT: u32 = 600;
+
+    // At the origin looking down -z.
+    fn view_proj(reverse_z: bool) -> [[f32; 4]; 4] {
+        let camera = Camera::new((0.0, 0.0, 0.0), Deg(-90.0), Deg(0.0));
+        let mut projection = Projection::new(WIDTH, HEIGHT, Deg(60.0), 0.1, 100.0);
+        projection.set_reverse_z(reverse_z);
+        let mut uniform = CameraUniform::new();
+        uniform.update_view_proj(&camera, &projection);
+        uniform.view_proj
+    }
+
+    fn assert_near(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-3, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn point_ahead_projects_to_screen_center() {
+        for reverse_z in [false, true] {
+            let point = project(&view_proj(reverse_z), Point3::new(0.0, 0.0, -10.0), WIDTH, HEIGHT).unwrap();
+            assert_near(point.x, WIDTH as f32 * 0.5);
+            assert_near(point.y, HEIGHT as f32 * 0.5);
+            assert!((0.0..=1.0).contains(&point.depth));
+        }
+    }
+
+    #[test]
+    fn screen_y_points_down() {
+        let view_proj = view_proj(false);
+        let above = project(&view_proj, Point3::new(0.0, 1.0, -10.0), WIDTH, HEIGHT).unwrap();
+        let right = project(&view_proj, Point3::new(1.0, 0.0, -10.0), WIDTH, HEIGHT).unwrap();
+        assert!(above.y < HEIGHT as f32 * 0.5);
+        assert!(right.x > WIDTH as f32 * 0.5);
+    }
+
+    #[test]
+    fn points_behind_the_camera_or_past_far_are_rejected() {
+        for reverse_z in [false, true] {
+            let view_proj = view_proj(reverse_z);
+            assert_eq!(project(&view_proj, Point3::new(0.0, 0.0, 10.0), WIDTH, HEIGHT), None);
+            assert_eq!(project(&view_proj, Point3::new(0.0, 0.0, 0.0), WIDTH, HEIGHT), None);
+        }
+        // Only standard depth has a far plane.
+        assert_eq!(project(&view_proj(false), Point3::new(0.0, 0.0, -200.0), WIDTH, HEIGHT), None);
+        assert!(project(&view_proj(true), Point3::new(0.0, 0.0, -200.0), WIDTH, HEIGHT).is_some());
+    }
+
+    #[test]
+    fn labels_shrink_with_distance_within_limits() {
+        let style = WorldLabelStyle::default();
+        assert_eq!(style.scale_at(style.reference_distance), 1.0);
+        assert_eq!(style.scale_at(style.reference_distance * 2.0), 0.5);
+        assert_eq!(style.scale_at(1000.0), style.min_scale);
+        assert_eq!(style.scale_at(0.0), style.max_scale);
+    }
+
+    #[test]
+    fn labels_fade_out_between_fade_start_and_end() {
+        let style = WorldLabelStyle {
+            fade_start: 10.0,
+            fade_end: 20.0,
+            ..Default::default()
+        };
+        assert_eq!(style.alpha_at(0.0), 1.0);
+        assert_eq!(style.alpha_at(10.0), 1.0);
+        assert_eq!(style.alpha_at(15.0), 0.5);
+        assert_eq!(style.alpha_at(20.0), 0.0);
+        assert_eq!(style.alpha_at(100.0), 0.0);
+    }
+}
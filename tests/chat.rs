@@ -0,0 +1,83 @@
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use wgpu_sandbox::chat::{self, ChatClient, ChatEvent, ChatMessage, LocalChat};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+fn next_message(client: &ChatClient) -> ChatMessage {
+    match client.wait_for(TIMEOUT, |event| matches!(event, ChatEvent::Message(_))) {
+        Some(ChatEvent::Message(message)) => message,
+        other => panic!("expected a message, got {:?}", other),
+    }
+}
+
+fn wait_for_notice(client: &ChatClient, text: &str) {
+    let notice = client.wait_for(TIMEOUT, |event| matches!(event, ChatEvent::Notice { text: t, .. } if t == text));
+    assert!(notice.is_some(), "no '{}' notice", text);
+}
+
+#[test]
+fn clients_receive_each_others_messages() {
+    let local = LocalChat::start().unwrap();
+    let mut alice = local.client("alice", TIMEOUT).unwrap();
+    let mut bob = local.client("bob", TIMEOUT).unwrap();
+
+    let before = chat::now_ms();
+    alice.say("hi bob").unwrap();
+    let message = next_message(&bob);
+    assert_eq!(message.name, "alice");
+    assert_eq!(message.text, "hi bob");
+    assert!((before..=chat::now_ms()).contains(&message.timestamp_ms));
+    // The sender gets its own message back too.
+    assert_eq!(next_message(&alice), message);
+
+    let before = chat::now_ms();
+    bob.say("hi alice").unwrap();
+    let message = next_message(&alice);
+    assert_eq!(message.name, "bob");
+    assert_eq!(message.text, "hi alice");
+    assert!((before..=chat::now_ms()).contains(&message.timestamp_ms));
+}
+
+#[test]
+fn join_and_leave_notices() {
+    let local = LocalChat::start().unwrap();
+    let alice = local.client("alice", TIMEOUT).unwrap();
+    let bob = local.client("bob", TIMEOUT).unwrap();
+    wait_for_notice(&alice, "bob joined");
+    assert_eq!(local.server.client_count(), 2);
+
+    drop(bob);
+    wait_for_notice(&alice, "bob left");
+    assert_eq!(local.server.client_count(), 1);
+}
+
+#[test]
+fn overlong_packet_drops_only_that_client() {
+    let local = LocalChat::start().unwrap();
+    let mut alice = local.client("alice", TIMEOUT).unwrap();
+
+    let mut raw = TcpStream::connect(local.addr()).unwrap();
+    raw.write_all(b"{\"type\":\"hello\",\"name\":\"mallory\"}\n").unwrap();
+    wait_for_notice(&alice, "mallory joined");
+
+    // Well past the packet limit, with no newline to end it.
+    raw.write_all(&[b'a'; 5000]).unwrap();
+    wait_for_notice(&alice, "mallory left");
+
+    // The server has closed mallory's connection.
+    raw.set_read_timeout(Some(TIMEOUT)).unwrap();
+    let mut received = Vec::new();
+    if let Err(e) = raw.read_to_end(&mut received) {
+        assert!(
+            !matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut),
+            "connection still open"
+        );
+    }
+
+    alice.say("still here").unwrap();
+    assert_eq!(next_message(&alice).text, "still here");
+    assert_eq!(local.server.client_count(), 1);
+}